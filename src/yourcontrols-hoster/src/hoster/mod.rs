@@ -6,12 +6,15 @@ use laminar::{Metrics, Socket};
 use state::ActiveState;
 use std::{
     collections::HashMap,
-    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    net::SocketAddr,
     thread::sleep,
     time::{Duration, Instant},
 };
 
-use yourcontrols_net::{get_socket_config, Message, Payloads, SenderReceiver};
+use yourcontrols_net::{
+    get_socket_config, get_socket_duplex, Message, Payloads, SenderReceiver, StreamKind,
+    StreamTransport,
+};
 
 const SERVER_TIMEOUT: u64 = 3;
 
@@ -24,15 +27,25 @@ pub struct Hoster {
 }
 
 impl Hoster {
-    pub fn new(port: u16, communicator_address: SocketAddr) -> Self {
-        let socket = Socket::bind_with_config(
-            SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port),
-            get_socket_config(SERVER_TIMEOUT),
-        )
-        .expect("Failed to bind!");
+    pub fn new(
+        port: u16,
+        communicator_address: SocketAddr,
+        stream_binds: Vec<(StreamKind, SocketAddr)>,
+    ) -> Self {
+        let socket =
+            Socket::from_udp_socket(get_socket_duplex(port), get_socket_config(SERVER_TIMEOUT))
+                .expect("Failed to bind!");
+
+        let mut net = SenderReceiver::from_socket(socket);
+
+        if !stream_binds.is_empty() {
+            net.add_stream(
+                StreamTransport::listen(stream_binds, SERVER_TIMEOUT).expect("Failed to bind!"),
+            );
+        }
 
         Self {
-            net: SenderReceiver::from_socket(socket),
+            net,
             communicator: Communicator::new(communicator_address),
             metrics_data: HashMap::new(),
             servers: ActiveState::new(),
@@ -43,13 +56,50 @@ impl Hoster {
     fn handle_heartbeats(&mut self) {
         for (_, state) in self.servers.get_server_states().iter_mut() {
             if state.heartbeat_instant.elapsed().as_secs_f32() < 0.5 {
-                return;
+                continue;
             }
 
-            state.send_to_all(Payloads::Heartbeat, None, &mut self.net);
+            state.send_heartbeats(&mut self.net);
+        }
+    }
 
-            state.heartbeat_instant = Instant::now();
+    // Clients whose UDP is blocked come straight here over TCP or WebSocket, and are let into the session they name
+    fn process_stream_rendezvous(&mut self, addr: SocketAddr, payload: &Payloads) {
+        let session_id = match payload {
+            Payloads::RendezvousHandshake { session_id, .. } => session_id,
+            Payloads::RequestHosting { .. } => {
+                // Sessions are only opened through the rendezvous server
+                self.net
+                    .send_message(
+                        Payloads::ConnectionDenied {
+                            reason: "Hosting needs UDP.".to_string(),
+                        },
+                        addr,
+                    )
+                    .ok();
+                return;
+            }
+            _ => return,
+        };
+
+        if self.servers.get_server_state(session_id).is_none() {
+            self.net
+                .send_message(
+                    Payloads::ConnectionDenied {
+                        reason: "Session not found.".to_string(),
+                    },
+                    addr,
+                )
+                .ok();
+            return;
         }
+
+        self.servers.add_client(addr, session_id.clone());
+
+        // Any address does, the client sends everything down the same connection
+        self.net
+            .send_message(Payloads::AttemptConnection { peers: vec![addr] }, addr)
+            .ok();
     }
 
     fn process_net(&mut self) {
@@ -58,6 +108,13 @@ impl Hoster {
         while let Ok(message) = self.net.get_next_message() {
             match message {
                 Message::Payload(addr, payload) => {
+                    if self.net.is_stream_peer(&addr)
+                        && self.servers.get_session_id_for(&addr).is_none()
+                    {
+                        self.process_stream_rendezvous(addr, &payload);
+                        continue;
+                    }
+
                     // Get server state for session
                     if let Some(state) = self.servers.get_server_state_for(&addr) {
                         state.process_payload(addr, payload, &mut self.net);
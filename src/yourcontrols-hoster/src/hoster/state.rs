@@ -3,13 +3,15 @@ use std::{
     net::SocketAddr,
     time::Instant,
 };
-use yourcontrols_net::{Payloads, SenderReceiver};
+use yourcontrols_net::{get_seconds, Payloads, PeerClock, SenderReceiver};
 
 pub const SERVER_NAME: &str = "SERVER";
 
 pub struct ClientConnection {
     pub addr: SocketAddr,
     pub is_observer: bool,
+    // Offset of the client's clock, echoed so the client can send its update times in ours
+    pub clock: PeerClock,
 }
 
 pub struct ServerState {
@@ -39,9 +41,22 @@ impl ServerState {
             .find(|client| client.addr == addr)
     }
 
-    pub fn add_client(&mut self, name: String, addr: SocketAddr, is_observer: bool) {
+    pub fn get_name_for(&self, addr: &SocketAddr) -> Option<String> {
         self.clients
-            .insert(name, ClientConnection { addr, is_observer });
+            .iter()
+            .find(|(_, client)| client.addr == *addr)
+            .map(|(name, _)| name.clone())
+    }
+
+    pub fn add_client(&mut self, name: String, addr: SocketAddr, is_observer: bool) {
+        self.clients.insert(
+            name,
+            ClientConnection {
+                addr,
+                is_observer,
+                clock: PeerClock::new(),
+            },
+        );
     }
 
     pub fn remove_client(&mut self, name: &str) {
@@ -84,6 +99,22 @@ impl ServerState {
         net.send_message_to_multiple(payload, to_send).ok();
     }
 
+    // Each client gets their own heartbeat echoed back
+    pub fn send_heartbeats(&mut self, net: &mut SenderReceiver) {
+        for client in self.clients.values() {
+            net.send_message(
+                Payloads::Heartbeat {
+                    time: get_seconds(),
+                    echo: client.clock.get_echo(),
+                },
+                client.addr,
+            )
+            .ok();
+        }
+
+        self.heartbeat_instant = Instant::now();
+    }
+
     pub fn set_host(&mut self, name: String, net: &mut SenderReceiver) {
         let client = self.clients.get_mut(&name).expect("always there");
         client.is_observer = false;
@@ -108,7 +139,24 @@ impl ServerState {
         payload: Payloads,
         net: &mut SenderReceiver,
     ) {
-        match &payload {
+        let sender = self.clients.values_mut().find(|client| client.addr == addr);
+
+        // Clients send update times in our time base, sealed ones couldn't be converted here
+        if let (Payloads::Heartbeat { time, echo }, Some(sender)) = (&payload, sender) {
+            sender.clock.on_heartbeat(*time, *echo, get_seconds());
+            return;
+        }
+
+        // Sealed payloads are forwarded as they came, their readable copy only drives the bookkeeping
+        let visible = match &payload {
+            Payloads::Encrypted {
+                visible: Some(visible),
+                ..
+            } => visible.as_ref(),
+            _ => &payload,
+        };
+
+        match visible {
             // Unused
             Payloads::InvalidName { .. }
             | Payloads::RendezvousHandshake { .. }
@@ -121,11 +169,69 @@ impl ServerState {
             | Payloads::RequestHosting { .. }
             | Payloads::PeerEstablished { .. }
             | Payloads::ConnectionDenied { .. }
-            | Payloads::Heartbeat
+            | Payloads::Heartbeat { .. }
             | Payloads::SetSelfObserver { .. }
-            | Payloads::PlayerLeft { .. } => return,
+            | Payloads::PlayerLeft { .. }
+            // Relays don't hold slots, nobody gets a token to resume with
+            | Payloads::ResumeToken { .. }
+            | Payloads::Resume { .. }
+            | Payloads::Resumed
+            // Only the rendezvous server answers probes
+            | Payloads::NatProbe { .. }
+            | Payloads::NatProbeResponse { .. }
+            // Only the rendezvous server moves sessions
+            | Payloads::RequestHosterFallback { .. } => return,
             // Used
-            Payloads::AircraftDefinition { .. } | Payloads::Update { .. } => {}
+            Payloads::DefinitionManifest { .. } | Payloads::DefinitionChunk { .. } => {
+                // Only what the host flies is handed out
+                if self.get_name_for(&addr).as_deref() != Some(self.hoster.as_str()) {
+                    return;
+                }
+            }
+            Payloads::RequestDefinitionChunks { .. } => {
+                if let Some(client) = self.clients.get(&self.hoster) {
+                    net.send_message(payload, client.addr).ok();
+                }
+
+                return;
+            }
+            Payloads::AircraftDefinition { .. }
+            | Payloads::Update { .. }
+            | Payloads::RequestPause { .. }
+            | Payloads::SetPause { .. }
+            | Payloads::SetWeather { .. }
+            | Payloads::Encrypted { .. } => {}
+            Payloads::KeyExchange { peer, data } => {
+                // Only members get a key, and only under the name they joined with
+                let Some(sender) = self.get_name_for(&addr) else {
+                    return;
+                };
+
+                // The host hands out the session key, everyone else asks it for one
+                let (target, peer) = if sender == self.hoster {
+                    (peer.clone(), peer.clone())
+                } else {
+                    (self.hoster.clone(), sender.clone())
+                };
+
+                // Asked before it was made host, it already has the key
+                if target == sender {
+                    return;
+                }
+
+                if let Some(client) = self.clients.get(&target) {
+                    net.send_message(
+                        Payloads::KeyExchange {
+                            peer,
+                            data: data.clone(),
+                        },
+                        client.addr,
+                    )
+                    .ok();
+                }
+
+                return;
+            }
             Payloads::InitHandshake { name, version } => {
                 let server_version = dotenv::var("APP_VERSION").unwrap();
 
@@ -2,7 +2,21 @@ mod hoster;
 
 use dotenv::var;
 use hoster::Hoster;
-use yourcontrols_net::get_rendezvous_server;
+use std::net::{Ipv4Addr, SocketAddr};
+use yourcontrols_net::{get_rendezvous_server, StreamKind};
+
+fn get_stream_binds() -> Vec<(StreamKind, SocketAddr)> {
+    [
+        (StreamKind::Tcp, "HOSTER_TCP_PORT"),
+        (StreamKind::WebSocket, "HOSTER_WS_PORT"),
+    ]
+    .iter()
+    .filter_map(|(kind, var_name)| {
+        let port: u16 = var(var_name).ok()?.parse().ok()?;
+        Some((*kind, SocketAddr::from((Ipv4Addr::UNSPECIFIED, port))))
+    })
+    .collect()
+}
 
 fn main() {
     let mut hoster = Hoster::new(
@@ -11,6 +25,7 @@ fn main() {
             .parse()
             .unwrap(),
         get_rendezvous_server(false).unwrap(),
+        get_stream_binds(),
     );
     hoster.run();
 }
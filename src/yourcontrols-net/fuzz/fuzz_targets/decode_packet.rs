@@ -0,0 +1,10 @@
+#![no_main]
+// Raw packets, as anyone can send them to the app, yourcontrols-server or yourcontrols-hoster
+use libfuzzer_sys::fuzz_target;
+use yourcontrols_net::PayloadDecoder;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(mut decoder) = PayloadDecoder::new() {
+        decoder.decode(data).ok();
+    }
+});
@@ -0,0 +1,25 @@
+#![no_main]
+// Payloads behind a valid wrapper, so the fuzzer spends its time past decompression
+use libfuzzer_sys::fuzz_target;
+use yourcontrols_net::{PayloadDecoder, PayloadWrapper};
+
+fuzz_target!(|data: &[u8]| {
+    let compressed = match zstd::bulk::compress(data, 0) {
+        Ok(compressed) => compressed,
+        Err(_) => return,
+    };
+
+    let wrapper = PayloadWrapper {
+        data: compressed,
+        size: data.len(),
+    };
+
+    let bytes = match rmp_serde::to_vec(&wrapper) {
+        Ok(bytes) => bytes,
+        Err(_) => return,
+    };
+
+    if let Ok(mut decoder) = PayloadDecoder::new() {
+        decoder.decode(&bytes).ok();
+    }
+});
@@ -3,6 +3,7 @@ use laminar::Socket;
 use log::info;
 use spin_sleep::sleep;
 use std::{
+    collections::VecDeque,
     mem::drop,
     net::{IpAddr, SocketAddr},
     sync::{
@@ -13,16 +14,26 @@ use std::{
     time::{Duration, Instant},
 };
 use crate::{
+    clock::PeerClock,
+    crypto::{answer_key_request, generate_secret, KeyRequest, SessionKey},
     messages::{Message, Payloads, SenderReceiver},
+    punch::{get_random_port, PunchSession},
+    resume::Reconnect,
+    stream::{StreamKind, StreamTransport},
     util::{
-        ClientReceiver, ClientSender, Event, ReceiveMessage, ServerReceiver, ServerSender, TransferClient,
-        get_bind_address, get_local_endpoints_with_port, get_rendezvous_server, get_socket_config, match_ip_address_to_socket_addr,
-        HEARTBEAT_INTERVAL_MANUAL_SECS, LOOP_SLEEP_TIME_MS, MAX_PUNCH_RETRIES,
+        get_join_code, get_stream_fallback_servers, ClientReceiver, ClientSender, Event, ReceiveMessage, ServerReceiver, ServerSender, TransferClient,
+        bind_dual_stack, get_local_endpoints_with_port, get_rendezvous_server, get_seconds, get_socket_config, match_ip_address_to_socket_addr,
+        HEARTBEAT_INTERVAL_MANUAL_SECS, LOOP_SLEEP_TIME_MS,
     },
 };
 
 use yourcontrols_types::Error;
 
+// How long to wait on the rendezvous server before trying something else
+const WAIT_TIMEOUT_SECS: u64 = 5;
+// The host answers right away, a session without a key isn't joined
+const KEY_EXCHANGE_TIMEOUT_SECS: u64 = 10;
+
 struct TransferStruct {
     name: String,
     version: String,
@@ -33,14 +44,35 @@ struct TransferStruct {
     // Reading/writing to UDP stream
     net: SenderReceiver,
     // Hole punching
-    received_address: Vec<SocketAddr>,
+    punch: Option<PunchSession>,
     connected_address: Option<SocketAddr>,
-    retry_timer: Option<Instant>,
     session_id: String,
-    retries: u8,
+    // Proves to the host that we were given the join code, the servers never see it
+    secret: String,
+    // Asked the rendezvous server to move the session to a hoster once punching ran out
+    fallback_requested: bool,
+    // Since we last heard how to get any further, from the rendezvous server or otherwise
+    waiting_since: Instant,
+    // Sent to the rendezvous server over UDP, and again over TCP or WebSocket if that didn't get through
+    rendezvous_request: Option<Payloads>,
+    stream_fallbacks: VecDeque<(StreamKind, SocketAddr)>,
+    timeout: u64,
     // State
     should_stop: Arc<AtomicBool>,
     heartbeat_instant: Instant,
+    // Offset of the server's clock, update times are converted from ours to its and back
+    clock: PeerClock,
+    // Waiting on the session key from the host, since when
+    key_request: Option<(KeyRequest, Instant)>,
+    // Hands out the session key to everyone joining through the relay
+    is_key_holder: bool,
+    // Whether the handshake comes from a hoster relaying the session rather than the host's own server
+    through_relay: bool,
+    // Where we joined from, to go through the same steps again after a drop
+    rendezvous: Option<SocketAddr>,
+    target_address: Option<SocketAddr>,
+    resume_token: Option<String>,
+    reconnect: Option<Reconnect>,
 }
 
 impl TransferStruct {
@@ -49,26 +81,65 @@ impl TransferStruct {
     }
 
     // Should stop client
-    fn handle_message(&mut self, addr: SocketAddr, payload: Payloads) {
+    fn handle_message(&mut self, addr: SocketAddr, mut payload: Payloads) {
+        let from_server = self.connected_address == Some(addr);
+
+        match &mut payload {
+            Payloads::Heartbeat { time, echo } if from_server => {
+                self.clock.on_heartbeat(*time, *echo, get_seconds());
+            }
+            Payloads::Update { time, .. } => {
+                *time = self.clock.to_local_time(*time, get_seconds());
+            }
+            _ => {}
+        }
+
         match &payload {
+            // Only what the host or relay sealed says which definitions to trust
+            Payloads::DefinitionManifest { .. } if !from_server || !self.net.is_sealed(&addr) => return,
             // Unused by client
             Payloads::InitHandshake { .. } |
             Payloads::RendezvousHandshake  { .. } |
             Payloads::PeerEstablished { .. } |
             Payloads::RequestHosting {..} |
             Payloads::Ready |
+            Payloads::Resume { .. } |
             Payloads::SetSelfObserver { .. }|
             // No futher handling required
             Payloads::AircraftDefinition { .. } |
+            Payloads::DefinitionManifest { .. } |
+            Payloads::RequestDefinitionChunks { .. } |
+            Payloads::DefinitionChunk { .. } |
+            Payloads::RequestHosterFallback { .. } |
             Payloads::TransferControl { ..} |
+            Payloads::RequestPause { .. } |
+            Payloads::SetPause { .. } |
+            Payloads::SetWeather { .. } |
             Payloads::SetObserver { .. } |
             Payloads::PlayerJoined { .. } |
             Payloads::PlayerLeft { .. } |
             Payloads::Update { .. } |
             Payloads::ConnectionDenied { .. } |
-            Payloads::SetHost |
             Payloads::AttemptHosterConnection {..} |
-            Payloads::Heartbeat => {}
+            Payloads::NatProbe { .. } |
+            Payloads::NatProbeResponse { .. } |
+            Payloads::Heartbeat { .. } => {}
+            // Couldn't be opened without the key yet
+            Payloads::Encrypted { .. } => return,
+            Payloads::KeyExchange { peer, data } => {
+                self.handle_key_exchange(addr, peer, data);
+                return;
+            }
+            Payloads::ResumeToken { token } => {
+                self.resume_token = Some(token.clone());
+                return;
+            }
+            Payloads::Resumed => {
+                info!("[NETWORK] Resumed session");
+                self.reconnect = None;
+                self.server_tx.try_send(ReceiveMessage::Event(Event::Resumed)).ok();
+                return;
+            }
             // Used
             Payloads::InvalidVersion { server_version } => {
                 self.stop(format!("Server has mismatching version {}", server_version));
@@ -86,23 +157,50 @@ impl TransferStruct {
                 }
                 // Established connection with host
                 self.connected_address = Some(addr);
-                self.received_address.drain(..);
+                self.punch = None;
+
+                if self.through_relay {
+                    self.net.set_relay(addr);
+                }
+
+                // Take our old slot back, everything else is still set up
+                if let (Some(token), true) = (self.resume_token.clone(), self.is_reconnecting()) {
+                    self.net.send_message(Payloads::Resume {
+                        name: self.name.clone(),
+                        token,
+                    }, addr).ok();
+                    // Only clients the host has taken in get the key, this arrives right after on the same channel
+                    self.request_session_key(addr);
+
+                    info!("[NETWORK] Reestablished connection with port {}, resuming", addr.port());
+                    return;
+                }
 
                 // Send initial data
                 self.net.send_message(Payloads::InitHandshake {
                     name: self.name.clone(),
                     version: self.version.clone(),
                 }, addr).ok();
+                self.request_session_key(addr);
 
                 info!("[NETWORK] Established connection with port {} on {}!", addr.port(), session_id);
 
                 self.server_tx.try_send(ReceiveMessage::Event(Event::ConnectionEstablished)).ok();
             }
+            Payloads::SetHost => {
+                self.become_key_holder(addr);
+            }
             Payloads::HostingReceived { session_id } => {
                 self.session_id.clone_from(session_id);
             }
             Payloads::AttemptConnection { peers } => {
-                self.received_address.clone_from(peers) ;
+                // A hoster we reached over TCP or WebSocket is only ever at the address we connected to
+                self.punch = Some(if self.net.is_stream_peer(&addr) {
+                    self.through_relay = true;
+                    PunchSession::exact(peers.clone())
+                } else {
+                    PunchSession::new(peers.clone())
+                });
             }
         }
 
@@ -111,56 +209,325 @@ impl TransferStruct {
             .ok();
     }
 
+    fn request_session_key(&mut self, addr: SocketAddr) {
+        match KeyRequest::new(&self.secret) {
+            Ok((request, data)) => {
+                self.key_request = Some((request, Instant::now()));
+                self.net
+                    .send_message(
+                        Payloads::KeyExchange {
+                            peer: self.name.clone(),
+                            data,
+                        },
+                        addr,
+                    )
+                    .ok();
+            }
+            Err(e) => self.stop(format!("Could not start key exchange: {}", e)),
+        }
+    }
+
+    // The relay made us host, so joiners get the key from us from now on. Nothing for the relay to read goes out
+    fn become_key_holder(&mut self, relay: SocketAddr) {
+        self.key_request = None;
+        self.is_key_holder = true;
+
+        if self.net.get_session_key().is_none() {
+            match SessionKey::generate() {
+                Ok(key) => self.net.set_session_key(key),
+                Err(e) => {
+                    self.stop(format!("Could not generate session key: {}", e));
+                    return;
+                }
+            }
+        }
+
+        self.net.set_sealed(relay, true);
+        self.net.set_relay(relay);
+    }
+
+    fn handle_key_exchange(&mut self, addr: SocketAddr, peer: &str, data: &[u8]) {
+        if self.is_key_holder {
+            let response = match self.net.get_session_key() {
+                Some(key) => answer_key_request(&self.secret, data, key),
+                None => return,
+            };
+
+            match response {
+                Ok(data) => {
+                    self.net
+                        .send_message(
+                            Payloads::KeyExchange {
+                                peer: peer.to_string(),
+                                data,
+                            },
+                            addr,
+                        )
+                        .ok();
+                    self.server_tx
+                        .try_send(ReceiveMessage::Event(Event::KeyShared(peer.to_string())))
+                        .ok();
+                }
+                Err(e) => info!("[NETWORK] Refused key exchange from {}: {}", peer, e),
+            }
+            return;
+        }
+
+        // Every host that lets us in has a key, so there's no carrying on in the clear
+        if let Some((request, _)) = self.key_request.take() {
+            match request.read_response(data) {
+                Ok(key) => {
+                    self.net.set_session_key(key);
+                    self.net.set_sealed(addr, true);
+                    info!("[NETWORK] Session encrypted");
+                }
+                Err(e) => self.stop(format!(
+                    "Key exchange failed, check the session code: {}",
+                    e
+                )),
+            }
+        }
+    }
+
+    fn handle_key_timeout(&mut self) {
+        let timed_out = self.key_request.as_ref().is_some_and(|(_, requested_at)| {
+            requested_at.elapsed().as_secs() >= KEY_EXCHANGE_TIMEOUT_SECS
+        });
+
+        if timed_out {
+            self.key_request = None;
+            self.stop("Host never sent the session key, check the session code".to_string());
+        }
+    }
+
     fn handle_app_message(&mut self) {
-        while let Ok((payload, _)) = self.client_rx.try_recv() {
+        // Everything from the app gets sealed, it waits for the key
+        if let Some(address) = self.connected_address {
+            if !self.net.is_sealed(&address) {
+                return;
+            }
+        }
+
+        while let Ok((mut payload, _)) = self.client_rx.try_recv() {
+            // Relays can't open a sealed update to convert its time, so it goes out in the server's time base
+            if let Payloads::Update { time, .. } = &mut payload {
+                *time = self.clock.to_peer_time(*time, get_seconds());
+            }
+
             if let Some(address) = self.connected_address {
                 self.net.send_message(payload, address).ok();
             }
         }
     }
 
-    // Returns whether to stop client (can't establish connection)
     fn handle_handshake(&mut self) {
         if self.connected() {
             return;
         }
 
-        // Send a message every second
-        if let Some(timer) = self.retry_timer.as_ref() {
-            if timer.elapsed().as_secs() < 1 {
-                return;
-            }
+        let now = Instant::now();
+
+        let Some(punch) = self.punch.as_mut() else {
+            return;
+        };
+
+        let targets = punch.next_round(now, get_random_port);
+
+        if !targets.is_empty() {
+            info!(
+                "[NETWORK] Sent handshake packets to {} endpoints. Round #{}",
+                targets.len(),
+                punch.get_round()
+            );
         }
 
-        for addr in &self.received_address {
+        let is_spent = punch.is_spent(now);
+
+        for addr in targets {
             self.net
                 .send_message(
                     Payloads::Handshake {
                         session_id: self.session_id.clone(),
                     },
-                    *addr,
+                    addr,
                 )
                 .ok();
-            // Reset second timer
-            self.retry_timer = Some(Instant::now());
-            self.retries += 1;
+        }
 
-            // Over retry limit, stop connection
-            if self.retries == MAX_PUNCH_RETRIES {
+        if !is_spent {
+            return;
+        }
+
+        self.punch = None;
+        self.waiting_since = now;
+
+        // Reconnecting gives up on its own schedule
+        if !self.is_reconnecting() {
+            self.on_punch_failed();
+        }
+    }
+
+    // Out of punch rounds, joiners have the rendezvous server move everyone to a hoster instead
+    fn on_punch_failed(&mut self) {
+        let is_joining = matches!(
+            self.rendezvous_request,
+            Some(Payloads::RendezvousHandshake { .. })
+        );
+
+        match self.rendezvous {
+            Some(rendezvous) if is_joining && !self.fallback_requested => {
+                info!("[NETWORK] Could not punch through, asking to move the session to a hoster");
+
+                self.fallback_requested = true;
+                self.net
+                    .send_message(
+                        Payloads::RequestHosterFallback {
+                            session_id: self.session_id.clone(),
+                        },
+                        rendezvous,
+                    )
+                    .ok();
+            }
+            _ => {
                 self.should_stop.store(true, SeqCst);
                 self.server_tx
                     .try_send(ReceiveMessage::Event(Event::UnablePunchthrough))
                     .ok();
             }
+        }
+    }
 
-            info!(
-                "[NETWORK] Sent packet to port {}. Retry #{}",
-                addr.port(),
-                self.retries
-            );
+    // Nothing came back in time from the rendezvous server
+    fn handle_waiting(&mut self) {
+        if self.connected()
+            || self.is_reconnecting()
+            || self.punch.is_some()
+            || self.rendezvous.is_none()
+            || self.waiting_since.elapsed().as_secs() < WAIT_TIMEOUT_SECS
+        {
+            return;
+        }
+
+        if !self.fallback_requested && self.try_stream_fallback() {
+            self.waiting_since = Instant::now();
+        } else {
+            self.stop("Could not connect to session.".to_string())
         }
     }
 
+    // Returns whether there was another transport left to try
+    fn try_stream_fallback(&mut self) -> bool {
+        let Some(request) = self.rendezvous_request.clone() else {
+            return false;
+        };
+
+        while let Some((kind, addr)) = self.stream_fallbacks.pop_front() {
+            let stream = match StreamTransport::connect(kind, addr, self.timeout) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    info!("[NETWORK] Could not connect over {:?}: {}", kind, e);
+                    continue;
+                }
+            };
+
+            info!("[NETWORK] No response over UDP, retrying over {:?}", kind);
+
+            self.net = SenderReceiver::from_stream(stream);
+            self.net.refuse_clear();
+            self.punch = None;
+            self.net.send_message(request, addr).ok();
+
+            return true;
+        }
+
+        false
+    }
+
+    fn on_connection_lost(&mut self) {
+        if self.resume_token.is_none() {
+            self.stop("Connection timeout".to_string());
+            return;
+        }
+
+        self.connected_address = None;
+
+        // Could drop again before the resume went through, the grace period doesn't start over
+        if self.reconnect.is_none() {
+            info!("[NETWORK] Lost connection, trying to resume");
+
+            self.reconnect = Some(Reconnect::new(Instant::now()));
+            self.server_tx
+                .try_send(ReceiveMessage::Event(Event::Reconnecting))
+                .ok();
+        }
+    }
+
+    fn handle_reconnect(&mut self) {
+        // Connected again and waiting on the server to hand the slot back
+        if self.connected() {
+            return;
+        }
+
+        let now = Instant::now();
+
+        let Some(reconnect) = self.reconnect.as_mut() else {
+            return;
+        };
+
+        if reconnect.has_expired(now) {
+            self.stop("Connection timeout".to_string());
+            return;
+        }
+
+        if !reconnect.is_due(now) {
+            return;
+        }
+
+        reconnect.on_attempt(now);
+        let attempts = reconnect.get_attempts();
+
+        info!("[NETWORK] Reconnect attempt #{}", attempts);
+
+        if let Err(e) = self.restart_connection() {
+            info!("[NETWORK] Could not reconnect: {}", e);
+        }
+    }
+
+    // A fresh socket, so neither side has stale connection state, then the same way in as the first time
+    fn restart_connection(&mut self) -> Result<(), Error> {
+        let socket = get_socket(self.timeout)?;
+        let port = socket.local_addr()?.port();
+
+        self.net = SenderReceiver::from_socket(socket);
+        self.net.refuse_clear();
+        self.key_request = None;
+        self.connected_address = None;
+        self.punch = self
+            .target_address
+            .map(|addr| PunchSession::exact(vec![addr]));
+        self.fallback_requested = false;
+        self.waiting_since = Instant::now();
+
+        if let Some(rendezvous) = self.rendezvous {
+            self.net
+                .send_message(
+                    Payloads::RendezvousHandshake {
+                        session_id: self.session_id.clone(),
+                        local_endpoint: get_local_endpoints_with_port(false, port),
+                        local_endpoint_v6: get_local_endpoints_with_port(true, port),
+                    },
+                    rendezvous,
+                )
+                .ok();
+        }
+
+        Ok(())
+    }
+
+    fn is_reconnecting(&self) -> bool {
+        self.reconnect.is_some()
+    }
+
     // Reliably compared to default heartbeat implementation
     fn handle_heartbeat(&mut self) {
         if !self.connected() {
@@ -173,7 +540,15 @@ impl TransferStruct {
             }
 
             self.heartbeat_instant = Instant::now();
-            self.net.send_message(Payloads::Heartbeat, addr).ok();
+            self.net
+                .send_message(
+                    Payloads::Heartbeat {
+                        time: get_seconds(),
+                        echo: self.clock.get_echo(),
+                    },
+                    addr,
+                )
+                .ok();
         }
     }
 
@@ -189,6 +564,13 @@ impl TransferStruct {
     }
 }
 
+fn get_socket(timeout: u64) -> Result<Socket, Error> {
+    Ok(Socket::from_udp_socket(
+        bind_dual_stack(None)?,
+        get_socket_config(timeout),
+    )?)
+}
+
 pub struct Client {
     should_stop: Arc<AtomicBool>,
     transfer: Option<Arc<Mutex<TransferStruct>>>,
@@ -226,22 +608,17 @@ impl Client {
         }
     }
 
-    fn get_socket(&self, is_ipv6: bool) -> Result<Socket, laminar::ErrorKind> {
-        Socket::bind_with_config(
-            get_bind_address(is_ipv6, None),
-            get_socket_config(self.timeout),
-        )
-    }
-
     pub fn start(
         &mut self,
         ip: IpAddr,
         port: u16,
         session_id: Option<String>, // Only used when connecting to the hoster as a secret password
+        secret: String,
     ) -> Result<(), Error> {
         self.run(
             ip.is_ipv6(),
             session_id,
+            secret,
             None,
             Some(match_ip_address_to_socket_addr(ip, port)),
         )
@@ -250,28 +627,37 @@ impl Client {
     pub fn start_with_hole_punch(
         &mut self,
         session_id: String,
+        secret: String,
         is_ipv6: bool,
     ) -> Result<(), Error> {
         self.run(
             is_ipv6,
             Some(session_id),
+            secret,
             Some(get_rendezvous_server(is_ipv6)?),
             None,
         )
     }
 
     pub fn start_with_relay(&mut self, is_ipv6: bool) -> Result<(), Error> {
-        self.run(is_ipv6, None, Some(get_rendezvous_server(is_ipv6)?), None)
+        self.run(
+            is_ipv6,
+            None,
+            generate_secret()?,
+            Some(get_rendezvous_server(is_ipv6)?),
+            None,
+        )
     }
 
     pub fn run(
         &mut self,
         is_ipv6: bool,
         session_id: Option<String>,
+        secret: String,
         rendezvous: Option<SocketAddr>,
         target_address: Option<SocketAddr>,
     ) -> Result<(), Error> {
-        let socket = self.get_socket(is_ipv6)?;
+        let socket = get_socket(self.timeout)?;
         let port = socket.local_addr().unwrap().port();
 
         self.is_host = session_id.is_none() && target_address.is_none();
@@ -284,43 +670,54 @@ impl Client {
             server_tx: self.server_tx.clone(),
             net: SenderReceiver::from_socket(socket),
             // Holepunching
-            retries: 0,
-            received_address: target_address.map(|x| vec![x]).unwrap_or_default(),
+            punch: target_address.map(|addr| PunchSession::exact(vec![addr])),
             connected_address: None,
-            retry_timer: None,
             session_id: session_id.clone().unwrap_or_default(),
+            secret,
+            fallback_requested: false,
+            waiting_since: Instant::now(),
+            rendezvous_request: None,
+            stream_fallbacks: VecDeque::new(),
+            timeout: self.timeout,
             // State
             name: self.get_server_name().to_string(),
             version: self.version.clone(),
             should_stop: self.should_stop.clone(),
             heartbeat_instant: Instant::now(),
+            clock: PeerClock::new(),
+            key_request: None,
+            is_key_holder: false,
+            // Hosting through the rendezvous server or joining a hoster by its address, only direct servers
+            // and hosts punched through to are reached without one
+            through_relay: session_id.is_some() == target_address.is_some(),
+            rendezvous,
+            target_address,
+            resume_token: None,
+            reconnect: None,
         };
 
+        transfer.net.refuse_clear();
+
         if let Some(rendezvous) = rendezvous {
-            if let Some(session_id) = session_id {
+            let request = if let Some(session_id) = session_id {
                 // Send a handshake to rendezvous to resolve session id with an ip address
-                transfer
-                    .net
-                    .send_message(
-                        Payloads::RendezvousHandshake {
-                            session_id,
-                            local_endpoint: get_local_endpoints_with_port(is_ipv6, port),
-                        },
-                        rendezvous,
-                    )
-                    .ok();
+                Payloads::RendezvousHandshake {
+                    session_id,
+                    local_endpoint: get_local_endpoints_with_port(false, port),
+                    local_endpoint_v6: get_local_endpoints_with_port(true, port),
+                }
             } else {
-                transfer
-                    .net
-                    .send_message(
-                        Payloads::RequestHosting {
-                            self_hosted: false,
-                            local_endpoint: get_local_endpoints_with_port(is_ipv6, port),
-                        },
-                        rendezvous,
-                    )
-                    .ok();
-            }
+                Payloads::RequestHosting {
+                    self_hosted: false,
+                    local_endpoint: get_local_endpoints_with_port(false, port),
+                    local_endpoint_v6: get_local_endpoints_with_port(true, port),
+                }
+            };
+
+            transfer.net.send_message(request.clone(), rendezvous).ok();
+
+            transfer.rendezvous_request = Some(request);
+            transfer.stream_fallbacks = get_stream_fallback_servers(is_ipv6).into();
         } else if let Some(addr) = target_address {
             info!("Sending request to port {} to join session", addr.port());
             // Send a handshake to the target address to start establishing a connection
@@ -340,7 +737,6 @@ impl Client {
 
         self.transfer = Some(transfer_send);
 
-        let rendezvous_timer = Instant::now();
         // Run main loop
         thread::spawn(move || {
             let sleep_duration = Duration::from_millis(LOOP_SLEEP_TIME_MS);
@@ -361,7 +757,7 @@ impl Client {
                                 .map(|x| x == addr)
                                 .unwrap_or(false);
                             if was_connected_addr {
-                                transfer.stop("Connection timeout".to_string())
+                                transfer.on_connection_lost();
                             }
                         }
                         Message::Metrics(addr, metrics) => {
@@ -378,15 +774,10 @@ impl Client {
                     }
                 }
 
-                // Check rendezvous timer
-                if !transfer.connected()
-                    && rendezvous.is_some()
-                    && rendezvous_timer.elapsed().as_secs() >= 5
-                {
-                    transfer.stop("Could not connect to session.".to_string())
-                }
-
+                transfer.handle_waiting();
+                transfer.handle_reconnect();
                 transfer.handle_handshake();
+                transfer.handle_key_timeout();
                 transfer.handle_app_message();
                 transfer.handle_heartbeat();
 
@@ -431,10 +822,19 @@ impl TransferClient for Client {
         None
     }
 
+    fn get_join_code(&self) -> Option<String> {
+        let transfer = self.transfer.as_ref()?.lock().unwrap();
+        Some(get_join_code(&transfer.session_id, &transfer.secret))
+    }
+
     fn stop(&mut self, reason: String) {
         self.should_stop.store(true, SeqCst);
         self.server_tx
             .try_send(ReceiveMessage::Event(Event::ConnectionLost(reason)))
             .ok();
     }
+
+    fn close(&mut self) {
+        self.should_stop.store(true, SeqCst);
+    }
 }
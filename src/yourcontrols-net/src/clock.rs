@@ -0,0 +1,213 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+// About 4 seconds worth of heartbeats
+const MAX_SAMPLES: usize = 8;
+// Real clocks drift far less than this, anything more is noise between samples
+const MAX_DRIFT: f64 = 0.001;
+
+// A heartbeat from the peer and when it arrived by our clock, echoed back so the peer can measure the round trip
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct ClockSample {
+    pub sent: f64,
+    pub received: f64,
+}
+
+struct OffsetSample {
+    local_time: f64,
+    offset: f64,
+    rtt: f64,
+}
+
+// NTP style estimate of how far ahead a peer's clock is from ours, so times it sends can be put into our time base
+#[derive(Default)]
+pub struct PeerClock {
+    samples: VecDeque<OffsetSample>,
+    // Last heartbeat from the peer, sent back with ours
+    last_heartbeat: Option<ClockSample>,
+    // Seconds the offset changes by per second
+    drift: f64,
+}
+
+impl PeerClock {
+    pub fn new() -> Self {
+        Self {
+            samples: VecDeque::new(),
+            last_heartbeat: None,
+            drift: 0.0,
+        }
+    }
+
+    pub fn get_echo(&self) -> Option<ClockSample> {
+        self.last_heartbeat
+    }
+
+    pub fn on_heartbeat(&mut self, time: f64, echo: Option<ClockSample>, now: f64) {
+        self.last_heartbeat = Some(ClockSample {
+            sent: time,
+            received: now,
+        });
+
+        // Peer doesn't know about our heartbeats yet
+        let echo = match echo {
+            Some(echo) => echo,
+            None => return,
+        };
+
+        // Time on the wire, without how long the peer held onto our heartbeat
+        let rtt = (now - echo.sent) - (time - echo.received);
+        // Our own clock jumped
+        if rtt < 0.0 {
+            return;
+        }
+
+        self.samples.push_back(OffsetSample {
+            local_time: now,
+            offset: ((echo.received - echo.sent) + (time - now)) / 2.0,
+            rtt,
+        });
+
+        if self.samples.len() > MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+
+        self.drift = self.estimate_drift();
+    }
+
+    // The quickest round trip was the least affected by queuing, so it gives the best offset
+    fn best_sample(&self) -> Option<&OffsetSample> {
+        self.samples
+            .iter()
+            .min_by(|a, b| a.rtt.partial_cmp(&b.rtt).unwrap())
+    }
+
+    // Least squares slope of the offset over time
+    fn estimate_drift(&self) -> f64 {
+        let count = self.samples.len() as f64;
+        if count < 2.0 {
+            return 0.0;
+        }
+
+        let mean_time = self.samples.iter().map(|x| x.local_time).sum::<f64>() / count;
+        let mean_offset = self.samples.iter().map(|x| x.offset).sum::<f64>() / count;
+
+        let mut covariance = 0.0;
+        let mut variance = 0.0;
+
+        for sample in self.samples.iter() {
+            let dt = sample.local_time - mean_time;
+            covariance += dt * (sample.offset - mean_offset);
+            variance += dt * dt;
+        }
+
+        if variance < f64::EPSILON {
+            return 0.0;
+        }
+
+        (covariance / variance).clamp(-MAX_DRIFT, MAX_DRIFT)
+    }
+
+    pub fn get_offset(&self, now: f64) -> Option<f64> {
+        let best = self.best_sample()?;
+        Some(best.offset + self.drift * (now - best.local_time))
+    }
+
+    // Times are passed through as is until the first round trip completes
+    pub fn to_local_time(&self, time: f64, now: f64) -> f64 {
+        time - self.get_offset(now).unwrap_or(0.0)
+    }
+
+    // One of our times in the peer's time base
+    pub fn to_peer_time(&self, time: f64, now: f64) -> f64 {
+        time + self.get_offset(now).unwrap_or(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Peer clock runs 100 seconds ahead of ours
+    const PEER_OFFSET: f64 = 100.0;
+
+    fn exchange(clock: &mut PeerClock, now: f64, one_way: f64, held: f64) {
+        let sent = now;
+        let received = sent + one_way + PEER_OFFSET;
+        let replied = received + held;
+
+        clock.on_heartbeat(
+            replied,
+            Some(ClockSample { sent, received }),
+            replied - PEER_OFFSET + one_way,
+        );
+    }
+
+    #[test]
+    fn test_estimates_offset() {
+        let mut clock = PeerClock::new();
+
+        assert_eq!(clock.to_local_time(5.0, 0.0), 5.0);
+
+        exchange(&mut clock, 0.0, 0.05, 0.2);
+        assert!((clock.get_offset(1.0).unwrap() - PEER_OFFSET).abs() < 1e-9);
+        assert!((clock.to_local_time(PEER_OFFSET + 1.0, 1.0) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_prefers_fastest_round_trip() {
+        let mut clock = PeerClock::new();
+
+        // Queuing only on the way back skews the offset by half the extra delay
+        clock.on_heartbeat(
+            PEER_OFFSET + 0.1,
+            Some(ClockSample {
+                sent: 0.0,
+                received: PEER_OFFSET + 0.02,
+            }),
+            0.4,
+        );
+        exchange(&mut clock, 0.5, 0.02, 0.0);
+
+        assert!((clock.get_offset(0.6).unwrap() - PEER_OFFSET).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_relayed_update_between_skewed_clocks() {
+        // Sender is 100 seconds behind the relay, the receiver 40 ahead of it
+        let mut sender = PeerClock::new();
+        let mut receiver = PeerClock::new();
+        exchange(&mut sender, 0.0, 0.05, 0.1);
+        receiver.on_heartbeat(
+            10.1,
+            Some(ClockSample {
+                sent: 50.0,
+                received: 10.05,
+            }),
+            50.15,
+        );
+
+        // The relay can't open a sealed update, so its time has to be in the relay's base already
+        let sent_at = 1.0;
+        let relayed = sender.to_peer_time(sent_at, sent_at);
+        assert!((relayed - (sent_at + PEER_OFFSET)).abs() < 1e-9);
+
+        // Same moment by the receiver's clock
+        let received = receiver.to_local_time(relayed, 200.0);
+        assert!((received - (sent_at + PEER_OFFSET + 40.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_ignores_missing_echo() {
+        let mut clock = PeerClock::new();
+
+        clock.on_heartbeat(PEER_OFFSET, None, 0.0);
+        assert!(clock.get_offset(0.0).is_none());
+        assert_eq!(
+            clock.get_echo(),
+            Some(ClockSample {
+                sent: PEER_OFFSET,
+                received: 0.0
+            })
+        );
+    }
+}
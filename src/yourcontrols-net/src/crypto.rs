@@ -0,0 +1,314 @@
+use blake2::{Blake2s256, Digest};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use yourcontrols_types::Error;
+
+// Unauthenticated ephemeral keys, authenticated by everyone knowing the join secret
+const NOISE_PARAMS: &str = "Noise_NNpsk0_25519_ChaChaPoly_BLAKE2s";
+const PROLOGUE: &[u8] = b"YourControls session key v2";
+const MAX_HANDSHAKE_LEN: usize = 1024;
+// How far behind the newest nonce from a sender a late packet may still be
+const REPLAY_WINDOW: u64 = 64;
+
+const KEY_LEN: usize = 32;
+// Part of the join code the servers never see, 5 bits a letter so 130 bits in all
+const SECRET_LEN: usize = 26;
+const SECRET_LETTERS: &[u8; 32] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+fn crypto_error(e: impl std::fmt::Display) -> Error {
+    Error::CryptoError(e.to_string())
+}
+
+// Made up by whoever holds the session key, joiners get it along with the session code
+pub fn generate_secret() -> Result<String, Error> {
+    let mut bytes = [0; SECRET_LEN];
+    getrandom::getrandom(&mut bytes).map_err(crypto_error)?;
+
+    Ok(bytes
+        .iter()
+        .map(|byte| SECRET_LETTERS[*byte as usize % SECRET_LETTERS.len()] as char)
+        .collect())
+}
+
+fn get_psk(secret: &str) -> [u8; KEY_LEN] {
+    let mut hasher = Blake2s256::new();
+    hasher.update(PROLOGUE);
+    hasher.update(secret.as_bytes());
+    hasher.finalize().into()
+}
+
+fn build_handshake(secret: &str, initiator: bool) -> Result<snow::HandshakeState, Error> {
+    let psk = get_psk(secret);
+    let builder = snow::Builder::new(NOISE_PARAMS.parse().map_err(crypto_error)?)
+        .prologue(PROLOGUE)
+        .psk(0, &psk);
+
+    if initiator {
+        builder.build_initiator()
+    } else {
+        builder.build_responder()
+    }
+    .map_err(crypto_error)
+}
+
+// What the host hands out at the end of the handshake
+#[derive(Serialize, Deserialize)]
+struct KeyGrant {
+    key: [u8; KEY_LEN],
+}
+
+// A joiner asking the host for the session key
+pub struct KeyRequest {
+    handshake: snow::HandshakeState,
+}
+
+impl KeyRequest {
+    // Returns the request along with the first handshake message to send
+    pub fn new(secret: &str) -> Result<(Self, Vec<u8>), Error> {
+        let mut handshake = build_handshake(secret, true)?;
+
+        let mut message = vec![0; MAX_HANDSHAKE_LEN];
+        let len = handshake
+            .write_message(&[], &mut message)
+            .map_err(crypto_error)?;
+        message.truncate(len);
+
+        Ok((Self { handshake }, message))
+    }
+
+    pub fn read_response(mut self, message: &[u8]) -> Result<SessionKey, Error> {
+        let mut payload = vec![0; MAX_HANDSHAKE_LEN];
+        let len = self
+            .handshake
+            .read_message(message, &mut payload)
+            .map_err(crypto_error)?;
+
+        let grant: KeyGrant = rmp_serde::from_slice(&payload[..len])?;
+
+        SessionKey::from_bytes(grant.key)
+    }
+}
+
+// The host's side, answers a joiner's first handshake message with the session key
+pub fn answer_key_request(
+    secret: &str,
+    message: &[u8],
+    key: &SessionKey,
+) -> Result<Vec<u8>, Error> {
+    let mut handshake = build_handshake(secret, false)?;
+
+    let mut payload = vec![0; MAX_HANDSHAKE_LEN];
+    handshake
+        .read_message(message, &mut payload)
+        .map_err(crypto_error)?;
+
+    let grant = rmp_serde::to_vec(&KeyGrant { key: key.key })?;
+
+    let mut response = vec![0; MAX_HANDSHAKE_LEN];
+    let len = handshake
+        .write_message(&grant, &mut response)
+        .map_err(crypto_error)?;
+    response.truncate(len);
+
+    Ok(response)
+}
+
+// Nonces already seen from one sender
+#[derive(Default)]
+struct ReplayWindow {
+    newest: u64,
+    // Bit n set means newest - n was seen
+    seen: u64,
+}
+
+impl ReplayWindow {
+    fn check_and_update(&mut self, nonce: u64) -> bool {
+        if nonce > self.newest {
+            let shift = nonce - self.newest;
+            self.seen = if shift >= REPLAY_WINDOW {
+                0
+            } else {
+                self.seen << shift
+            };
+            self.seen |= 1;
+            self.newest = nonce;
+            return true;
+        }
+
+        let age = self.newest - nonce;
+        if age >= REPLAY_WINDOW || self.seen & (1 << age) != 0 {
+            return false;
+        }
+
+        self.seen |= 1 << age;
+        true
+    }
+}
+
+pub struct SealedData {
+    pub sender: u32,
+    pub nonce: u64,
+    pub data: Vec<u8>,
+}
+
+// Key everyone in the session shares. Each holder picks a random sender id so nonces never repeat between them
+pub struct SessionKey {
+    key: [u8; KEY_LEN],
+    cipher: ChaCha20Poly1305,
+    sender: u32,
+    next_nonce: u64,
+    windows: HashMap<u32, ReplayWindow>,
+}
+
+impl SessionKey {
+    pub fn generate() -> Result<Self, Error> {
+        let mut key = [0; KEY_LEN];
+        getrandom::getrandom(&mut key).map_err(crypto_error)?;
+
+        Self::from_bytes(key)
+    }
+
+    fn from_bytes(key: [u8; KEY_LEN]) -> Result<Self, Error> {
+        let mut sender = [0; 4];
+        getrandom::getrandom(&mut sender).map_err(crypto_error)?;
+
+        Ok(Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+            key,
+            sender: u32::from_le_bytes(sender),
+            next_nonce: 1,
+            windows: HashMap::new(),
+        })
+    }
+
+    fn get_nonce(sender: u32, nonce: u64) -> Nonce {
+        let mut bytes = [0; 12];
+        bytes[..4].copy_from_slice(&sender.to_le_bytes());
+        bytes[4..].copy_from_slice(&nonce.to_le_bytes());
+        Nonce::clone_from_slice(&bytes)
+    }
+
+    pub fn seal(&mut self, plaintext: &[u8]) -> Result<SealedData, Error> {
+        let nonce = self.next_nonce;
+        self.next_nonce += 1;
+
+        let data = self
+            .cipher
+            .encrypt(&Self::get_nonce(self.sender, nonce), plaintext)
+            .map_err(crypto_error)?;
+
+        Ok(SealedData {
+            sender: self.sender,
+            nonce,
+            data,
+        })
+    }
+
+    pub fn open(&mut self, sealed: &SealedData) -> Result<Vec<u8>, Error> {
+        let plaintext = self
+            .cipher
+            .decrypt(
+                &Self::get_nonce(sealed.sender, sealed.nonce),
+                sealed.data.as_slice(),
+            )
+            .map_err(crypto_error)?;
+
+        // Only count it as seen once it's known to be genuine
+        if !self
+            .windows
+            .entry(sealed.sender)
+            .or_default()
+            .check_and_update(sealed.nonce)
+        {
+            return Err(Error::CryptoError("Replayed payload".to_string()));
+        }
+
+        Ok(plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exchange(joiner_secret: &str, host_secret: &str) -> Result<SessionKey, Error> {
+        let host_key = SessionKey::generate()?;
+
+        let (request, message) = KeyRequest::new(joiner_secret)?;
+        let response = answer_key_request(host_secret, &message, &host_key)?;
+
+        request.read_response(&response)
+    }
+
+    #[test]
+    fn test_key_exchange() {
+        let mut host_key = SessionKey::generate().unwrap();
+
+        let (request, message) = KeyRequest::new("ABCDEF").unwrap();
+        let response = answer_key_request("ABCDEF", &message, &host_key).unwrap();
+        let mut joiner_key = request.read_response(&response).unwrap();
+
+        let sealed = joiner_key.seal(b"hello").unwrap();
+        assert_ne!(sealed.data, b"hello");
+        assert_eq!(host_key.open(&sealed).unwrap(), b"hello");
+
+        let sealed = host_key.seal(b"world").unwrap();
+        assert_eq!(joiner_key.open(&sealed).unwrap(), b"world");
+    }
+
+    #[test]
+    fn test_wrong_secret_fails() {
+        assert!(exchange("ABCDEF", "ABCDEG").is_err());
+        // Knowing only what the servers know isn't enough
+        assert!(exchange("", &generate_secret().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_secrets_differ() {
+        let secret = generate_secret().unwrap();
+
+        assert_eq!(secret.len(), SECRET_LEN);
+        assert!(secret.bytes().all(|byte| SECRET_LETTERS.contains(&byte)));
+        assert_ne!(secret, generate_secret().unwrap());
+    }
+
+    #[test]
+    fn test_tampering_fails() {
+        let mut key = SessionKey::generate().unwrap();
+        let mut other = SessionKey::from_bytes(key.key).unwrap();
+
+        let mut sealed = key.seal(b"take control").unwrap();
+        sealed.data[0] ^= 1;
+        assert!(other.open(&sealed).is_err());
+
+        // A failed attempt doesn't burn the nonce
+        sealed.data[0] ^= 1;
+        assert!(other.open(&sealed).is_ok());
+    }
+
+    #[test]
+    fn test_replays_are_rejected() {
+        let mut key = SessionKey::generate().unwrap();
+        let mut other = SessionKey::from_bytes(key.key).unwrap();
+
+        let first = key.seal(b"1").unwrap();
+        let second = key.seal(b"2").unwrap();
+
+        // Out of order is fine, twice is not
+        assert!(other.open(&second).is_ok());
+        assert!(other.open(&first).is_ok());
+        assert!(other.open(&first).is_err());
+        assert!(other.open(&second).is_err());
+
+        // Too old to tell
+        let late = key.seal(b"late").unwrap();
+        for _ in 0..REPLAY_WINDOW {
+            other.open(&key.seal(b"x").unwrap()).unwrap();
+        }
+        assert!(other.open(&late).is_err());
+    }
+}
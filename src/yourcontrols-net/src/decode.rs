@@ -0,0 +1,228 @@
+use yourcontrols_types::Error;
+use zstd::bulk::Decompressor;
+
+use crate::definition::{DEFINITION_CHUNK_SIZE, REQUEST_BATCH};
+use crate::messages::{PayloadWrapper, Payloads};
+
+// Largest anything may decompress to, definitions sent whole by older versions are the biggest
+const MAX_PAYLOAD_SIZE: usize = 16 * 1024 * 1024;
+// zstd doesn't do better than this on what we send, a larger claimed size is made up
+const MAX_COMPRESSION_RATIO: usize = 64;
+// Small payloads are allowed this much regardless, they compress unpredictably
+const MIN_SIZE_ALLOWANCE: usize = 64 * 1024;
+// Payloads not listed in get_max_size are a few names and numbers
+const DEFAULT_MAX_SIZE: usize = 64 * 1024;
+const MAX_UPDATE_SIZE: usize = 2 * 1024 * 1024;
+// Endpoints of one peer, the rendezvous server never sends more
+const MAX_PEERS: usize = 64;
+
+fn get_max_size(payload: &Payloads) -> usize {
+    match payload {
+        // Sealed payloads may carry a whole definition too
+        Payloads::AircraftDefinition { .. } | Payloads::Encrypted { .. } => MAX_PAYLOAD_SIZE,
+        Payloads::Update { .. } => MAX_UPDATE_SIZE,
+        // Room for the hash and the rest of the payload around the chunk
+        Payloads::DefinitionChunk { .. } => DEFINITION_CHUNK_SIZE + 1024,
+        _ => DEFAULT_MAX_SIZE,
+    }
+}
+
+fn has_valid_collections(payload: &Payloads) -> bool {
+    match payload {
+        Payloads::Update { data, .. } => data.is_within_limits(),
+        Payloads::AttemptConnection { peers } => peers.len() <= MAX_PEERS,
+        Payloads::RequestDefinitionChunks { chunks, .. } => chunks.len() <= REQUEST_BATCH as usize,
+        Payloads::Encrypted {
+            visible: Some(visible),
+            ..
+        } => has_valid_collections(visible),
+        _ => true,
+    }
+}
+
+// Checked before anything is allocated for it
+fn check_claimed_size(size: usize, compressed_size: usize) -> Result<(), Error> {
+    let allowed = compressed_size
+        .saturating_mul(MAX_COMPRESSION_RATIO)
+        .clamp(MIN_SIZE_ALLOWANCE, MAX_PAYLOAD_SIZE);
+
+    if size > allowed {
+        return Err(Error::InvalidPayload(format!(
+            "Claims {} bytes from {} compressed",
+            size, compressed_size
+        )));
+    }
+
+    Ok(())
+}
+
+// Turns bytes off the wire back into payloads, refusing anything larger than a real peer would send
+pub struct PayloadDecoder {
+    decompressor: Decompressor<'static>,
+}
+
+impl PayloadDecoder {
+    pub fn new() -> Result<Self, Error> {
+        Ok(Self {
+            decompressor: Decompressor::new()?,
+        })
+    }
+
+    pub fn decode(&mut self, bytes: &[u8]) -> Result<Payloads, Error> {
+        // Decode wrapper struct
+        let wrapper: PayloadWrapper = rmp_serde::from_slice(bytes)?;
+
+        check_claimed_size(wrapper.size, wrapper.data.len())?;
+
+        // Decompress, never into more than the claimed size
+        let payload_bytes = self.decompressor.decompress(&wrapper.data, wrapper.size)?;
+
+        if payload_bytes.len() != wrapper.size {
+            return Err(Error::InvalidPayload(format!(
+                "Claimed {} bytes but was {}",
+                wrapper.size,
+                payload_bytes.len()
+            )));
+        }
+
+        // Decode to struct
+        let payload: Payloads = rmp_serde::from_slice(&payload_bytes)?;
+
+        if payload_bytes.len() > get_max_size(&payload) {
+            return Err(Error::InvalidPayload(format!(
+                "{} bytes is too large for its type",
+                payload_bytes.len()
+            )));
+        }
+
+        if !has_valid_collections(&payload) {
+            return Err(Error::InvalidPayload(
+                "Too many entries in a collection".to_string(),
+            ));
+        }
+
+        Ok(payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::definition::hash_definition;
+    use yourcontrols_types::{AllNeedSync, VarReaderTypes, MAX_SYNC_ENTRIES};
+
+    fn wrap(payload_bytes: &[u8], size: usize) -> Vec<u8> {
+        let wrapper = PayloadWrapper {
+            data: zstd::bulk::compress(payload_bytes, 0).unwrap(),
+            size,
+        };
+        rmp_serde::to_vec(&wrapper).unwrap()
+    }
+
+    fn encode(payload: &Payloads) -> Vec<u8> {
+        let payload_bytes = rmp_serde::to_vec(payload).unwrap();
+        wrap(&payload_bytes, payload_bytes.len())
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Payloads, Error> {
+        PayloadDecoder::new().unwrap().decode(bytes)
+    }
+
+    fn update(vars: usize) -> Payloads {
+        let mut data = AllNeedSync::new();
+        for i in 0..vars {
+            data.lvars
+                .insert(format!("L:VAR_{}", i), VarReaderTypes::F64(1.0));
+        }
+
+        Payloads::Update {
+            data,
+            from: "Host".to_string(),
+            is_unreliable: false,
+            time: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let payload = decode(&encode(&update(100))).unwrap();
+        assert!(matches!(payload, Payloads::Update { data, .. } if data.lvars.len() == 100));
+    }
+
+    #[test]
+    fn test_garbage_rejected() {
+        assert!(decode(&[]).is_err());
+        assert!(decode(&[0xFF; 64]).is_err());
+    }
+
+    #[test]
+    fn test_made_up_size_rejected() {
+        let payload_bytes = rmp_serde::to_vec(&Payloads::Ready).unwrap();
+
+        assert!(matches!(
+            decode(&wrap(&payload_bytes, usize::MAX)),
+            Err(Error::InvalidPayload(_))
+        ));
+        // Within what's allowed but not what's there
+        assert!(matches!(
+            decode(&wrap(&payload_bytes, payload_bytes.len() + 1)),
+            Err(Error::InvalidPayload(_))
+        ));
+    }
+
+    #[test]
+    fn test_size_limited_by_type() {
+        let chunk = Payloads::DefinitionChunk {
+            hash: String::new(),
+            index: 0,
+            bytes: vec![0; DEFINITION_CHUNK_SIZE].into(),
+        };
+        assert!(decode(&encode(&chunk)).is_ok());
+
+        // Doesn't compress, so it gets past the ratio, but no name is this long
+        let mut seed: u32 = 1;
+        let name = (0..DEFAULT_MAX_SIZE)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                (b'a' + (seed % 26) as u8) as char
+            })
+            .collect();
+        let name = Payloads::PlayerLeft { name };
+        assert!(matches!(
+            decode(&encode(&name)),
+            Err(Error::InvalidPayload(_))
+        ));
+    }
+
+    #[test]
+    fn test_chunk_of_high_bytes_fits() {
+        // Every byte would take two if the chunk went out as an array
+        let chunk = Payloads::DefinitionChunk {
+            hash: hash_definition(&[]),
+            index: u32::MAX,
+            bytes: vec![0xFF; DEFINITION_CHUNK_SIZE].into(),
+        };
+
+        assert!(matches!(
+            decode(&encode(&chunk)),
+            Ok(Payloads::DefinitionChunk { bytes, .. }) if bytes.iter().all(|byte| *byte == 0xFF)
+        ));
+    }
+
+    #[test]
+    fn test_oversized_collections_rejected() {
+        assert!(matches!(
+            decode(&encode(&update(MAX_SYNC_ENTRIES + 1))),
+            Err(Error::InvalidPayload(_))
+        ));
+
+        let request = Payloads::RequestDefinitionChunks {
+            from: String::new(),
+            hash: String::new(),
+            chunks: (0..REQUEST_BATCH + 1).collect(),
+        };
+        assert!(decode(&encode(&request)).is_err());
+    }
+}
@@ -0,0 +1,341 @@
+use blake2::{Blake2s256, Digest};
+use std::time::{Duration, Instant};
+use yourcontrols_types::Error;
+
+use crate::messages::Payloads;
+
+pub const DEFINITION_CHUNK_SIZE: usize = 16 * 1024;
+// Largest airliner definitions are a few MB, anything past this is not a definition
+const MAX_DEFINITION_SIZE: u32 = 64 * 1024 * 1024;
+// Chunks asked for at once, the next batch goes out once these arrived
+pub(crate) const REQUEST_BATCH: u32 = 32;
+// No chunk for this long, ask for the missing ones again
+const REREQUEST_AFTER: Duration = Duration::from_secs(3);
+
+pub fn hash_definition(bytes: &[u8]) -> String {
+    Blake2s256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn get_chunk_count(size: u32) -> u32 {
+    (size as usize).div_ceil(DEFINITION_CHUNK_SIZE) as u32
+}
+
+// Definitions split up for sending, either all there on the sending side or filled in as chunks arrive
+pub struct ChunkedDefinition {
+    hash: String,
+    size: u32,
+    // One per chunk, so a bad one is caught as it arrives rather than once all are in
+    chunk_hashes: Vec<String>,
+    chunks: Vec<Option<Box<[u8]>>>,
+    received: u32,
+    // Chunks below this were asked for
+    requested_up_to: u32,
+    last_activity: Instant,
+}
+
+impl ChunkedDefinition {
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let chunks: Vec<Option<Box<[u8]>>> = bytes
+            .chunks(DEFINITION_CHUNK_SIZE)
+            .map(|chunk| Some(chunk.into()))
+            .collect();
+        let count = chunks.len() as u32;
+        let chunk_hashes = bytes
+            .chunks(DEFINITION_CHUNK_SIZE)
+            .map(hash_definition)
+            .collect();
+
+        Self {
+            hash: hash_definition(bytes),
+            size: bytes.len() as u32,
+            chunk_hashes,
+            chunks,
+            received: count,
+            requested_up_to: count,
+            last_activity: Instant::now(),
+        }
+    }
+
+    pub fn from_manifest(
+        hash: String,
+        size: u32,
+        chunk_hashes: Vec<String>,
+    ) -> Result<Self, Error> {
+        let chunk_count = chunk_hashes.len();
+
+        if size == 0 || size > MAX_DEFINITION_SIZE || chunk_count != get_chunk_count(size) as usize
+        {
+            return Err(Error::InvalidDefinitionTransfer(format!(
+                "{} bytes in {} chunks",
+                size, chunk_count
+            )));
+        }
+
+        Ok(Self {
+            hash,
+            size,
+            chunk_hashes,
+            chunks: vec![None; chunk_count],
+            received: 0,
+            requested_up_to: 0,
+            last_activity: Instant::now(),
+        })
+    }
+
+    pub fn get_hash(&self) -> &str {
+        &self.hash
+    }
+
+    pub fn get_manifest(&self) -> Payloads {
+        Payloads::DefinitionManifest {
+            hash: self.hash.clone(),
+            size: self.size,
+            chunk_hashes: self.chunk_hashes.clone(),
+        }
+    }
+
+    pub fn get_chunk_count(&self) -> u32 {
+        self.chunks.len() as u32
+    }
+
+    pub fn get_received_count(&self) -> u32 {
+        self.received
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.received == self.get_chunk_count()
+    }
+
+    pub fn get_chunk(&self, index: u32) -> Option<Payloads> {
+        let bytes = self.chunks.get(index as usize)?.as_ref()?;
+
+        Some(Payloads::DefinitionChunk {
+            hash: self.hash.clone(),
+            index,
+            bytes: bytes.clone(),
+        })
+    }
+
+    fn get_expected_length(&self, index: u32) -> usize {
+        let start = index as usize * DEFINITION_CHUNK_SIZE;
+        (self.size as usize - start).min(DEFINITION_CHUNK_SIZE)
+    }
+
+    // Returns whether the chunk was new and what the manifest said. A bad one stays missing and is asked for again
+    pub fn add_chunk(&mut self, index: u32, bytes: Box<[u8]>, now: Instant) -> bool {
+        if index >= self.get_chunk_count() || bytes.len() != self.get_expected_length(index) {
+            return false;
+        }
+
+        let chunk = &mut self.chunks[index as usize];
+        if chunk.is_some() || hash_definition(&bytes) != self.chunk_hashes[index as usize] {
+            return false;
+        }
+
+        *chunk = Some(bytes);
+        self.received += 1;
+        self.last_activity = now;
+
+        true
+    }
+
+    fn get_outstanding(&self) -> Vec<u32> {
+        (0..self.requested_up_to)
+            .filter(|index| self.chunks[*index as usize].is_none())
+            .collect()
+    }
+
+    // Chunks to ask for now. What went missing, say across a reconnect, is asked for again
+    pub fn next_request(&mut self, now: Instant) -> Option<Vec<u32>> {
+        let outstanding = self.get_outstanding();
+
+        if outstanding.is_empty() {
+            if self.requested_up_to >= self.get_chunk_count() {
+                return None;
+            }
+
+            let end = (self.requested_up_to + REQUEST_BATCH).min(self.get_chunk_count());
+            let batch = (self.requested_up_to..end).collect();
+
+            self.requested_up_to = end;
+            self.last_activity = now;
+
+            return Some(batch);
+        }
+
+        if now.duration_since(self.last_activity) < REREQUEST_AFTER {
+            return None;
+        }
+
+        self.last_activity = now;
+
+        Some(outstanding)
+    }
+
+    // All chunks put back together, as long as they hash to what the manifest said
+    pub fn into_bytes(self) -> Result<Vec<u8>, Error> {
+        if !self.is_complete() {
+            return Err(Error::InvalidDefinitionTransfer(
+                "Missing chunks".to_string(),
+            ));
+        }
+
+        let bytes: Vec<u8> = self.chunks.into_iter().flatten().flatten().collect();
+
+        if hash_definition(&bytes) != self.hash {
+            return Err(Error::InvalidDefinitionTransfer(
+                "Hash does not match".to_string(),
+            ));
+        }
+
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_definition() -> Vec<u8> {
+        (0..DEFINITION_CHUNK_SIZE * 40 + 100)
+            .map(|i| (i % 251) as u8)
+            .collect()
+    }
+
+    fn get_download(sent: &ChunkedDefinition) -> ChunkedDefinition {
+        ChunkedDefinition::from_manifest(
+            sent.get_hash().to_string(),
+            sent.size,
+            sent.chunk_hashes.clone(),
+        )
+        .unwrap()
+    }
+
+    fn deliver(
+        sent: &ChunkedDefinition,
+        received: &mut ChunkedDefinition,
+        index: u32,
+        now: Instant,
+    ) -> bool {
+        match sent.get_chunk(index) {
+            Some(Payloads::DefinitionChunk { index, bytes, .. }) => {
+                received.add_chunk(index, bytes, now)
+            }
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = get_definition();
+        let sent = ChunkedDefinition::from_bytes(&bytes);
+        let mut received = get_download(&sent);
+        let now = Instant::now();
+
+        assert_eq!(sent.get_chunk_count(), 41);
+
+        while let Some(request) = received.next_request(now) {
+            for index in request {
+                assert!(deliver(&sent, &mut received, index, now));
+            }
+        }
+
+        assert!(received.is_complete());
+        assert_eq!(received.into_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_requests_in_batches_and_again_when_missing() {
+        let sent = ChunkedDefinition::from_bytes(&get_definition());
+        let mut received = get_download(&sent);
+        let now = Instant::now();
+
+        let first = received.next_request(now).unwrap();
+        assert_eq!(first, (0..REQUEST_BATCH).collect::<Vec<_>>());
+
+        // Two got lost
+        for index in 2..REQUEST_BATCH {
+            deliver(&sent, &mut received, index, now);
+        }
+        assert_eq!(received.next_request(now), None);
+        assert_eq!(
+            received.next_request(now + REREQUEST_AFTER),
+            Some(vec![0, 1])
+        );
+
+        deliver(&sent, &mut received, 0, now);
+        deliver(&sent, &mut received, 1, now);
+        assert_eq!(
+            received.next_request(now + REREQUEST_AFTER),
+            Some((REQUEST_BATCH..41).collect())
+        );
+    }
+
+    #[test]
+    fn test_bad_chunks_rejected() {
+        let sent = ChunkedDefinition::from_bytes(&get_definition());
+        let mut received = get_download(&sent);
+        let now = Instant::now();
+
+        assert!(!received.add_chunk(41, vec![0; DEFINITION_CHUNK_SIZE].into(), now));
+        // Last chunk is short
+        assert!(!received.add_chunk(40, vec![0; DEFINITION_CHUNK_SIZE].into(), now));
+        assert!(deliver(&sent, &mut received, 3, now));
+        assert!(!deliver(&sent, &mut received, 3, now));
+        assert_eq!(received.get_received_count(), 1);
+    }
+
+    #[test]
+    fn test_tampered_chunk_asked_for_again() {
+        let sent = ChunkedDefinition::from_bytes(&get_definition());
+        let mut received = get_download(&sent);
+        let now = Instant::now();
+
+        received.next_request(now);
+        assert!(!received.add_chunk(7, vec![0; DEFINITION_CHUNK_SIZE].into(), now));
+
+        for index in (0..REQUEST_BATCH).filter(|index| *index != 7) {
+            deliver(&sent, &mut received, index, now);
+        }
+        assert_eq!(received.next_request(now + REREQUEST_AFTER), Some(vec![7]));
+
+        assert!(deliver(&sent, &mut received, 7, now));
+    }
+
+    #[test]
+    fn test_tampered_manifest_fails_hash() {
+        let bytes = get_definition();
+        let sent = ChunkedDefinition::from_bytes(&bytes);
+        let mut received = ChunkedDefinition::from_manifest(
+            hash_definition(b"something else"),
+            sent.size,
+            sent.chunk_hashes.clone(),
+        )
+        .unwrap();
+        let now = Instant::now();
+
+        for index in 0..sent.get_chunk_count() {
+            deliver(&sent, &mut received, index, now);
+        }
+
+        assert!(received.into_bytes().is_err());
+    }
+
+    #[test]
+    fn test_manifest_must_add_up() {
+        let hashes = |count| vec![String::new(); count];
+
+        assert!(ChunkedDefinition::from_manifest(String::new(), 100, hashes(2)).is_err());
+        assert!(ChunkedDefinition::from_manifest(String::new(), 0, hashes(0)).is_err());
+        assert!(ChunkedDefinition::from_manifest(
+            String::new(),
+            MAX_DEFINITION_SIZE + 1,
+            hashes(4097)
+        )
+        .is_err());
+        assert!(ChunkedDefinition::from_manifest(String::new(), 100, hashes(1)).is_ok());
+    }
+}
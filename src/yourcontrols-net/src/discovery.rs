@@ -0,0 +1,237 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use spin_sleep::sleep;
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, AtomicU16, Ordering::SeqCst},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use yourcontrols_types::Error;
+
+use crate::util::get_canonical_addr;
+
+pub const DISCOVERY_PORT: u16 = 25072;
+const ADVERTISE_INTERVAL: Duration = Duration::from_secs(1);
+// A few missed adverts before a session drops off the list
+const SESSION_EXPIRY: Duration = Duration::from_secs(4);
+// Anything else broadcasting on the port is ignored
+const ADVERT_MAGIC: &[u8] = b"YCLAN";
+const ADVERT_VERSION: u8 = 1;
+const MAX_ADVERT_SIZE: usize = 1024;
+
+// What a Direct host tells everyone on the LAN about itself
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LanSession {
+    pub name: String,
+    pub aircraft: String,
+    pub players: u16,
+    pub version: String,
+    pub port: u16,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DiscoveredSession {
+    pub ip: String,
+    #[serde(flatten)]
+    pub session: LanSession,
+}
+
+fn encode_advert(session: &LanSession) -> Result<Vec<u8>, Error> {
+    let mut bytes = ADVERT_MAGIC.to_vec();
+    bytes.push(ADVERT_VERSION);
+    bytes.extend(rmp_serde::to_vec(session).map_err(Error::NetEncodeError)?);
+    Ok(bytes)
+}
+
+fn decode_advert(bytes: &[u8]) -> Option<LanSession> {
+    let bytes = bytes.strip_prefix(ADVERT_MAGIC)?;
+    let (version, session) = bytes.split_first()?;
+
+    if *version != ADVERT_VERSION {
+        return None;
+    }
+
+    rmp_serde::from_slice(session).ok()
+}
+
+// Broadcasts the session every second until should_stop is set. The host counts as a player too
+pub fn advertise(
+    session: LanSession,
+    connections: Arc<AtomicU16>,
+    should_stop: Arc<AtomicBool>,
+) -> Result<(), Error> {
+    let socket = UdpSocket::bind(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0))?;
+    socket.set_broadcast(true)?;
+
+    let target = SocketAddr::new(Ipv4Addr::BROADCAST.into(), DISCOVERY_PORT);
+
+    info!(
+        "[NETWORK] Advertising session on LAN port {}",
+        DISCOVERY_PORT
+    );
+
+    thread::spawn(move || {
+        let mut session = session;
+
+        while !should_stop.load(SeqCst) {
+            session.players = connections.load(SeqCst).saturating_add(1);
+
+            if let Ok(bytes) = encode_advert(&session) {
+                socket.send_to(&bytes, target).ok();
+            }
+
+            sleep(ADVERTISE_INTERVAL);
+        }
+    });
+
+    Ok(())
+}
+
+// Listens for hosts advertising on the LAN, sessions that stop advertising drop off
+pub struct LanBrowser {
+    socket: UdpSocket,
+    sessions: HashMap<SocketAddr, (LanSession, Instant)>,
+}
+
+impl LanBrowser {
+    pub fn bind() -> Result<Self, Error> {
+        // Other instances on the same machine may be listening too
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        socket.bind(&SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), DISCOVERY_PORT).into())?;
+
+        let socket: UdpSocket = socket.into();
+        socket.set_nonblocking(true)?;
+
+        Ok(Self {
+            socket,
+            sessions: HashMap::new(),
+        })
+    }
+
+    // Returns whether the list of sessions changed
+    pub fn poll(&mut self) -> bool {
+        let now = Instant::now();
+        let mut changed = false;
+        let mut buf = [0; MAX_ADVERT_SIZE];
+
+        while let Ok((size, addr)) = self.socket.recv_from(&mut buf) {
+            if let Some(session) = decode_advert(&buf[..size]) {
+                changed |= self.on_advert(get_canonical_addr(addr), session, now);
+            }
+        }
+
+        changed | self.expire(now)
+    }
+
+    fn on_advert(&mut self, addr: SocketAddr, session: LanSession, now: Instant) -> bool {
+        let key = SocketAddr::new(addr.ip(), session.port);
+        let changed = self.sessions.get(&key).map(|(known, _)| known) != Some(&session);
+
+        self.sessions.insert(key, (session, now));
+
+        changed
+    }
+
+    fn expire(&mut self, now: Instant) -> bool {
+        let count = self.sessions.len();
+
+        self.sessions
+            .retain(|_, (_, last_seen)| now.duration_since(*last_seen) < SESSION_EXPIRY);
+
+        self.sessions.len() != count
+    }
+
+    pub fn get_sessions(&self) -> Vec<DiscoveredSession> {
+        let mut sessions: Vec<DiscoveredSession> = self
+            .sessions
+            .iter()
+            .map(|(addr, (session, _))| DiscoveredSession {
+                ip: addr.ip().to_string(),
+                session: session.clone(),
+            })
+            .collect();
+
+        sessions.sort_by(|a, b| a.session.name.cmp(&b.session.name));
+        sessions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(name: &str, players: u16) -> LanSession {
+        LanSession {
+            name: name.to_string(),
+            aircraft: "Asobo C172".to_string(),
+            players,
+            version: "2.8.5".to_string(),
+            port: 25071,
+        }
+    }
+
+    fn browser() -> LanBrowser {
+        LanBrowser {
+            socket: UdpSocket::bind("127.0.0.1:0").unwrap(),
+            sessions: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_advert_round_trip() {
+        let bytes = encode_advert(&session("Host", 2)).unwrap();
+        assert_eq!(decode_advert(&bytes), Some(session("Host", 2)));
+    }
+
+    #[test]
+    fn test_foreign_packets_ignored() {
+        assert_eq!(decode_advert(b"M-SEARCH * HTTP/1.1"), None);
+
+        let mut bytes = encode_advert(&session("Host", 2)).unwrap();
+        bytes[ADVERT_MAGIC.len()] = ADVERT_VERSION + 1;
+        assert_eq!(decode_advert(&bytes), None);
+    }
+
+    #[test]
+    fn test_repeated_advert_is_not_a_change() {
+        let mut browser = browser();
+        let addr: SocketAddr = "192.168.1.20:50000".parse().unwrap();
+        let now = Instant::now();
+
+        assert!(browser.on_advert(addr, session("Host", 1), now));
+        assert!(!browser.on_advert(addr, session("Host", 1), now + ADVERTISE_INTERVAL));
+        // Someone joined
+        assert!(browser.on_advert(addr, session("Host", 2), now + ADVERTISE_INTERVAL * 2));
+
+        let sessions = browser.get_sessions();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].ip, "192.168.1.20");
+        assert_eq!(sessions[0].session.players, 2);
+    }
+
+    #[test]
+    fn test_silent_session_expires() {
+        let mut browser = browser();
+        let now = Instant::now();
+
+        browser.on_advert("192.168.1.20:50000".parse().unwrap(), session("A", 1), now);
+        browser.on_advert(
+            "192.168.1.21:50000".parse().unwrap(),
+            session("B", 1),
+            now + SESSION_EXPIRY,
+        );
+
+        assert!(!browser.expire(now + ADVERTISE_INTERVAL));
+        assert!(browser.expire(now + SESSION_EXPIRY));
+        assert_eq!(browser.get_sessions()[0].session.name, "B");
+    }
+}
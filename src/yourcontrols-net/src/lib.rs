@@ -1,12 +1,29 @@
 mod client;
+mod clock;
+mod crypto;
+mod decode;
+mod definition;
+mod discovery;
 mod messages;
+mod nat;
+mod portmap;
+mod punch;
+mod resume;
 mod server;
+mod stream;
 mod util;
 
 pub use client::Client;
-pub use messages::{Message, Payloads, SenderReceiver};
+pub use clock::{ClockSample, PeerClock};
+pub use decode::PayloadDecoder;
+pub use definition::{hash_definition, ChunkedDefinition};
+pub use discovery::{DiscoveredSession, LanBrowser, LanSession, DISCOVERY_PORT};
+pub use messages::{Message, PayloadWrapper, Payloads, SenderReceiver};
+pub use nat::{detect_nat_type, NatType};
+pub use portmap::PortMapMethod;
 pub use server::Server;
+pub use stream::{StreamKind, StreamTransport};
 pub use util::{
-    get_addr_from_hostname_and_port, get_rendezvous_server, get_socket_config, get_socket_duplex,
-    is_actually_ipv4, Event, ReceiveMessage, TransferClient,
+    get_addr_from_hostname_and_port, get_rendezvous_server, get_seconds, get_socket_config,
+    get_socket_duplex, is_actually_ipv4, split_join_code, Event, ReceiveMessage, TransferClient,
 };
@@ -1,10 +1,15 @@
-use crossbeam_channel::{Receiver, Sender};
+use crate::clock::ClockSample;
+use crate::crypto::{SealedData, SessionKey};
+use crate::decode::PayloadDecoder;
+use crate::stream::StreamTransport;
+use crate::util::{get_canonical_addr, get_mapped_addr};
+use crossbeam_channel::{Receiver, Sender, TryRecvError};
 use laminar::{Metrics, Packet, Socket, SocketEvent};
 use rmp_serde::{self};
 use serde::{Deserialize, Serialize};
-use std::{net::SocketAddr, time::Instant};
-use yourcontrols_types::AllNeedSync;
-use zstd::bulk::{Compressor, Decompressor};
+use std::{collections::HashSet, net::SocketAddr, time::Instant};
+use yourcontrols_types::{AllNeedSync, WeatherSample};
+use zstd::bulk::Compressor;
 
 use yourcontrols_types::Error;
 
@@ -14,6 +19,7 @@ pub enum Payloads {
     InvalidVersion {
         server_version: String,
     },
+    // Whole definitions at once, from versions before they went in chunks
     AircraftDefinition {
         bytes: Box<[u8]>,
     },
@@ -21,6 +27,9 @@ pub enum Payloads {
     RequestHosting {
         self_hosted: bool,
         local_endpoint: Option<SocketAddr>,
+        // The same for IPv6, so the rendezvous server can pair on whichever family both sides have
+        #[serde(default)]
+        local_endpoint_v6: Option<SocketAddr>,
     },
     ConnectionDenied {
         reason: String,
@@ -56,12 +65,31 @@ pub enum Payloads {
     SetSelfObserver {
         name: String,
     },
+    // Someone paused or changed sim rate, the host decides if it goes through
+    RequestPause {
+        from: String,
+        paused: bool,
+        sim_rate: f64,
+    },
+    // Pause state everyone should be in, sent by the host
+    SetPause {
+        from: String,
+        paused: bool,
+        sim_rate: f64,
+    },
+    // Host's weather, sent periodically when weather sync is on
+    SetWeather {
+        from: String,
+        weather: WeatherSample,
+    },
     // Ready to receive data
     Ready,
     // Hole punching payloads
     RendezvousHandshake {
         session_id: String,
         local_endpoint: Option<SocketAddr>,
+        #[serde(default)]
+        local_endpoint_v6: Option<SocketAddr>,
     },
     Handshake {
         session_id: String,
@@ -78,13 +106,108 @@ pub enum Payloads {
     PeerEstablished {
         peer: SocketAddr,
     },
-    Heartbeat,
+    Heartbeat {
+        time: f64,
+        // Last heartbeat received from the peer, used to estimate the clock offset between the two
+        echo: Option<ClockSample>,
+    },
+    // Noise handshake for the session key between the host and someone joining, relays pass it along by name
+    KeyExchange {
+        peer: String,
+        data: Vec<u8>,
+    },
+    // A payload only the session can read, relays forward it without looking inside
+    Encrypted {
+        sender: u32,
+        nonce: u64,
+        data: Vec<u8>,
+        is_unreliable: bool,
+        channel: u8,
+        // Readable copy of what relays need to keep track of the session. Peers only trust the sealed one
+        visible: Option<Box<Payloads>>,
+    },
+    // Lets a client that dropped out take its slot back within the grace period, replaced after every resume
+    ResumeToken {
+        token: String,
+    },
+    // Sent instead of InitHandshake when reconnecting
+    Resume {
+        name: String,
+        token: String,
+    },
+    Resumed,
+    // Asks the rendezvous server where it sees us from, optionally answered from its second port
+    NatProbe {
+        change_port: bool,
+    },
+    NatProbeResponse {
+        observed: SocketAddr,
+    },
+    // Hole punching to the host ran out of rounds, the whole session moves to a hoster instead
+    RequestHosterFallback {
+        session_id: String,
+    },
+    // Definitions in chunks, joiners that have a copy with this hash never ask for any
+    DefinitionManifest {
+        hash: String,
+        size: u32,
+        chunk_hashes: Vec<String>,
+    },
+    RequestDefinitionChunks {
+        from: String,
+        hash: String,
+        chunks: Vec<u32>,
+    },
+    DefinitionChunk {
+        hash: String,
+        index: u32,
+        #[serde(with = "raw_bytes")]
+        bytes: Box<[u8]>,
+    },
 }
 
+// Written as one binary blob, serde's default is an array where every byte above 0x7F takes two
+mod raw_bytes {
+    use serde::{
+        de::{Error, Visitor},
+        Deserializer, Serializer,
+    };
+    use std::fmt;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(bytes)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Box<[u8]>, D::Error> {
+        struct BytesVisitor;
+
+        impl<'de> Visitor<'de> for BytesVisitor {
+            type Value = Box<[u8]>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("bytes")
+            }
+
+            fn visit_bytes<E: Error>(self, bytes: &[u8]) -> Result<Self::Value, E> {
+                Ok(bytes.into())
+            }
+
+            fn visit_byte_buf<E: Error>(self, bytes: Vec<u8>) -> Result<Self::Value, E> {
+                Ok(bytes.into_boxed_slice())
+            }
+        }
+
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+}
+
+// Name relays use for payloads they make up themselves
+pub const RELAY_NAME: &str = "SERVER";
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct PayloadWrapper {
     pub data: Vec<u8>,
-    pub size: usize, // used for decompression buffer size
+    pub size: usize, // used for decompression buffer size, checked against limits first
 }
 
 pub enum Message {
@@ -112,6 +235,8 @@ fn get_packet_for_message(
         Payloads::InvalidName {..} => Packet::reliable_unordered(target, payload_bytes),
         Payloads::PeerEstablished {..} |
         Payloads::RendezvousHandshake  {..} |
+        Payloads::NatProbe {..} |
+        Payloads::NatProbeResponse {..} |
         Payloads::Handshake {..} => Packet::unreliable(target, payload_bytes),
         Payloads::InitHandshake {..} |
         Payloads::PlayerJoined {..} |
@@ -119,63 +244,310 @@ fn get_packet_for_message(
         Payloads::SetObserver {..} |
         Payloads::Ready |
         Payloads::TransferControl {..} |
+        Payloads::RequestPause {..} |
+        Payloads::SetPause {..} |
+        Payloads::SetWeather {..} |
         Payloads::AircraftDefinition {..}  |
+        Payloads::DefinitionManifest {..} |
+        Payloads::RequestDefinitionChunks {..} |
+        Payloads::KeyExchange {..} |
+        Payloads::ResumeToken {..} |
+        Payloads::Resume {..} |
+        Payloads::Resumed |
+        Payloads::RequestHosterFallback {..} |
         Payloads::RequestHosting {..} => Packet::reliable_ordered(target, payload_bytes, Some(1)),
+        // Own channel, so updates don't wait behind a big definition
+        Payloads::DefinitionChunk {..} => Packet::reliable_ordered(target, payload_bytes, Some(3)),
         Payloads::Update {is_unreliable, ..} => if *is_unreliable {Packet::unreliable_sequenced(target, payload_bytes, Some(0))} else {Packet::reliable_ordered(target, payload_bytes, Some(0))}
+        Payloads::Encrypted {is_unreliable, channel, ..} => if *is_unreliable {Packet::unreliable_sequenced(target, payload_bytes, Some(*channel))} else {Packet::reliable_ordered(target, payload_bytes, Some(*channel))}
+    }
+}
+
+// Hop level payloads, or ones needed before anyone has the key
+fn can_be_sealed(message: &Payloads) -> bool {
+    !matches!(
+        message,
+        Payloads::InvalidName
+            | Payloads::InvalidVersion { .. }
+            | Payloads::ConnectionDenied { .. }
+            | Payloads::SetHost
+            | Payloads::RequestHosting { .. }
+            | Payloads::InitHandshake { .. }
+            | Payloads::Resume { .. }
+            | Payloads::RendezvousHandshake { .. }
+            | Payloads::Handshake { .. }
+            | Payloads::NatProbe { .. }
+            | Payloads::NatProbeResponse { .. }
+            | Payloads::RequestHosterFallback { .. }
+            | Payloads::HostingReceived { .. }
+            | Payloads::AttemptConnection { .. }
+            | Payloads::AttemptHosterConnection { .. }
+            | Payloads::PeerEstablished { .. }
+            | Payloads::Heartbeat { .. }
+            | Payloads::KeyExchange { .. }
+            | Payloads::Encrypted { .. }
+            // The same for everyone flying the aircraft, relays keep a copy to hand out. Checked against the sealed manifest
+            | Payloads::RequestDefinitionChunks { .. }
+            | Payloads::DefinitionChunk { .. }
+    )
+}
+
+// What a peer with the key may still send in the clear. Relays can't seal what they make up themselves,
+// anyone else has the key and seals those too
+pub fn is_accepted_in_clear(message: &Payloads, from_relay: bool) -> bool {
+    match message {
+        Payloads::TransferControl { from, .. } | Payloads::SetObserver { from, .. } => {
+            from_relay && from == RELAY_NAME
+        }
+        Payloads::PlayerJoined { .. } | Payloads::PlayerLeft { .. } => from_relay,
+        _ => !can_be_sealed(message),
+    }
+}
+
+// Channel the sealed payload travels on, same as it would in the clear
+fn get_sealed_delivery(message: &Payloads) -> (bool, u8) {
+    match message {
+        Payloads::Update { is_unreliable, .. } => (*is_unreliable, 0),
+        Payloads::DefinitionChunk { .. } => (false, 3),
+        _ => (false, 1),
+    }
+}
+
+// Relays route control and readiness themselves and cache definitions, so they get to see those
+fn get_visible_copy(message: &Payloads) -> Option<Box<Payloads>> {
+    match message {
+        Payloads::TransferControl { .. }
+        | Payloads::SetObserver { .. }
+        | Payloads::SetSelfObserver { .. }
+        | Payloads::DefinitionManifest { .. }
+        | Payloads::Ready => Some(Box::new(message.clone())),
+        _ => None,
     }
 }
 
 fn get_compression_level_for_message(msg: &Payloads) -> i32 {
     match msg {
-        Payloads::AircraftDefinition { .. } => 22,
+        Payloads::AircraftDefinition { .. } | Payloads::DefinitionChunk { .. } => 22,
         _ => 0,
     }
 }
 
-pub struct SenderReceiver {
+struct UdpTransport {
     socket: Socket,
     sender: Sender<Packet>,
     receiver: Receiver<SocketEvent>,
+    // IPv4 peers have to be addressed as IPv4-mapped IPv6 addresses
+    is_dual_stack: bool,
+}
+
+pub struct SenderReceiver {
+    udp: Option<UdpTransport>,
+    // TCP or WebSocket peers, next to UDP on relays or instead of it when UDP is blocked
+    stream: Option<StreamTransport>,
     compressor: Compressor<'static>,
-    decompressor: Decompressor<'static>,
+    decoder: PayloadDecoder,
+    // Shared by everyone in the session once the key exchange is done
+    session_key: Option<SessionKey>,
+    // Peers that have the key, payloads to them are sealed and they may no longer send in the clear
+    sealed_peers: HashSet<SocketAddr>,
+    // Session members don't take in the clear what should be sealed from anyone, relays have no key to expect it with
+    refuses_clear: bool,
+    // The hoster relaying the session, the only one whose own payloads are taken in the clear
+    relay: Option<SocketAddr>,
 }
 
 impl SenderReceiver {
     pub fn from_socket(socket: Socket) -> Self {
         let sender = socket.get_packet_sender();
         let receiver = socket.get_event_receiver();
+        let is_dual_stack = socket
+            .local_addr()
+            .map(|addr| addr.is_ipv6())
+            .unwrap_or(false);
+
+        Self::new(
+            Some(UdpTransport {
+                socket,
+                sender,
+                receiver,
+                is_dual_stack,
+            }),
+            None,
+        )
+    }
+
+    pub fn from_stream(stream: StreamTransport) -> Self {
+        Self::new(None, Some(stream))
+    }
 
+    fn new(udp: Option<UdpTransport>, stream: Option<StreamTransport>) -> Self {
         Self {
-            socket,
-            sender,
-            receiver,
+            udp,
+            stream,
             compressor: Compressor::new(0).unwrap(),
-            decompressor: Decompressor::new().unwrap(),
+            decoder: PayloadDecoder::new().unwrap(),
+            session_key: None,
+            sealed_peers: HashSet::new(),
+            refuses_clear: false,
+            relay: None,
         }
     }
 
+    // Accept TCP and WebSocket peers alongside UDP ones
+    pub fn add_stream(&mut self, stream: StreamTransport) {
+        self.stream = Some(stream);
+    }
+
+    pub fn is_stream_peer(&self, addr: &SocketAddr) -> bool {
+        self.stream
+            .as_ref()
+            .map(|stream| stream.handles(addr))
+            .unwrap_or(false)
+    }
+
+    pub fn set_session_key(&mut self, key: SessionKey) {
+        self.session_key = Some(key);
+    }
+
+    pub fn get_session_key(&self) -> Option<&SessionKey> {
+        self.session_key.as_ref()
+    }
+
+    pub fn set_sealed(&mut self, addr: SocketAddr, sealed: bool) {
+        if sealed {
+            self.sealed_peers.insert(addr);
+        } else {
+            self.sealed_peers.remove(&addr);
+        }
+    }
+
+    pub fn is_sealed(&self, addr: &SocketAddr) -> bool {
+        self.sealed_peers.contains(addr)
+    }
+
+    pub fn refuse_clear(&mut self) {
+        self.refuses_clear = true;
+    }
+
+    pub fn set_relay(&mut self, relay: SocketAddr) {
+        self.relay = Some(relay);
+    }
+
+    fn should_seal(&self, message: &Payloads, target: SocketAddr) -> bool {
+        self.session_key.is_some() && self.sealed_peers.contains(&target) && can_be_sealed(message)
+    }
+
     pub fn get_next_message(&mut self) -> Result<Message, Error> {
         // Receive packet
-        let packet = match self.receiver.try_recv()? {
+        let packet = match self.next_event()? {
             SocketEvent::Packet(packet) => packet,
-            SocketEvent::Timeout(addr) => return Ok(Message::ConnectionClosed(addr)),
-            SocketEvent::Metrics(addr, metrics) => return Ok(Message::Metrics(addr, metrics)),
+            SocketEvent::Timeout(addr) => {
+                return Ok(Message::ConnectionClosed(get_canonical_addr(addr)))
+            }
+            SocketEvent::Metrics(addr, metrics) => {
+                return Ok(Message::Metrics(get_canonical_addr(addr), metrics))
+            }
             _ => return Err(Error::NotProcessed),
         };
+        let addr = get_canonical_addr(packet.addr());
+
+        let payload = match self.decode_payload(packet.payload())? {
+            // Without a key it's passed on as is, relays forward it and peers still waiting on theirs ignore it
+            Payloads::Encrypted {
+                sender,
+                nonce,
+                data,
+                ..
+            } if self.session_key.is_some() => self.open(SealedData {
+                sender,
+                nonce,
+                data,
+            })?,
+            payload => {
+                if (self.refuses_clear || self.sealed_peers.contains(&addr))
+                    && !is_accepted_in_clear(&payload, self.relay == Some(addr))
+                {
+                    return Err(Error::CryptoError("Unencrypted payload".to_string()));
+                }
+                payload
+            }
+        };
+
+        Ok(Message::Payload(addr, payload))
+    }
+
+    fn next_event(&mut self) -> Result<SocketEvent, Error> {
+        if let Some(event) = self.stream.as_mut().and_then(|stream| stream.next_event()) {
+            return Ok(event);
+        }
+
+        match self.udp.as_ref() {
+            Some(udp) => Ok(udp.receiver.try_recv()?),
+            None => Err(TryRecvError::Empty.into()),
+        }
+    }
+
+    fn send_packet(&mut self, message: &Payloads, payload_bytes: Vec<u8>, target: SocketAddr) {
+        if let Some(stream) = self.stream.as_mut() {
+            if stream.handles(&target) {
+                stream.send(get_packet_for_message(message, payload_bytes, target));
+                return;
+            }
+        }
+
+        if let Some(udp) = self.udp.as_ref() {
+            let target = if udp.is_dual_stack {
+                get_mapped_addr(target)
+            } else {
+                target
+            };
+            udp.sender
+                .send(get_packet_for_message(message, payload_bytes, target))
+                .ok();
+        }
+    }
 
-        // Decode wrapper struct
-        let wrapper: PayloadWrapper = rmp_serde::from_slice(packet.payload())?;
+    fn decode_payload(&mut self, bytes: &[u8]) -> Result<Payloads, Error> {
+        self.decoder.decode(bytes)
+    }
 
-        // Decompress
-        let payload_bytes = self.decompressor.decompress(&wrapper.data, wrapper.size)?;
+    fn open(&mut self, sealed: SealedData) -> Result<Payloads, Error> {
+        let key = self.session_key.as_mut().ok_or(Error::NotProcessed)?;
+        let bytes = key.open(&sealed)?;
 
-        // Decode to struct
-        let payload = rmp_serde::from_slice(&payload_bytes)?;
-        Ok(Message::Payload(packet.addr(), payload))
+        match self.decode_payload(&bytes)? {
+            Payloads::Encrypted { .. } => {
+                Err(Error::CryptoError("Nested encrypted payload".to_string()))
+            }
+            payload => Ok(payload),
+        }
+    }
+
+    fn seal(&mut self, message: &Payloads) -> Result<Payloads, Error> {
+        let bytes = self.prepare_payload_bytes(message)?;
+
+        let key = self.session_key.as_mut().ok_or(Error::NotProcessed)?;
+        let sealed = key.seal(&bytes)?;
+        let (is_unreliable, channel) = get_sealed_delivery(message);
+
+        Ok(Payloads::Encrypted {
+            sender: sealed.sender,
+            nonce: sealed.nonce,
+            data: sealed.data,
+            is_unreliable,
+            channel,
+            visible: get_visible_copy(message),
+        })
     }
 
     pub fn poll(&mut self) {
-        self.socket.manual_poll(Instant::now());
+        if let Some(udp) = self.udp.as_mut() {
+            udp.socket.manual_poll(Instant::now());
+        }
+
+        if let Some(stream) = self.stream.as_mut() {
+            stream.poll();
+        }
     }
 
     fn prepare_payload_bytes(&mut self, message: &Payloads) -> Result<Vec<u8>, Error> {
@@ -199,11 +571,15 @@ impl SenderReceiver {
     }
 
     pub fn send_message(&mut self, message: Payloads, target: SocketAddr) -> Result<(), Error> {
+        let message = if self.should_seal(&message, target) {
+            self.seal(&message)?
+        } else {
+            message
+        };
+
         let payload_bytes = self.prepare_payload_bytes(&message)?;
         // Send payload
-        self.sender
-            .send(get_packet_for_message(&message, payload_bytes, target))
-            .ok();
+        self.send_packet(&message, payload_bytes, target);
 
         Ok(())
     }
@@ -213,16 +589,32 @@ impl SenderReceiver {
         message: Payloads,
         targets: Vec<SocketAddr>,
     ) -> Result<(), Error> {
-        let payload_bytes = self.prepare_payload_bytes(&message)?;
+        let (sealed_targets, targets): (Vec<SocketAddr>, Vec<SocketAddr>) = targets
+            .into_iter()
+            .partition(|addr| self.should_seal(&message, *addr));
+
+        // Everyone with the key gets the same ciphertext
+        if !sealed_targets.is_empty() {
+            let sealed = self.seal(&message)?;
+            self.send_bytes_to_multiple(&sealed, sealed_targets)?;
+        }
+
+        if !targets.is_empty() {
+            self.send_bytes_to_multiple(&message, targets)?;
+        }
+
+        Ok(())
+    }
+
+    fn send_bytes_to_multiple(
+        &mut self,
+        message: &Payloads,
+        targets: Vec<SocketAddr>,
+    ) -> Result<(), Error> {
+        let payload_bytes = self.prepare_payload_bytes(message)?;
 
         for addr in targets {
-            self.sender
-                .send(get_packet_for_message(
-                    &message,
-                    payload_bytes.clone(),
-                    addr,
-                ))
-                .ok();
+            self.send_packet(message, payload_bytes.clone(), addr);
         }
 
         Ok(())
@@ -0,0 +1,217 @@
+use laminar::Socket;
+use spin_sleep::sleep;
+use std::{
+    fmt::Display,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+use yourcontrols_types::Error;
+
+use crate::{
+    messages::{Message, Payloads, SenderReceiver},
+    util::{
+        bind_dual_stack, get_local_ip_address, get_probe_servers, get_socket_config,
+        is_actually_ipv4, LOOP_SLEEP_TIME_MS,
+    },
+};
+
+const PROBE_ATTEMPTS: u32 = 3;
+const PROBE_TIMEOUT_MS: u64 = 400;
+const PROBE_SOCKET_TIMEOUT_SECS: u64 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NatType {
+    // Not behind a NAT at all
+    Open,
+    // Same public address for every destination, and anyone can send to it
+    FullCone,
+    // Same public address for every destination, but only those we've sent to get through
+    RestrictedCone,
+    // A new public address per destination, nobody can predict where to punch
+    Symmetric,
+    // The server never answered
+    UdpBlocked,
+    // The second port never answered, so mappings couldn't be compared
+    Unknown,
+}
+
+impl Display for NatType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NatType::Open => write!(f, "Open"),
+            NatType::FullCone => write!(f, "Full Cone"),
+            NatType::RestrictedCone => write!(f, "Restricted Cone"),
+            NatType::Symmetric => write!(f, "Symmetric"),
+            NatType::UdpBlocked => write!(f, "UDP Blocked"),
+            NatType::Unknown => write!(f, "Unknown"),
+        }
+    }
+}
+
+impl NatType {
+    // Others can connect without us sending to them first
+    pub fn accepts_unsolicited(&self) -> bool {
+        matches!(self, NatType::Open | NatType::FullCone)
+    }
+
+    // The address the rendezvous server sees is the one peers can punch through to
+    pub fn can_hole_punch(&self) -> bool {
+        matches!(
+            self,
+            NatType::Open | NatType::FullCone | NatType::RestrictedCone
+        )
+    }
+}
+
+#[derive(Default)]
+struct ProbeResults {
+    // Where the server's main port saw us from
+    primary: Option<SocketAddr>,
+    // Where its second port saw us from
+    secondary: Option<SocketAddr>,
+    // The second port got an answer through before we ever sent to it
+    reached_from_other_port: bool,
+}
+
+fn classify(results: &ProbeResults, local_ip: Option<IpAddr>) -> NatType {
+    let primary = match results.primary {
+        Some(addr) => addr,
+        None => return NatType::UdpBlocked,
+    };
+
+    if Some(primary.ip()) == local_ip {
+        return NatType::Open;
+    }
+
+    match results.secondary {
+        Some(secondary) if secondary != primary => NatType::Symmetric,
+        Some(_) if results.reached_from_other_port => NatType::FullCone,
+        Some(_) => NatType::RestrictedCone,
+        None => NatType::Unknown,
+    }
+}
+
+// Where `from` saw us, asking `target` until `from` answers
+fn probe(
+    net: &mut SenderReceiver,
+    target: SocketAddr,
+    from: SocketAddr,
+    change_port: bool,
+) -> Option<SocketAddr> {
+    for _ in 0..PROBE_ATTEMPTS {
+        net.send_message(Payloads::NatProbe { change_port }, target)
+            .ok()?;
+
+        let deadline = Instant::now() + Duration::from_millis(PROBE_TIMEOUT_MS);
+
+        while Instant::now() < deadline {
+            net.poll();
+
+            loop {
+                match net.get_next_message() {
+                    Ok(Message::Payload(addr, Payloads::NatProbeResponse { observed }))
+                        if addr == from =>
+                    {
+                        return Some(observed)
+                    }
+                    Err(Error::ReadTimeout(_)) => break,
+                    _ => {}
+                }
+            }
+
+            sleep(Duration::from_millis(LOOP_SLEEP_TIME_MS));
+        }
+    }
+
+    None
+}
+
+// Blocks for a few seconds at most, run it off the main loop
+pub fn detect_nat_type(prefer_ipv6: bool) -> Result<NatType, Error> {
+    let (primary, secondary) = get_probe_servers(prefer_ipv6)?;
+
+    let socket = Socket::from_udp_socket(
+        bind_dual_stack(None)?,
+        get_socket_config(PROBE_SOCKET_TIMEOUT_SECS),
+    )?;
+    let mut net = SenderReceiver::from_socket(socket);
+
+    let mut results = ProbeResults {
+        primary: probe(&mut net, primary, primary, false),
+        ..Default::default()
+    };
+
+    if results.primary.is_some() {
+        // Before anything goes to the second port, otherwise a restricted NAT would let it through too
+        results.reached_from_other_port = probe(&mut net, primary, secondary, true).is_some();
+        results.secondary = probe(&mut net, secondary, secondary, false);
+    }
+
+    let local_ip = results
+        .primary
+        .and_then(|addr| get_local_ip_address(!is_actually_ipv4(addr)));
+
+    Ok(classify(&results, local_ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    fn local_ip() -> Option<IpAddr> {
+        Some("192.168.1.20".parse().unwrap())
+    }
+
+    #[test]
+    fn test_no_answer_is_blocked() {
+        let results = ProbeResults::default();
+        assert_eq!(classify(&results, local_ip()), NatType::UdpBlocked);
+    }
+
+    #[test]
+    fn test_seen_from_own_address_is_open() {
+        let results = ProbeResults {
+            primary: Some(addr("192.168.1.20:50000")),
+            secondary: Some(addr("192.168.1.20:50000")),
+            reached_from_other_port: true,
+        };
+        assert_eq!(classify(&results, local_ip()), NatType::Open);
+    }
+
+    #[test]
+    fn test_cone_types() {
+        let mut results = ProbeResults {
+            primary: Some(addr("203.0.113.7:40000")),
+            secondary: Some(addr("203.0.113.7:40000")),
+            reached_from_other_port: true,
+        };
+        assert_eq!(classify(&results, local_ip()), NatType::FullCone);
+
+        results.reached_from_other_port = false;
+        assert_eq!(classify(&results, local_ip()), NatType::RestrictedCone);
+    }
+
+    #[test]
+    fn test_new_mapping_per_destination_is_symmetric() {
+        let results = ProbeResults {
+            primary: Some(addr("203.0.113.7:40000")),
+            secondary: Some(addr("203.0.113.7:40001")),
+            reached_from_other_port: false,
+        };
+        assert_eq!(classify(&results, local_ip()), NatType::Symmetric);
+        assert!(!NatType::Symmetric.can_hole_punch());
+    }
+
+    #[test]
+    fn test_missing_second_answer_is_unknown() {
+        let results = ProbeResults {
+            primary: Some(addr("203.0.113.7:40000")),
+            ..Default::default()
+        };
+        assert_eq!(classify(&results, local_ip()), NatType::Unknown);
+    }
+}
@@ -0,0 +1,566 @@
+use igd::{search_gateway, PortMappingProtocol, SearchOptions};
+use log::info;
+use std::{
+    fmt::Display,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering::SeqCst},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use yourcontrols_types::Error;
+
+// NAT-PMP and PCP share the port
+const GATEWAY_PORT: u16 = 5351;
+const UPNP_LEASE_SECS: u32 = 86400;
+const REQUESTED_LIFETIME_SECS: u32 = 7200;
+// Shorter than the RFCs' schedule, hosting shouldn't hang on a router that isn't listening
+const RETRY_TIMEOUTS_MS: [u64; 2] = [250, 500];
+const RENEW_RETRY_SECS: u64 = 60;
+
+const NAT_PMP_VERSION: u8 = 0;
+const NAT_PMP_MAP_UDP: u8 = 1;
+const PCP_VERSION: u8 = 2;
+const PCP_MAP: u8 = 1;
+const PCP_RESPONSE_BIT: u8 = 0x80;
+const PROTOCOL_UDP: u8 = 17;
+const RESULT_SUCCESS: u16 = 0;
+const RESULT_UNSUPPORTED_VERSION: u16 = 1;
+
+const NAT_PMP_REQUEST_LEN: usize = 12;
+const NAT_PMP_RESPONSE_LEN: usize = 16;
+const PCP_REQUEST_LEN: usize = 60;
+const PCP_RESPONSE_LEN: usize = 60;
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PortMapMethod {
+    Upnp,
+    NatPmp,
+    Pcp,
+}
+
+impl Display for PortMapMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PortMapMethod::Upnp => write!(f, "UPnP"),
+            PortMapMethod::NatPmp => write!(f, "NAT-PMP"),
+            PortMapMethod::Pcp => write!(f, "PCP"),
+        }
+    }
+}
+
+enum RequestError {
+    // Nothing listening, no point trying the other protocol on the same gateway
+    NoResponse,
+    UnsupportedVersion,
+    Failed(String),
+}
+
+impl Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestError::NoResponse => write!(f, "no response"),
+            RequestError::UnsupportedVersion => write!(f, "unsupported version"),
+            RequestError::Failed(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+enum Mapper {
+    Upnp(igd::Gateway),
+    NatPmp(SocketAddr),
+    Pcp(SocketAddr, [u8; NONCE_LEN]),
+}
+
+pub struct PortMapping {
+    mapper: Mapper,
+    local_addr: SocketAddrV4,
+    external_port: u16,
+    lifetime: Duration,
+}
+
+impl PortMapping {
+    pub fn get_method(&self) -> PortMapMethod {
+        match self.mapper {
+            Mapper::Upnp(_) => PortMapMethod::Upnp,
+            Mapper::NatPmp(_) => PortMapMethod::NatPmp,
+            Mapper::Pcp(..) => PortMapMethod::Pcp,
+        }
+    }
+
+    pub fn get_external_port(&self) -> u16 {
+        self.external_port
+    }
+
+    pub fn get_lifetime(&self) -> Duration {
+        self.lifetime
+    }
+
+    // Asks for the same mapping again before the gateway lets it run out
+    pub fn renew(&mut self) -> Result<(), Error> {
+        let (external_port, lifetime) = match &self.mapper {
+            Mapper::Upnp(gateway) => {
+                add_upnp_port(gateway, self.local_addr)?;
+                (self.local_addr.port(), UPNP_LEASE_SECS)
+            }
+            Mapper::NatPmp(gateway) => request_nat_pmp(
+                *gateway,
+                self.local_addr,
+                self.external_port,
+                REQUESTED_LIFETIME_SECS,
+            )
+            .map_err(to_error)?,
+            Mapper::Pcp(gateway, nonce) => request_pcp(
+                *gateway,
+                self.local_addr,
+                nonce,
+                self.external_port,
+                REQUESTED_LIFETIME_SECS,
+            )
+            .map_err(to_error)?,
+        };
+
+        self.external_port = external_port;
+        self.lifetime = Duration::from_secs(lifetime as u64);
+
+        Ok(())
+    }
+
+    pub fn remove(self) -> Result<(), Error> {
+        match &self.mapper {
+            Mapper::Upnp(gateway) => gateway
+                .remove_port(PortMappingProtocol::UDP, self.external_port)
+                .map_err(to_error),
+            Mapper::NatPmp(gateway) => request_nat_pmp(*gateway, self.local_addr, 0, 0)
+                .map(|_| ())
+                .map_err(to_error),
+            Mapper::Pcp(gateway, nonce) => {
+                request_pcp(*gateway, self.local_addr, nonce, self.external_port, 0)
+                    .map(|_| ())
+                    .map_err(to_error)
+            }
+        }
+    }
+}
+
+fn to_error(e: impl Display) -> Error {
+    Error::PortMapError(e.to_string())
+}
+
+fn add_upnp_port(gateway: &igd::Gateway, local_addr: SocketAddrV4) -> Result<(), Error> {
+    gateway
+        .add_port(
+            PortMappingProtocol::UDP,
+            local_addr.port(),
+            local_addr,
+            UPNP_LEASE_SECS,
+            "YourControls",
+        )
+        .map_err(Error::AddPortError)
+}
+
+fn map_with_upnp(gateway: igd::Gateway, local_addr: SocketAddrV4) -> Result<PortMapping, Error> {
+    add_upnp_port(&gateway, local_addr)?;
+
+    Ok(PortMapping {
+        mapper: Mapper::Upnp(gateway),
+        local_addr,
+        external_port: local_addr.port(),
+        lifetime: Duration::from_secs(UPNP_LEASE_SECS as u64),
+    })
+}
+
+// No portable way to read the default route, routers almost always sit at .1 or .254 of the local /24
+fn get_gateway_guesses(local_ip: Ipv4Addr) -> Vec<Ipv4Addr> {
+    let [a, b, c, _] = local_ip.octets();
+
+    vec![Ipv4Addr::new(a, b, c, 1), Ipv4Addr::new(a, b, c, 254)]
+        .into_iter()
+        .filter(|ip| *ip != local_ip)
+        .collect()
+}
+
+// UPnP first, then PCP and NAT-PMP on the gateway UPnP found or the usual gateway addresses
+pub fn map_port(local_addr: SocketAddrV4) -> Result<PortMapping, Error> {
+    let mut gateways = Vec::new();
+
+    let upnp_error = match search_gateway(SearchOptions {
+        bind_addr: SocketAddr::new(IpAddr::V4(*local_addr.ip()), 0),
+        timeout: Some(Duration::from_secs(3)),
+        ..Default::default()
+    }) {
+        Ok(gateway) => {
+            info!("[NETWORK] Found gateway at {}", gateway.root_url);
+            gateways.push(*gateway.addr.ip());
+
+            match map_with_upnp(gateway, local_addr) {
+                Ok(mapping) => return Ok(mapping),
+                Err(e) => e,
+            }
+        }
+        Err(e) => Error::GatewayNotFound(e),
+    };
+
+    for guess in get_gateway_guesses(*local_addr.ip()) {
+        if !gateways.contains(&guess) {
+            gateways.push(guess);
+        }
+    }
+
+    let mut errors = vec![format!("UPnP: {}", upnp_error)];
+
+    for gateway in gateways {
+        match map_with_pcp_or_nat_pmp(SocketAddr::from((gateway, GATEWAY_PORT)), local_addr) {
+            Ok(mapping) => return Ok(mapping),
+            Err(e) => errors.push(format!("{}: {}", gateway, e)),
+        }
+    }
+
+    Err(Error::PortMapError(errors.join(", ")))
+}
+
+fn map_with_pcp_or_nat_pmp(
+    gateway: SocketAddr,
+    local_addr: SocketAddrV4,
+) -> Result<PortMapping, RequestError> {
+    let mut nonce = [0; NONCE_LEN];
+    getrandom::getrandom(&mut nonce).map_err(|e| RequestError::Failed(e.to_string()))?;
+
+    let requested_port = local_addr.port();
+
+    // A NAT-PMP only gateway answers PCP with an unsupported version right away
+    let (mapper, (external_port, lifetime)) = match request_pcp(
+        gateway,
+        local_addr,
+        &nonce,
+        requested_port,
+        REQUESTED_LIFETIME_SECS,
+    ) {
+        Ok(mapped) => (Mapper::Pcp(gateway, nonce), mapped),
+        Err(RequestError::NoResponse) => return Err(RequestError::NoResponse),
+        Err(_) => (
+            Mapper::NatPmp(gateway),
+            request_nat_pmp(gateway, local_addr, requested_port, REQUESTED_LIFETIME_SECS)?,
+        ),
+    };
+
+    Ok(PortMapping {
+        mapper,
+        local_addr,
+        external_port,
+        lifetime: Duration::from_secs(lifetime as u64),
+    })
+}
+
+// Sends until the gateway answers, only taking answers from the gateway itself
+fn exchange(
+    gateway: SocketAddr,
+    local_ip: Ipv4Addr,
+    request: &[u8],
+    response: &mut [u8],
+) -> Result<usize, RequestError> {
+    let failed = |e: std::io::Error| RequestError::Failed(e.to_string());
+    let socket = UdpSocket::bind((local_ip, 0)).map_err(failed)?;
+
+    for timeout in RETRY_TIMEOUTS_MS {
+        socket.send_to(request, gateway).map_err(failed)?;
+
+        let deadline = Instant::now() + Duration::from_millis(timeout);
+
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            if remaining.is_zero() {
+                break;
+            }
+
+            socket.set_read_timeout(Some(remaining)).map_err(failed)?;
+
+            match socket.recv_from(response) {
+                Ok((len, from)) if from == gateway => return Ok(len),
+                Ok(_) => continue,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => break,
+                // Windows reports the ICMP port unreachable from an earlier send here
+                Err(e) if e.kind() == ErrorKind::ConnectionReset => break,
+                Err(e) => return Err(failed(e)),
+            }
+        }
+    }
+
+    Err(RequestError::NoResponse)
+}
+
+fn get_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn get_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+fn check_result(result: u16) -> Result<(), RequestError> {
+    match result {
+        RESULT_SUCCESS => Ok(()),
+        RESULT_UNSUPPORTED_VERSION => Err(RequestError::UnsupportedVersion),
+        code => Err(RequestError::Failed(format!(
+            "gateway refused with code {}",
+            code
+        ))),
+    }
+}
+
+// RFC 6886, a lifetime of 0 removes the mapping. Returns the external port and lifetime granted
+fn request_nat_pmp(
+    gateway: SocketAddr,
+    local_addr: SocketAddrV4,
+    external_port: u16,
+    lifetime: u32,
+) -> Result<(u16, u32), RequestError> {
+    let mut request = Vec::with_capacity(NAT_PMP_REQUEST_LEN);
+    request.extend_from_slice(&[NAT_PMP_VERSION, NAT_PMP_MAP_UDP, 0, 0]);
+    request.extend_from_slice(&local_addr.port().to_be_bytes());
+    request.extend_from_slice(&external_port.to_be_bytes());
+    request.extend_from_slice(&lifetime.to_be_bytes());
+
+    let mut response = [0; NAT_PMP_RESPONSE_LEN];
+    let len = exchange(gateway, *local_addr.ip(), &request, &mut response)?;
+
+    // Errors can come back shorter than a full mapping response
+    if len < 4 || response[1] != NAT_PMP_MAP_UDP | PCP_RESPONSE_BIT {
+        return Err(RequestError::Failed(
+            "malformed NAT-PMP response".to_string(),
+        ));
+    }
+
+    check_result(get_u16(&response, 2))?;
+
+    if len < NAT_PMP_RESPONSE_LEN || get_u16(&response, 8) != local_addr.port() {
+        return Err(RequestError::Failed(
+            "malformed NAT-PMP response".to_string(),
+        ));
+    }
+
+    Ok((get_u16(&response, 10), get_u32(&response, 12)))
+}
+
+// RFC 6887 MAP, the nonce ties renewals and removal to the same mapping
+fn request_pcp(
+    gateway: SocketAddr,
+    local_addr: SocketAddrV4,
+    nonce: &[u8; NONCE_LEN],
+    external_port: u16,
+    lifetime: u32,
+) -> Result<(u16, u32), RequestError> {
+    let mut request = Vec::with_capacity(PCP_REQUEST_LEN);
+    request.extend_from_slice(&[PCP_VERSION, PCP_MAP, 0, 0]);
+    request.extend_from_slice(&lifetime.to_be_bytes());
+    request.extend_from_slice(&local_addr.ip().to_ipv6_mapped().octets());
+    request.extend_from_slice(nonce);
+    request.extend_from_slice(&[PROTOCOL_UDP, 0, 0, 0]);
+    request.extend_from_slice(&local_addr.port().to_be_bytes());
+    request.extend_from_slice(&external_port.to_be_bytes());
+    // No preference for the external address
+    request.extend_from_slice(&Ipv4Addr::UNSPECIFIED.to_ipv6_mapped().octets());
+
+    let mut response = [0; PCP_RESPONSE_LEN];
+    let len = exchange(gateway, *local_addr.ip(), &request, &mut response)?;
+
+    // A NAT-PMP gateway answers in its own format
+    if len >= 4 && response[0] == NAT_PMP_VERSION {
+        return Err(RequestError::UnsupportedVersion);
+    }
+
+    if len < 4 || response[0] != PCP_VERSION || response[1] != PCP_MAP | PCP_RESPONSE_BIT {
+        return Err(RequestError::Failed("malformed PCP response".to_string()));
+    }
+
+    check_result(response[3] as u16)?;
+
+    if len < PCP_RESPONSE_LEN || response[24..36] != nonce[..] {
+        return Err(RequestError::Failed("malformed PCP response".to_string()));
+    }
+
+    Ok((get_u16(&response, 42), get_u32(&response, 4)))
+}
+
+// Renews the mapping while hosting and takes it down once the server stops
+pub fn keep_mapped(mut mapping: PortMapping, should_stop: Arc<AtomicBool>) {
+    thread::spawn(move || {
+        let mut renew_at = Instant::now() + mapping.get_lifetime() / 2;
+
+        while !should_stop.load(SeqCst) {
+            if Instant::now() >= renew_at {
+                let next = match mapping.renew() {
+                    Ok(()) => mapping.get_lifetime() / 2,
+                    Err(e) => {
+                        info!("[NETWORK] Could not renew port mapping: {}", e);
+                        Duration::from_secs(RENEW_RETRY_SECS)
+                    }
+                };
+
+                renew_at = Instant::now() + next;
+            }
+
+            thread::sleep(Duration::from_secs(1));
+        }
+
+        match mapping.remove() {
+            Ok(()) => info!("[NETWORK] Removed port mapping"),
+            Err(e) => info!("[NETWORK] Could not remove port mapping: {}", e),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::JoinHandle;
+
+    const LOCAL_ADDR: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 47000);
+
+    // Stands in for the router, answering each request it gets with the given responder
+    fn spawn_gateway(
+        requests: usize,
+        respond: impl Fn(&[u8]) -> Vec<u8> + Send + 'static,
+    ) -> (SocketAddr, JoinHandle<Vec<Vec<u8>>>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        let handle = thread::spawn(move || {
+            let mut received = Vec::new();
+            let mut buf = [0; 1100];
+
+            for _ in 0..requests {
+                let (len, from) = socket.recv_from(&mut buf).unwrap();
+                socket.send_to(&respond(&buf[..len]), from).unwrap();
+                received.push(buf[..len].to_vec());
+            }
+
+            received
+        });
+
+        (addr, handle)
+    }
+
+    fn nat_pmp_response(request: &[u8], result: u16, external_port: u16) -> Vec<u8> {
+        let lifetime = get_u32(request, 8);
+
+        let mut response = vec![NAT_PMP_VERSION, request[1] | PCP_RESPONSE_BIT];
+        response.extend_from_slice(&result.to_be_bytes());
+        response.extend_from_slice(&1234u32.to_be_bytes());
+        response.extend_from_slice(&request[4..6]);
+        response.extend_from_slice(&external_port.to_be_bytes());
+        response.extend_from_slice(&lifetime.to_be_bytes());
+        response
+    }
+
+    fn pcp_response(request: &[u8], external_port: u16) -> Vec<u8> {
+        let mut response = vec![PCP_VERSION, request[1] | PCP_RESPONSE_BIT, 0, 0];
+        response.extend_from_slice(&request[4..8]);
+        response.extend_from_slice(&[0; 16]);
+        // Nonce, protocol and internal port as asked
+        response.extend_from_slice(&request[24..42]);
+        response.extend_from_slice(&external_port.to_be_bytes());
+        response.extend_from_slice(&Ipv4Addr::new(203, 0, 113, 7).to_ipv6_mapped().octets());
+        response
+    }
+
+    // What a NAT-PMP only gateway says to anything but version 0
+    fn unsupported_version_response() -> Vec<u8> {
+        let mut response = vec![NAT_PMP_VERSION, PCP_MAP | PCP_RESPONSE_BIT];
+        response.extend_from_slice(&RESULT_UNSUPPORTED_VERSION.to_be_bytes());
+        response.extend_from_slice(&[0; 4]);
+        response
+    }
+
+    #[test]
+    fn test_pcp_map_renew_and_remove() {
+        let (gateway, handle) = spawn_gateway(3, |request| pcp_response(request, 50000));
+
+        let mut mapping = map_with_pcp_or_nat_pmp(gateway, LOCAL_ADDR).ok().unwrap();
+        assert_eq!(mapping.get_method(), PortMapMethod::Pcp);
+        assert_eq!(mapping.get_external_port(), 50000);
+        assert_eq!(
+            mapping.get_lifetime().as_secs(),
+            REQUESTED_LIFETIME_SECS as u64
+        );
+
+        mapping.renew().unwrap();
+        mapping.remove().unwrap();
+
+        let requests = handle.join().unwrap();
+        assert_eq!(requests[0].len(), PCP_REQUEST_LEN);
+        assert_eq!(requests[0][36], PROTOCOL_UDP);
+        assert_eq!(get_u16(&requests[0], 40), LOCAL_ADDR.port());
+        // Renewal asks for the port it got with the same nonce
+        assert_eq!(requests[1][24..36], requests[0][24..36]);
+        assert_eq!(get_u16(&requests[1], 42), 50000);
+        // Removal is a zero lifetime
+        assert_eq!(get_u32(&requests[2], 4), 0);
+    }
+
+    #[test]
+    fn test_falls_back_to_nat_pmp() {
+        let (gateway, handle) = spawn_gateway(3, |request| match request[0] {
+            PCP_VERSION => unsupported_version_response(),
+            _ => nat_pmp_response(request, RESULT_SUCCESS, 40000),
+        });
+
+        let mapping = map_with_pcp_or_nat_pmp(gateway, LOCAL_ADDR).ok().unwrap();
+        assert_eq!(mapping.get_method(), PortMapMethod::NatPmp);
+        assert_eq!(mapping.get_external_port(), 40000);
+
+        mapping.remove().unwrap();
+
+        let requests = handle.join().unwrap();
+        assert_eq!(requests[1].len(), NAT_PMP_REQUEST_LEN);
+        assert_eq!(get_u16(&requests[1], 4), LOCAL_ADDR.port());
+        assert_eq!(get_u32(&requests[2], 8), 0);
+    }
+
+    #[test]
+    fn test_refused_mapping_fails() {
+        let (gateway, handle) = spawn_gateway(2, |request| match request[0] {
+            PCP_VERSION => unsupported_version_response(),
+            // Not authorized
+            _ => nat_pmp_response(request, 2, 0),
+        });
+
+        assert!(matches!(
+            map_with_pcp_or_nat_pmp(gateway, LOCAL_ADDR),
+            Err(RequestError::Failed(_))
+        ));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_silent_gateway_gives_up() {
+        // Bound but never answering
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let gateway = socket.local_addr().unwrap();
+
+        let started = Instant::now();
+        assert!(matches!(
+            map_with_pcp_or_nat_pmp(gateway, LOCAL_ADDR),
+            Err(RequestError::NoResponse)
+        ));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_gateway_guesses() {
+        assert_eq!(
+            get_gateway_guesses(Ipv4Addr::new(192, 168, 1, 20)),
+            vec![
+                Ipv4Addr::new(192, 168, 1, 1),
+                Ipv4Addr::new(192, 168, 1, 254)
+            ]
+        );
+        assert_eq!(
+            get_gateway_guesses(Ipv4Addr::new(10, 0, 0, 1)),
+            vec![Ipv4Addr::new(10, 0, 0, 254)]
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+// One round a second, every round goes to the reported endpoints again
+pub const PUNCH_ROUNDS: u8 = 8;
+const ROUND_INTERVAL: Duration = Duration::from_secs(1);
+// Symmetric NATs usually hand out the next few ports for each new destination
+const PREDICTION_RANGE: u16 = 16;
+const PREDICTED_PER_ROUND: u16 = 4;
+// Once prediction is done, random ports in case the NAT doesn't allocate in order. All from our one socket,
+// so this only finds a peer whose NAT is random when ours keeps the port the rendezvous server saw
+const SPRAYED_PER_ROUND: usize = 24;
+// Predicted and random targets over the whole attempt, on top of the reported endpoints
+const MAX_EXTRA_TARGETS: usize = 256;
+const LOWEST_SPRAYED_PORT: u16 = 1024;
+
+// Only the address the rendezvous server saw is mapped by a NAT, guessing ports on a LAN address is pointless
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified())
+        }
+        // Nobody translates IPv6, a firewall only lets the exact endpoint through
+        IpAddr::V6(_) => false,
+    }
+}
+
+// Handshakes to one peer, to the endpoints the rendezvous server gave us and then to ports its NAT might use instead
+pub struct PunchSession {
+    candidates: Vec<SocketAddr>,
+    predict: bool,
+    timer: Option<Instant>,
+    round: u8,
+    extra_sent: usize,
+}
+
+impl PunchSession {
+    pub fn new(candidates: Vec<SocketAddr>) -> Self {
+        Self {
+            candidates,
+            predict: true,
+            timer: None,
+            round: 0,
+            extra_sent: 0,
+        }
+    }
+
+    // For an address the user typed in, only that endpoint makes sense
+    pub fn exact(candidates: Vec<SocketAddr>) -> Self {
+        Self {
+            predict: false,
+            ..Self::new(candidates)
+        }
+    }
+
+    pub fn get_round(&self) -> u8 {
+        self.round
+    }
+
+    // Every round went out and the last one had its second to get an answer
+    pub fn is_spent(&self, now: Instant) -> bool {
+        self.round >= PUNCH_ROUNDS
+            && !matches!(self.timer, Some(timer) if now.duration_since(timer) < ROUND_INTERVAL)
+    }
+
+    // The peer answered from somewhere this session was aiming at
+    pub fn covers(&self, addr: &SocketAddr) -> bool {
+        self.candidates
+            .iter()
+            .any(|candidate| candidate.ip() == addr.ip())
+    }
+
+    // Where this round's handshakes go, empty until the next round is due
+    pub fn next_round(
+        &mut self,
+        now: Instant,
+        mut random_port: impl FnMut() -> u16,
+    ) -> Vec<SocketAddr> {
+        if self.round >= PUNCH_ROUNDS {
+            return Vec::new();
+        }
+
+        if let Some(timer) = self.timer {
+            if now.duration_since(timer) < ROUND_INTERVAL {
+                return Vec::new();
+            }
+        }
+
+        self.timer = Some(now);
+
+        let mut targets = self.candidates.clone();
+        let round = self.round as u16;
+        self.round += 1;
+
+        if !self.predict || round == 0 {
+            return targets;
+        }
+
+        let public: Vec<SocketAddr> = self
+            .candidates
+            .iter()
+            .filter(|candidate| is_public(candidate.ip()))
+            .copied()
+            .collect();
+
+        let mut extra = Vec::new();
+
+        // Nearest ports first, a few more each round
+        let first_offset = (round - 1) * PREDICTED_PER_ROUND + 1;
+        if first_offset <= PREDICTION_RANGE {
+            for offset in first_offset..first_offset + PREDICTED_PER_ROUND {
+                for candidate in &public {
+                    if let Some(port) = candidate.port().checked_add(offset) {
+                        extra.push(SocketAddr::new(candidate.ip(), port));
+                    }
+                }
+            }
+        } else {
+            for candidate in &public {
+                for _ in 0..SPRAYED_PER_ROUND {
+                    let port = random_port().max(LOWEST_SPRAYED_PORT);
+                    extra.push(SocketAddr::new(candidate.ip(), port));
+                }
+            }
+        }
+
+        extra.truncate(MAX_EXTRA_TARGETS - self.extra_sent);
+        self.extra_sent += extra.len();
+
+        for addr in extra {
+            if !targets.contains(&addr) {
+                targets.push(addr);
+            }
+        }
+
+        targets
+    }
+}
+
+pub fn get_random_port() -> u16 {
+    let mut bytes = [0; 2];
+    getrandom::getrandom(&mut bytes).ok();
+    u16::from_be_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    fn run_all_rounds(session: &mut PunchSession, start: Instant) -> Vec<Vec<SocketAddr>> {
+        let mut port = 40000;
+
+        (0..PUNCH_ROUNDS as u32 + 2)
+            .map(|round| {
+                session.next_round(start + ROUND_INTERVAL * round, || {
+                    port += 7;
+                    port
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_first_round_is_exact() {
+        let mut session =
+            PunchSession::new(vec![addr("203.0.113.7:50000"), addr("192.168.1.20:50000")]);
+        let start = Instant::now();
+
+        assert_eq!(
+            session.next_round(start, get_random_port),
+            vec![addr("203.0.113.7:50000"), addr("192.168.1.20:50000")]
+        );
+        // Not due yet
+        assert!(session
+            .next_round(start + Duration::from_millis(500), get_random_port)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_predicts_next_ports_of_public_endpoint() {
+        let mut session =
+            PunchSession::new(vec![addr("203.0.113.7:50000"), addr("192.168.1.20:50000")]);
+        let rounds = run_all_rounds(&mut session, Instant::now());
+
+        assert_eq!(
+            rounds[1],
+            vec![
+                addr("203.0.113.7:50000"),
+                addr("192.168.1.20:50000"),
+                addr("203.0.113.7:50001"),
+                addr("203.0.113.7:50002"),
+                addr("203.0.113.7:50003"),
+                addr("203.0.113.7:50004"),
+            ]
+        );
+
+        let predicted: Vec<u16> = rounds[1..5]
+            .iter()
+            .flatten()
+            .filter(|target| target.ip() == addr("203.0.113.7:0").ip())
+            .map(|target| target.port())
+            .filter(|port| *port != 50000)
+            .collect();
+        assert_eq!(
+            predicted,
+            (50001..=50000 + PREDICTION_RANGE).collect::<Vec<_>>()
+        );
+
+        // Never guesses on the LAN address
+        assert!(rounds
+            .iter()
+            .flatten()
+            .filter(|target| target.ip() == addr("192.168.1.20:0").ip())
+            .all(|target| target.port() == 50000));
+    }
+
+    #[test]
+    fn test_spraying_stays_in_budget() {
+        let mut session =
+            PunchSession::new(vec![addr("203.0.113.7:50000"), addr("198.51.100.3:60000")]);
+        let start = Instant::now();
+        let rounds = run_all_rounds(&mut session, start);
+
+        let extra = rounds
+            .iter()
+            .map(|round| round.len().saturating_sub(2))
+            .sum::<usize>();
+        assert!(extra <= MAX_EXTRA_TARGETS);
+        // Sprayed after prediction ran out
+        assert!(rounds[5].len() > 2);
+        // Nothing once the rounds are spent, which is a second after the last one
+        assert!(rounds[PUNCH_ROUNDS as usize].is_empty());
+        let last_round = start + ROUND_INTERVAL * (PUNCH_ROUNDS as u32 - 1);
+        assert!(!session.is_spent(last_round));
+        assert!(session.is_spent(last_round + ROUND_INTERVAL));
+    }
+
+    #[test]
+    fn test_exact_session_never_guesses() {
+        let mut session = PunchSession::exact(vec![addr("203.0.113.7:50000")]);
+        let rounds = run_all_rounds(&mut session, Instant::now());
+
+        assert!(rounds[..PUNCH_ROUNDS as usize]
+            .iter()
+            .all(|round| *round == vec![addr("203.0.113.7:50000")]));
+        assert!(session.covers(&addr("203.0.113.7:50123")));
+        assert!(!session.covers(&addr("198.51.100.3:50000")));
+    }
+}
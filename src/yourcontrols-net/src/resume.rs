@@ -0,0 +1,124 @@
+use std::time::{Duration, Instant};
+use yourcontrols_types::Error;
+
+use crate::messages::Payloads;
+
+// How long a dropped client's slot is held for it to come back to
+pub const RESUME_GRACE_SECS: u64 = 30;
+
+const TOKEN_LEN: usize = 16;
+const FIRST_RETRY_DELAY_MS: u64 = 1000;
+const MAX_RETRY_DELAY_MS: u64 = 8000;
+
+pub fn generate_token() -> Result<String, Error> {
+    let mut bytes = [0; TOKEN_LEN];
+    getrandom::getrandom(&mut bytes).map_err(|e| Error::CryptoError(e.to_string()))?;
+
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+// Doesn't stop at the first differing byte
+pub fn tokens_match(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+// Membership and control changes a client misses while away, played back to it when it resumes
+pub fn should_replay(payload: &Payloads) -> bool {
+    matches!(
+        payload,
+        Payloads::PlayerJoined { .. }
+            | Payloads::PlayerLeft { .. }
+            | Payloads::TransferControl { .. }
+            | Payloads::SetObserver { .. }
+    )
+}
+
+fn get_retry_delay(attempts: u32) -> Duration {
+    let delay = FIRST_RETRY_DELAY_MS.saturating_mul(1 << attempts.min(16));
+    Duration::from_millis(delay.min(MAX_RETRY_DELAY_MS))
+}
+
+// Client side, when to try getting back into the session after a drop
+pub struct Reconnect {
+    started: Instant,
+    next_attempt: Instant,
+    attempts: u32,
+}
+
+impl Reconnect {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            started: now,
+            next_attempt: now,
+            attempts: 0,
+        }
+    }
+
+    pub fn is_due(&self, now: Instant) -> bool {
+        now >= self.next_attempt
+    }
+
+    pub fn on_attempt(&mut self, now: Instant) {
+        self.next_attempt = now + get_retry_delay(self.attempts);
+        self.attempts += 1;
+    }
+
+    pub fn get_attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    // The server has given the slot away by now
+    pub fn has_expired(&self, now: Instant) -> bool {
+        now.duration_since(self.started) >= Duration::from_secs(RESUME_GRACE_SECS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay_backs_off() {
+        let delays: Vec<u64> = (0..6)
+            .map(|attempts| get_retry_delay(attempts).as_millis() as u64)
+            .collect();
+        assert_eq!(delays, vec![1000, 2000, 4000, 8000, 8000, 8000]);
+        assert_eq!(get_retry_delay(u32::MAX).as_millis() as u64, 8000);
+    }
+
+    #[test]
+    fn test_reconnect_schedule() {
+        let start = Instant::now();
+        let mut reconnect = Reconnect::new(start);
+
+        // First attempt goes out right away
+        assert!(reconnect.is_due(start));
+        reconnect.on_attempt(start);
+        assert!(!reconnect.is_due(start + Duration::from_millis(999)));
+        assert!(reconnect.is_due(start + Duration::from_millis(1000)));
+
+        let second = start + Duration::from_millis(1000);
+        reconnect.on_attempt(second);
+        assert!(!reconnect.is_due(second + Duration::from_millis(1999)));
+        assert!(reconnect.is_due(second + Duration::from_millis(2000)));
+        assert_eq!(reconnect.get_attempts(), 2);
+
+        assert!(!reconnect.has_expired(start + Duration::from_secs(RESUME_GRACE_SECS - 1)));
+        assert!(reconnect.has_expired(start + Duration::from_secs(RESUME_GRACE_SECS)));
+    }
+
+    #[test]
+    fn test_tokens() {
+        let token = generate_token().unwrap();
+        assert_eq!(token.len(), TOKEN_LEN * 2);
+        assert_ne!(token, generate_token().unwrap());
+
+        assert!(tokens_match(&token, &token.clone()));
+        assert!(!tokens_match(&token, &token[1..]));
+        assert!(!tokens_match(&token, &generate_token().unwrap()));
+    }
+}
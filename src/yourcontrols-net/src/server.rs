@@ -1,12 +1,11 @@
 use crossbeam_channel::unbounded;
-use igd::{PortMappingProtocol, SearchOptions, search_gateway};
 use laminar::{Metrics, Socket};
 use log::info;
 use spin_sleep::sleep;
 use std::{
     collections::HashMap,
     mem::drop,
-    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+    net::{IpAddr, SocketAddr, SocketAddrV4},
     sync::{
         atomic::{AtomicBool, AtomicU16, Ordering::SeqCst},
         Arc, Mutex,
@@ -16,12 +15,18 @@ use std::{
 };
 
 use crate::{
+    clock::PeerClock,
+    crypto::{answer_key_request, generate_secret, SessionKey},
+    discovery::{advertise, LanSession},
     get_socket_duplex,
-    messages::{Message, Payloads, SenderReceiver},
+    messages::{is_accepted_in_clear, Message, Payloads, SenderReceiver},
+    portmap::{keep_mapped, map_port, PortMapping},
+    punch::{get_random_port, PunchSession},
+    resume::{generate_token, should_replay, tokens_match, RESUME_GRACE_SECS},
     util::{
         ClientReceiver, ClientSender, Event, ReceiveMessage, ServerReceiver, ServerSender, TransferClient,
-        get_bind_address, get_local_endpoints_with_port, get_local_ip_address, get_rendezvous_server, get_socket_config,
-        HEARTBEAT_INTERVAL_MANUAL_SECS, LOOP_SLEEP_TIME_MS, MAX_PUNCH_RETRIES,
+        bind_dual_stack, get_join_code, get_local_endpoints_with_port, get_local_ip_address, get_rendezvous_server, get_seconds, get_socket_config,
+        HEARTBEAT_INTERVAL_MANUAL_SECS, LOOP_SLEEP_TIME_MS,
     },
 };
 
@@ -30,16 +35,64 @@ use yourcontrols_types::Error;
 struct Client {
     addr: SocketAddr,
     is_observer: bool,
+    // Offset of the client's clock, echoed so the client can send its update times in ours
+    clock: PeerClock,
+    resume_token: String,
+    // Whatever has to be sealed, kept back until the client has the key
+    held: Vec<Payloads>,
+}
+
+impl Client {
+    // Unreliable updates would be stale by then, the next one replaces them
+    fn hold(&mut self, payload: &Payloads) {
+        if !matches!(
+            payload,
+            Payloads::Update {
+                is_unreliable: true,
+                ..
+            }
+        ) {
+            self.held.push(payload.clone());
+        }
+    }
+}
+
+// Slot of a client that dropped out, kept for it to resume
+struct HeldClient {
+    client: Client,
+    dropped_at: Instant,
+    missed: Vec<Payloads>,
+}
+
+// Empty if no token could be made, the client then can't resume
+fn get_resume_token() -> String {
+    generate_token().unwrap_or_else(|e| {
+        info!("[NETWORK] Could not generate resume token: {}", e);
+        String::new()
+    })
+}
+
+fn get_token_payloads(token: &str) -> Vec<Payloads> {
+    if token.is_empty() {
+        return Vec::new();
+    }
+
+    vec![Payloads::ResumeToken {
+        token: token.to_string(),
+    }]
 }
 
 struct TransferStruct {
     session_id: String,
+    // Only members know it, the session key is handed to whoever proves they do
+    secret: String,
     clients: HashMap<String, Client>,
+    held_clients: HashMap<String, HeldClient>,
     // Reading/writing to UDP stream
     net: SenderReceiver,
     // Holepunching
     rendezvous_server: Option<SocketAddr>,
-    clients_to_holepunch: Vec<HolePunchSession>,
+    clients_to_holepunch: Vec<PunchSession>,
     // Sending/writing to app
     server_tx: ServerSender,
     client_rx: ClientReceiver,
@@ -58,17 +111,29 @@ struct TransferStruct {
 impl TransferStruct {
     fn send_to_all(&mut self, except: Option<&SocketAddr>, payload: Payloads) {
         let mut to_send = Vec::new();
+        let needs_key = !is_accepted_in_clear(&payload, false);
 
-        for (_, client) in self.clients.iter() {
+        for client in self.clients.values_mut() {
             if let Some(except) = except {
                 if client.addr == *except {
                     continue;
                 }
             }
 
+            if needs_key && !self.net.is_sealed(&client.addr) {
+                client.hold(&payload);
+                continue;
+            }
+
             to_send.push(client.addr);
         }
 
+        if should_replay(&payload) {
+            for held in self.held_clients.values_mut() {
+                held.missed.push(payload.clone());
+            }
+        }
+
         self.net.send_message_to_multiple(payload, to_send).ok();
     }
 
@@ -78,69 +143,80 @@ impl TransferStruct {
         }
 
         let session_id = self.session_id.clone();
+        let now = Instant::now();
         let mut to_send = Vec::new();
 
+        // Both sides punch at once, whoever gets through first wins. A peer that runs out asks for forwarding
         self.clients_to_holepunch.retain_mut(|session| {
-            // Send a message every second
-            if let Some(timer) = session.timer.as_ref() {
-                if timer.elapsed().as_secs() < 1 {
-                    return true;
-                }
-            }
+            let targets = session.next_round(now, get_random_port);
 
-            for addr in &session.addrs {
-                to_send.push((
-                    Payloads::Handshake {
-                        session_id: session_id.clone(),
-                    },
-                    *addr,
-                ));
+            if !targets.is_empty() {
                 info!(
-                    "[NETWORK] Sent handshake packet to port {}. Retry #{}",
-                    addr.port(),
-                    session.retries
+                    "[NETWORK] Sent handshake packets to {} endpoints. Round #{}",
+                    targets.len(),
+                    session.get_round()
                 );
             }
 
-            // Reset second timer
-            session.retries += 1;
-            session.timer = Some(Instant::now());
+            to_send.extend(targets);
 
-            // Over retry limit, stop connection
-            if session.retries == MAX_PUNCH_RETRIES {
-                return false;
-            }
-
-            true
+            !session.is_spent(now)
         });
 
-        for (payload, addr) in to_send {
-            self.net.send_message(payload, addr).ok();
+        for addr in to_send {
+            self.net
+                .send_message(
+                    Payloads::Handshake {
+                        session_id: session_id.clone(),
+                    },
+                    addr,
+                )
+                .ok();
         }
     }
 
     fn handle_message(&mut self, addr: SocketAddr, payload: Payloads) {
         let mut should_relay = true;
 
+        let sender = self.clients.values_mut().find(|client| client.addr == addr);
+
+        // Update times already come in our time base, the clients convert them before sending
+        if let (Payloads::Heartbeat { time, echo }, Some(sender)) = (&payload, sender) {
+            sender.clock.on_heartbeat(*time, *echo, get_seconds());
+            return;
+        }
+
         match &payload {
             // Unused for server
             Payloads::InvalidName { .. }
-            | Payloads::AttemptHosterConnection { .. }
             | Payloads::InvalidVersion { .. }
             | Payloads::PlayerJoined { .. }
             | Payloads::PlayerLeft { .. }
             | Payloads::SetObserver { .. }
             | Payloads::RequestHosting { .. }
             | Payloads::AircraftDefinition { .. }
+            | Payloads::DefinitionManifest { .. }
+            | Payloads::DefinitionChunk { .. }
             | Payloads::ConnectionDenied { .. }
-            | Payloads::Heartbeat
+            | Payloads::Heartbeat { .. }
             | Payloads::SetHost
+            | Payloads::SetPause { .. }
+            | Payloads::SetWeather { .. }
             | Payloads::RendezvousHandshake { .. }
-            | Payloads::PeerEstablished { .. } => return, // No client should be able to send this
+            | Payloads::PeerEstablished { .. }
+            | Payloads::ResumeToken { .. }
+            | Payloads::Resumed
+            | Payloads::NatProbe { .. }
+            | Payloads::NatProbeResponse { .. }
+            | Payloads::RequestHosterFallback { .. }
+            | Payloads::Encrypted { .. } => return, // No client should be able to send this
             // No processing needed
             Payloads::Update { .. } => {}
             Payloads::Ready => {}
-            Payloads::SetSelfObserver { .. } => {
+            // Only we have the definitions to answer with
+            Payloads::SetSelfObserver { .. }
+            | Payloads::RequestPause { .. }
+            | Payloads::RequestDefinitionChunks { .. } => {
                 should_relay = false;
             }
             // Used
@@ -169,6 +245,8 @@ impl TransferStruct {
                     }
                     invalid_name = true;
                 }
+                // Still held for someone who dropped out
+                invalid_name |= self.held_clients.contains_key(name);
 
                 if invalid_name {
                     self.net.send_message(Payloads::InvalidName {}, addr).ok();
@@ -201,12 +279,16 @@ impl TransferStruct {
                         addr,
                     )
                     .ok();
-                // Add client
+                // Add client, its token goes out sealed once it has the key
+                let resume_token = get_resume_token();
                 self.clients.insert(
                     name.clone(),
                     Client {
                         addr,
                         is_observer: false,
+                        clock: PeerClock::new(),
+                        held: get_token_payloads(&resume_token),
+                        resume_token,
                     },
                 );
 
@@ -231,6 +313,16 @@ impl TransferStruct {
                 self.in_control.clone_from(to);
             }
 
+            Payloads::KeyExchange { peer, data } => {
+                self.answer_key_exchange(addr, peer, data);
+                return;
+            }
+
+            Payloads::Resume { name, token } => {
+                self.resume_client(addr, name, token);
+                return;
+            }
+
             Payloads::Handshake { session_id, .. } => {
                 info!(
                     "[NETWORK] Handshake received from port {} on {}",
@@ -254,7 +346,7 @@ impl TransferStruct {
                             .ok();
 
                         self.clients_to_holepunch
-                            .retain(|x| x.addrs.contains(&addr));
+                            .retain(|session| !session.covers(&addr));
                     }
                 } else {
                     self.net
@@ -269,6 +361,13 @@ impl TransferStruct {
 
                 should_relay = false;
             }
+            // A joiner couldn't punch through, the app moves us to the hoster like everyone else
+            Payloads::AttemptHosterConnection { .. } => {
+                if self.rendezvous_server != Some(addr) {
+                    return;
+                }
+                should_relay = false;
+            }
             Payloads::HostingReceived { session_id } => {
                 info!("[NETWORK] Obtained session ID: {}", session_id);
                 self.session_id.clone_from(session_id);
@@ -288,7 +387,7 @@ impl TransferStruct {
                         .join(", ")
                 );
                 self.clients_to_holepunch
-                    .push(HolePunchSession::new(peers.clone()));
+                    .push(PunchSession::new(peers.clone()));
                 should_relay = false;
             }
         }
@@ -302,6 +401,126 @@ impl TransferStruct {
             .ok();
     }
 
+    // Only clients that got past the version check get the key, so everyone in the session has one
+    fn answer_key_exchange(&mut self, addr: SocketAddr, peer: &str, data: &[u8]) {
+        let Some(name) = self
+            .clients
+            .iter()
+            .find(|(_, client)| client.addr == addr)
+            .map(|(name, _)| name.clone())
+        else {
+            info!("[NETWORK] Ignoring key exchange from port {}", addr.port());
+            return;
+        };
+
+        let response = match self.net.get_session_key() {
+            Some(key) => answer_key_request(&self.secret, data, key),
+            None => return,
+        };
+
+        match response {
+            Ok(data) => {
+                self.net
+                    .send_message(
+                        Payloads::KeyExchange {
+                            peer: peer.to_string(),
+                            data,
+                        },
+                        addr,
+                    )
+                    .ok();
+                self.net.set_sealed(addr, true);
+
+                info!("[NETWORK] Session encrypted with {}", name);
+
+                let held = self
+                    .clients
+                    .get_mut(&name)
+                    .map(|client| std::mem::take(&mut client.held))
+                    .unwrap_or_default();
+
+                for payload in held {
+                    self.net.send_message(payload, addr).ok();
+                }
+
+                self.server_tx
+                    .try_send(ReceiveMessage::Event(Event::KeyShared(name)))
+                    .ok();
+            }
+            Err(e) => {
+                info!("[NETWORK] Refused key exchange from {}: {}", name, e);
+                self.net
+                    .send_message(
+                        Payloads::ConnectionDenied {
+                            reason: String::from("Wrong session code!"),
+                        },
+                        addr,
+                    )
+                    .ok();
+
+                // Never had the key, so there's nothing to hold their slot for
+                self.clients.remove(&name);
+                self.net.set_sealed(addr, false);
+                self.announce_player_left(name);
+            }
+        }
+    }
+
+    fn resume_client(&mut self, addr: SocketAddr, name: &str, token: &str) {
+        let is_valid = |client: &Client| {
+            !client.resume_token.is_empty() && tokens_match(&client.resume_token, token)
+        };
+
+        // The old connection might not have timed out on our side yet
+        let resumed = match self.held_clients.get(name) {
+            Some(held) if is_valid(&held.client) => self
+                .held_clients
+                .remove(name)
+                .map(|held| (held.client, held.missed)),
+            _ => match self.clients.get(name) {
+                Some(client) if is_valid(client) => {
+                    self.clients.remove(name).map(|client| (client, Vec::new()))
+                }
+                _ => None,
+            },
+        };
+
+        let Some((mut client, missed)) = resumed else {
+            self.net
+                .send_message(
+                    Payloads::ConnectionDenied {
+                        reason: String::from("Could not resume session."),
+                    },
+                    addr,
+                )
+                .ok();
+            return;
+        };
+
+        info!(
+            "[NETWORK] {} resumed from port {} (was {})",
+            name,
+            addr.port(),
+            client.addr.port()
+        );
+
+        if client.addr != addr {
+            self.metrics.remove(&client.addr);
+            self.net.set_sealed(client.addr, false);
+        }
+
+        // A token only gets used once. The new address needs the key again before any of it goes out
+        client.resume_token = get_resume_token();
+        client.held = std::iter::once(Payloads::Resumed)
+            .chain(get_token_payloads(&client.resume_token))
+            .chain(missed)
+            .collect();
+        client.addr = addr;
+        client.clock = PeerClock::new();
+
+        self.clients.insert(name.to_string(), client);
+    }
+
     fn handle_app_message(&mut self) {
         while let Ok((payload, target)) = self.client_rx.try_recv() {
             if let Payloads::TransferControl { from: _, to } = &payload {
@@ -309,8 +528,12 @@ impl TransferStruct {
             }
 
             if let Some(target) = target {
-                if let Some(client) = self.clients.get(&target) {
-                    self.net.send_message(payload, client.addr).ok();
+                if let Some(client) = self.clients.get_mut(&target) {
+                    if is_accepted_in_clear(&payload, false) || self.net.is_sealed(&client.addr) {
+                        self.net.send_message(payload, client.addr).ok();
+                    } else {
+                        client.hold(&payload);
+                    }
                 }
             } else {
                 self.send_to_all(None, payload);
@@ -325,7 +548,19 @@ impl TransferStruct {
         }
 
         self.heartbeat_instant = Instant::now();
-        self.send_to_all(None, Payloads::Heartbeat);
+
+        // Each client gets their own heartbeat echoed back
+        for client in self.clients.values() {
+            self.net
+                .send_message(
+                    Payloads::Heartbeat {
+                        time: get_seconds(),
+                        echo: client.clock.get_echo(),
+                    },
+                    client.addr,
+                )
+                .ok();
+        }
     }
 
     fn handle_metrics(&mut self) {
@@ -347,15 +582,11 @@ impl TransferStruct {
     }
 
     fn remove_client(&mut self, addr: SocketAddr) {
-        let mut removed_client_name: Option<String> = None;
-
-        self.clients.retain(|name, client| {
-            if client.addr == addr {
-                removed_client_name = Some(name.clone());
-                return false;
-            }
-            true
-        });
+        let removed_client_name = self
+            .clients
+            .iter()
+            .find(|(_, client)| client.addr == addr)
+            .map(|(name, _)| name.clone());
 
         info!(
             "[NETWORK] Removing client from port {} who has name {:?}",
@@ -364,37 +595,58 @@ impl TransferStruct {
         );
 
         if let Some(name) = removed_client_name {
-            let player_left_payload = Payloads::PlayerLeft { name };
-
-            self.send_to_all(None, player_left_payload.clone());
-            self.number_connections.fetch_sub(1, SeqCst);
-            self.server_tx
-                .try_send(ReceiveMessage::Payload(player_left_payload))
-                .ok();
+            if let Some(client) = self.clients.remove(&name) {
+                if client.resume_token.is_empty() {
+                    self.announce_player_left(name);
+                } else {
+                    // Everyone else carries on as if they were still there until the grace period is up
+                    self.held_clients.insert(
+                        name,
+                        HeldClient {
+                            client,
+                            dropped_at: Instant::now(),
+                            missed: Vec::new(),
+                        },
+                    );
+                }
+            }
         }
 
         self.metrics.remove(&addr);
+        self.net.set_sealed(addr, false);
     }
 
-    fn should_stop(&self) -> bool {
-        self.should_stop.load(SeqCst)
-    }
-}
+    fn handle_held_clients(&mut self) {
+        let grace = Duration::from_secs(RESUME_GRACE_SECS);
 
-struct HolePunchSession {
-    addrs: Vec<SocketAddr>,
-    timer: Option<Instant>,
-    retries: u8,
-}
+        let expired: Vec<String> = self
+            .held_clients
+            .iter()
+            .filter(|(_, held)| held.dropped_at.elapsed() >= grace)
+            .map(|(name, _)| name.clone())
+            .collect();
 
-impl HolePunchSession {
-    pub fn new(addrs: Vec<SocketAddr>) -> Self {
-        Self {
-            addrs,
-            timer: None,
-            retries: 0,
+        for name in expired {
+            info!("[NETWORK] {} did not come back in time", name);
+
+            self.held_clients.remove(&name);
+            self.announce_player_left(name);
         }
     }
+
+    fn announce_player_left(&mut self, name: String) {
+        let player_left_payload = Payloads::PlayerLeft { name };
+
+        self.send_to_all(None, player_left_payload.clone());
+        self.number_connections.fetch_sub(1, SeqCst);
+        self.server_tx
+            .try_send(ReceiveMessage::Payload(player_left_payload))
+            .ok();
+    }
+
+    fn should_stop(&self) -> bool {
+        self.should_stop.load(SeqCst)
+    }
 }
 
 pub struct Server {
@@ -403,7 +655,6 @@ pub struct Server {
 
     transfer: Option<Arc<Mutex<TransferStruct>>>,
 
-    last_port_forward_result: Option<Result<(), Error>>,
     // Send data to peers
     client_tx: ClientSender,
     // Internally receive data to send to clients
@@ -427,7 +678,6 @@ impl Server {
         Self {
             number_connections: Arc::new(AtomicU16::new(0)),
 
-            last_port_forward_result: None,
             should_stop: Arc::new(AtomicBool::new(false)),
             client_rx,
             client_tx,
@@ -440,58 +690,77 @@ impl Server {
         }
     }
 
-    fn port_forward(&self, port: u16) -> Result<(), Error> {
-        let local_addr: Ipv4Addr = match get_local_ip_address(false) {
+    fn port_forward(port: u16) -> Result<PortMapping, Error> {
+        let local_addr = match get_local_ip_address(false) {
             Some(IpAddr::V4(ip)) => ip,
             Some(IpAddr::V6(_)) | None => return Err(Error::LocalAddrNotFound),
         };
 
         info!("[NETWORK] Found local address: {}", local_addr);
 
-        let gateway = match search_gateway(SearchOptions {
-            bind_addr: SocketAddr::new(IpAddr::V4(local_addr), 0),
-            timeout: Some(Duration::from_secs(3)),
-            ..Default::default()
-        }) {
-            Ok(g) => g,
-            Err(e) => return Err(Error::GatewayNotFound(e)),
-        };
-
-        info!("[NETWORK] Found gateway at {}", gateway.root_url);
+        let mapping = map_port(SocketAddrV4::new(local_addr, port))?;
 
-        match gateway.add_port(
-            PortMappingProtocol::UDP,
+        info!(
+            "[NETWORK] Port forwarded port {} to {} with {}",
             port,
-            SocketAddrV4::new(local_addr, port),
-            86400,
-            "YourControls",
-        ) {
-            Ok(()) => {}
-            Err(e) => return Err(Error::AddPortError(e)),
-        };
-
-        info!("[NETWORK] Port forwarded port {}", port);
+            mapping.get_external_port(),
+            mapping.get_method()
+        );
 
-        Ok(())
+        Ok(mapping)
     }
 
-    pub fn start(&mut self, is_ipv6: bool, port: u16, upnp: bool) -> Result<(), Error> {
+    pub fn start(&mut self, port: u16, upnp: bool) -> Result<(), Error> {
         let socket =
             Socket::from_udp_socket(get_socket_duplex(port), get_socket_config(self.timeout))?;
-        // Attempt to port forward
-        if upnp && !is_ipv6 {
-            self.last_port_forward_result = Some(self.port_forward(port));
+
+        self.run(socket, None)?;
+
+        // Attempt to port forward, only IPv4 is behind NAT. Searching for the gateway takes a few seconds
+        if upnp {
+            let server_tx = self.server_tx.clone();
+            let should_stop = self.should_stop.clone();
+
+            thread::spawn(move || {
+                let event = match Self::port_forward(port) {
+                    Ok(mapping) => {
+                        let method = mapping.get_method();
+                        let external_port = mapping.get_external_port();
+                        keep_mapped(mapping, should_stop);
+                        Event::PortForwarded(method, external_port)
+                    }
+                    Err(e) => {
+                        info!("[NETWORK] Could not port forward: {}", e);
+                        Event::PortForwardFailed(e.to_string())
+                    }
+                };
+
+                server_tx.try_send(ReceiveMessage::Event(event)).ok();
+            });
         }
 
-        self.run(socket, None)
+        Ok(())
     }
 
-    pub fn start_with_hole_punching(&mut self, is_ipv6: bool) -> Result<(), Error> {
-        let socket = Socket::bind_with_config(
-            get_bind_address(is_ipv6, None),
-            get_socket_config(self.timeout),
-        )?;
-        let addr: SocketAddr = get_rendezvous_server(is_ipv6)?;
+    // Lets joiners on the same network find this server without typing in an address
+    pub fn advertise_on_lan(&self, port: u16, aircraft: String) -> Result<(), Error> {
+        advertise(
+            LanSession {
+                name: self.username.clone(),
+                aircraft,
+                players: 0,
+                version: self.version.clone(),
+                port,
+            },
+            self.number_connections.clone(),
+            self.should_stop.clone(),
+        )
+    }
+
+    pub fn start_with_hole_punching(&mut self, prefer_ipv6: bool) -> Result<(), Error> {
+        let socket =
+            Socket::from_udp_socket(bind_dual_stack(None)?, get_socket_config(self.timeout))?;
+        let addr: SocketAddr = get_rendezvous_server(prefer_ipv6)?;
 
         self.run(socket, Some(addr))
     }
@@ -505,6 +774,7 @@ impl Server {
         let mut transfer = TransferStruct {
             // Holepunching
             session_id: String::new(),
+            secret: generate_secret()?,
             rendezvous_server: rendezvous,
             clients_to_holepunch: Vec::new(),
             // Transfer
@@ -514,6 +784,7 @@ impl Server {
             // State
             in_control: self.username.clone(),
             clients: HashMap::new(),
+            held_clients: HashMap::new(),
             should_stop: self.should_stop.clone(),
             number_connections: self.number_connections.clone(),
             username: self.username.clone(),
@@ -523,6 +794,9 @@ impl Server {
             metrics: HashMap::new(),
         };
 
+        transfer.net.set_session_key(SessionKey::generate()?);
+        transfer.net.refuse_clear();
+
         if let Some(addr) = rendezvous {
             // Send handshake payload to rendezvous server to get session ID
             transfer
@@ -530,10 +804,8 @@ impl Server {
                 .send_message(
                     Payloads::RequestHosting {
                         self_hosted: true,
-                        local_endpoint: get_local_endpoints_with_port(
-                            local_endpoint.is_ipv6(),
-                            port,
-                        ),
+                        local_endpoint: get_local_endpoints_with_port(false, port),
+                        local_endpoint_v6: get_local_endpoints_with_port(true, port),
                     },
                     addr,
                 )
@@ -588,6 +860,7 @@ impl Server {
                 transfer.handle_app_message();
                 transfer.handle_heartbeat();
                 transfer.handle_metrics();
+                transfer.handle_held_clients();
 
                 if transfer.should_stop() {
                     break;
@@ -668,10 +941,19 @@ impl TransferClient for Server {
         None
     }
 
+    fn get_join_code(&self) -> Option<String> {
+        let transfer = self.transfer.as_ref()?.lock().unwrap();
+        Some(get_join_code(&transfer.session_id, &transfer.secret))
+    }
+
     fn stop(&mut self, reason: String) {
         self.should_stop.store(true, SeqCst);
         self.server_tx
             .try_send(ReceiveMessage::Event(Event::ConnectionLost(reason)))
             .ok();
     }
+
+    fn close(&mut self) {
+        self.should_stop.store(true, SeqCst);
+    }
 }
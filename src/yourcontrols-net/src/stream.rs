@@ -0,0 +1,495 @@
+use laminar::{Packet, SocketEvent};
+use log::info;
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+use tungstenite::{
+    handshake::{
+        server::{NoCallback, ServerHandshake},
+        HandshakeError, MidHandshake,
+    },
+    protocol::WebSocketConfig,
+    Message as WebSocketMessage, WebSocket,
+};
+use yourcontrols_types::Error;
+
+use crate::util::get_canonical_addr;
+
+// Same limit laminar has on a fragmented packet, more than that is someone misbehaving
+const MAX_FRAME_LEN: usize = 1 << 20;
+// Stop queueing for a peer that isn't reading
+const MAX_BUFFERED_LEN: usize = 8 << 20;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const READ_CHUNK_LEN: usize = 16384;
+// Connections and handshakes together, anyone past that is turned away at accept
+const MAX_CONNECTIONS: usize = 512;
+
+type PendingHandshake = MidHandshake<ServerHandshake<TcpStream, NoCallback>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StreamKind {
+    Tcp,
+    WebSocket,
+}
+
+fn to_io_error(e: impl std::fmt::Display) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+// Payloads behind a u32 big endian length
+#[derive(Default)]
+struct LengthFramer {
+    read_buffer: Vec<u8>,
+    write_buffer: Vec<u8>,
+}
+
+impl LengthFramer {
+    fn push_frame(&mut self, bytes: &[u8]) {
+        self.write_buffer
+            .extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+        self.write_buffer.extend_from_slice(bytes);
+    }
+
+    fn next_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.read_buffer.len() < 4 {
+            return Ok(None);
+        }
+
+        let mut len_bytes = [0; 4];
+        len_bytes.copy_from_slice(&self.read_buffer[..4]);
+        let len = u32::from_be_bytes(len_bytes) as usize;
+
+        if len > MAX_FRAME_LEN {
+            return Err(to_io_error(format!("Frame of {} bytes is too large", len)));
+        }
+
+        if self.read_buffer.len() < 4 + len {
+            return Ok(None);
+        }
+
+        let frame = self.read_buffer[4..4 + len].to_vec();
+        self.read_buffer.drain(..4 + len);
+
+        Ok(Some(frame))
+    }
+}
+
+enum Framing {
+    Tcp(TcpStream, LengthFramer),
+    WebSocket(Box<WebSocket<TcpStream>>),
+}
+
+struct Connection {
+    framing: Framing,
+    last_received: Instant,
+}
+
+impl Connection {
+    fn new(framing: Framing) -> Self {
+        Self {
+            framing,
+            last_received: Instant::now(),
+        }
+    }
+
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match &mut self.framing {
+            Framing::Tcp(_, framer) => {
+                if framer.write_buffer.len() > MAX_BUFFERED_LEN {
+                    return Err(to_io_error("Peer is not reading"));
+                }
+                framer.push_frame(bytes);
+                Ok(())
+            }
+            Framing::WebSocket(socket) => {
+                match socket.write(WebSocketMessage::Binary(bytes.to_vec())) {
+                    Ok(()) => Ok(()),
+                    Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => Ok(()),
+                    Err(e) => Err(to_io_error(e)),
+                }
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.framing {
+            Framing::Tcp(stream, framer) => {
+                while !framer.write_buffer.is_empty() {
+                    match stream.write(&framer.write_buffer) {
+                        Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                        Ok(written) => {
+                            framer.write_buffer.drain(..written);
+                        }
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                        Err(e) => return Err(e),
+                    }
+                }
+                Ok(())
+            }
+            Framing::WebSocket(socket) => match socket.flush() {
+                Ok(()) => Ok(()),
+                Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => Ok(()),
+                Err(e) => Err(to_io_error(e)),
+            },
+        }
+    }
+
+    // Every whole payload that arrived since the last call
+    fn receive(&mut self) -> io::Result<Vec<Vec<u8>>> {
+        let mut frames = Vec::new();
+
+        match &mut self.framing {
+            Framing::Tcp(stream, framer) => {
+                let mut chunk = [0; READ_CHUNK_LEN];
+                loop {
+                    match stream.read(&mut chunk) {
+                        Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                        Ok(read) => framer.read_buffer.extend_from_slice(&chunk[..read]),
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                        Err(e) => return Err(e),
+                    }
+                }
+
+                while let Some(frame) = framer.next_frame()? {
+                    frames.push(frame);
+                }
+            }
+            Framing::WebSocket(socket) => loop {
+                match socket.read() {
+                    Ok(WebSocketMessage::Binary(bytes)) => frames.push(bytes),
+                    // Pings are answered on the next flush
+                    Ok(WebSocketMessage::Close(_)) => return Err(ErrorKind::UnexpectedEof.into()),
+                    Ok(_) => {}
+                    Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) => return Err(to_io_error(e)),
+                }
+            },
+        }
+
+        if !frames.is_empty() {
+            self.last_received = Instant::now();
+        }
+
+        Ok(frames)
+    }
+}
+
+// Nothing bigger than a TCP frame may come in, and a peer that doesn't read can't have us buffer forever
+fn get_websocket_config() -> WebSocketConfig {
+    WebSocketConfig {
+        max_message_size: Some(MAX_FRAME_LEN),
+        max_frame_size: Some(MAX_FRAME_LEN),
+        max_write_buffer_size: MAX_BUFFERED_LEN,
+        ..Default::default()
+    }
+}
+
+fn connect_websocket(addr: SocketAddr) -> io::Result<WebSocket<TcpStream>> {
+    let stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECT_TIMEOUT))?;
+
+    let (socket, _) = tungstenite::client::client_with_config(
+        format!("ws://{}/", addr),
+        stream,
+        Some(get_websocket_config()),
+    )
+    .map_err(to_io_error)?;
+
+    Ok(socket)
+}
+
+// Carries the same bytes laminar would over TCP or WebSocket, for networks that block UDP.
+// Incoming payloads come out as laminar socket events so SenderReceiver can't tell the difference
+pub struct StreamTransport {
+    listeners: Vec<(TcpListener, StreamKind)>,
+    // WebSocket handshakes go on a bit every poll so a slow peer can't hold everyone up, and when they started
+    handshakes: Vec<(SocketAddr, Instant, PendingHandshake)>,
+    connections: HashMap<SocketAddr, Connection>,
+    // Set when connected out to a single server, which then gets everything whatever the address
+    server: Option<SocketAddr>,
+    events: VecDeque<SocketEvent>,
+    timeout: Duration,
+}
+
+impl StreamTransport {
+    fn new(timeout: u64) -> Self {
+        Self {
+            listeners: Vec::new(),
+            handshakes: Vec::new(),
+            connections: HashMap::new(),
+            server: None,
+            events: VecDeque::new(),
+            timeout: Duration::from_secs(timeout),
+        }
+    }
+
+    pub fn connect(kind: StreamKind, addr: SocketAddr, timeout: u64) -> Result<Self, Error> {
+        let framing = match kind {
+            StreamKind::Tcp => {
+                let stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+                stream.set_nodelay(true)?;
+                Framing::Tcp(stream, LengthFramer::default())
+            }
+            StreamKind::WebSocket => Framing::WebSocket(Box::new(connect_websocket(addr)?)),
+        };
+
+        match &framing {
+            Framing::Tcp(stream, _) => stream.set_nonblocking(true)?,
+            Framing::WebSocket(socket) => socket.get_ref().set_nonblocking(true)?,
+        }
+
+        let mut transport = Self::new(timeout);
+        transport.connections.insert(addr, Connection::new(framing));
+        transport.server = Some(addr);
+
+        Ok(transport)
+    }
+
+    pub fn listen(binds: Vec<(StreamKind, SocketAddr)>, timeout: u64) -> Result<Self, Error> {
+        let mut transport = Self::new(timeout);
+
+        for (kind, addr) in binds {
+            let listener = TcpListener::bind(addr)?;
+            listener.set_nonblocking(true)?;
+
+            info!("[NETWORK] Accepting {:?} connections on {}", kind, addr);
+
+            transport.listeners.push((listener, kind));
+        }
+
+        Ok(transport)
+    }
+
+    // Whether a payload to this address goes through here instead of UDP
+    pub fn handles(&self, addr: &SocketAddr) -> bool {
+        self.server.is_some() || self.connections.contains_key(addr)
+    }
+
+    pub fn send(&mut self, packet: Packet) {
+        let addr = self.server.unwrap_or_else(|| packet.addr());
+
+        let failed = match self.connections.get_mut(&addr) {
+            Some(connection) => connection.send(packet.payload()).is_err(),
+            None => false,
+        };
+
+        if failed {
+            self.close(addr);
+        }
+    }
+
+    pub fn next_event(&mut self) -> Option<SocketEvent> {
+        self.events.pop_front()
+    }
+
+    fn close(&mut self, addr: SocketAddr) {
+        if self.connections.remove(&addr).is_some() {
+            self.events.push_back(SocketEvent::Timeout(addr));
+        }
+    }
+
+    fn accept(&mut self) {
+        for (listener, kind) in &self.listeners {
+            while let Ok((stream, addr)) = listener.accept() {
+                // Dropped right away, leaving it in the backlog would only make it try for longer
+                if self.connections.len() + self.handshakes.len() >= MAX_CONNECTIONS
+                    || stream.set_nonblocking(true).is_err()
+                {
+                    continue;
+                }
+
+                let addr = get_canonical_addr(addr);
+                match kind {
+                    StreamKind::Tcp => {
+                        stream.set_nodelay(true).ok();
+                        self.connections.insert(
+                            addr,
+                            Connection::new(Framing::Tcp(stream, LengthFramer::default())),
+                        );
+                    }
+                    StreamKind::WebSocket => {
+                        match tungstenite::accept_with_config(stream, Some(get_websocket_config()))
+                        {
+                            Ok(socket) => {
+                                self.connections.insert(
+                                    addr,
+                                    Connection::new(Framing::WebSocket(Box::new(socket))),
+                                );
+                            }
+                            Err(HandshakeError::Interrupted(handshake)) => {
+                                self.handshakes.push((addr, Instant::now(), handshake));
+                            }
+                            Err(HandshakeError::Failure(_)) => {}
+                        }
+                    }
+                }
+            }
+        }
+
+        for (addr, started, handshake) in std::mem::take(&mut self.handshakes) {
+            match handshake.handshake() {
+                Ok(socket) => {
+                    self.connections
+                        .insert(addr, Connection::new(Framing::WebSocket(Box::new(socket))));
+                }
+                Err(HandshakeError::Interrupted(handshake))
+                    if started.elapsed() < CONNECT_TIMEOUT =>
+                {
+                    self.handshakes.push((addr, started, handshake));
+                }
+                // Failed or too slow
+                Err(_) => {}
+            }
+        }
+    }
+
+    pub fn poll(&mut self) {
+        self.accept();
+
+        let mut closed = Vec::new();
+
+        for (addr, connection) in self.connections.iter_mut() {
+            let result = connection.flush().and_then(|_| connection.receive());
+
+            match result {
+                Ok(frames) => {
+                    for frame in frames {
+                        let packet = Packet::reliable_ordered(*addr, frame, None);
+                        self.events.push_back(SocketEvent::Packet(packet));
+                    }
+                }
+                Err(_) => closed.push(*addr),
+            }
+
+            if connection.last_received.elapsed() > self.timeout {
+                closed.push(*addr);
+            }
+        }
+
+        for addr in closed {
+            self.close(addr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_frames_split_across_reads() {
+        let mut sender = LengthFramer::default();
+        sender.push_frame(b"hello");
+        sender.push_frame(b"");
+        sender.push_frame(b"world");
+
+        let mut receiver = LengthFramer::default();
+        let (first, second) = sender.write_buffer.split_at(7);
+
+        receiver.read_buffer.extend_from_slice(first);
+        assert_eq!(receiver.next_frame().unwrap(), None);
+
+        receiver.read_buffer.extend_from_slice(second);
+        assert_eq!(receiver.next_frame().unwrap().unwrap(), b"hello");
+        assert_eq!(receiver.next_frame().unwrap().unwrap(), b"");
+        assert_eq!(receiver.next_frame().unwrap().unwrap(), b"world");
+        assert_eq!(receiver.next_frame().unwrap(), None);
+    }
+
+    fn round_trip(kind: StreamKind) {
+        let mut server =
+            StreamTransport::listen(vec![(kind, "127.0.0.1:0".parse().unwrap())], 5).unwrap();
+        let server_addr = server.listeners[0].0.local_addr().unwrap();
+        // The WebSocket handshake needs the server polling in the meantime
+        let connecting = thread::spawn(move || StreamTransport::connect(kind, server_addr, 5));
+        while !connecting.is_finished() {
+            server.poll();
+            thread::sleep(Duration::from_millis(5));
+        }
+        let mut client = connecting.join().unwrap().unwrap();
+
+        // Any address reaches the server
+        client.send(Packet::unreliable(
+            "10.0.0.1:1234".parse().unwrap(),
+            b"ping".to_vec(),
+        ));
+
+        let mut received = None;
+        for _ in 0..200 {
+            client.poll();
+            server.poll();
+            if let Some(SocketEvent::Packet(packet)) = server.next_event() {
+                received = Some(packet);
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        let packet = received.unwrap();
+        assert_eq!(packet.payload(), b"ping");
+        assert!(server.handles(&packet.addr()));
+
+        server.send(Packet::unreliable(packet.addr(), b"pong".to_vec()));
+
+        for _ in 0..200 {
+            server.poll();
+            client.poll();
+            if let Some(SocketEvent::Packet(packet)) = client.next_event() {
+                assert_eq!(packet.payload(), b"pong");
+                assert_eq!(packet.addr(), server_addr);
+                return;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        panic!("No reply");
+    }
+
+    #[test]
+    fn test_tcp_round_trip() {
+        round_trip(StreamKind::Tcp);
+    }
+
+    #[test]
+    fn test_websocket_round_trip() {
+        round_trip(StreamKind::WebSocket);
+    }
+
+    #[test]
+    fn test_stalled_handshake_does_not_block() {
+        let mut server = StreamTransport::listen(
+            vec![(StreamKind::WebSocket, "127.0.0.1:0".parse().unwrap())],
+            5,
+        )
+        .unwrap();
+        let server_addr = server.listeners[0].0.local_addr().unwrap();
+
+        // Connects and never says anything
+        let _stalled = TcpStream::connect(server_addr).unwrap();
+
+        let connecting =
+            thread::spawn(move || StreamTransport::connect(StreamKind::WebSocket, server_addr, 5));
+        while !connecting.is_finished() {
+            server.poll();
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        assert!(connecting.join().unwrap().is_ok());
+        assert_eq!(server.handshakes.len(), 1);
+    }
+
+    #[test]
+    fn test_oversized_frame_is_refused() {
+        let mut receiver = LengthFramer::default();
+        receiver
+            .read_buffer
+            .extend_from_slice(&(MAX_FRAME_LEN as u32 + 1).to_be_bytes());
+
+        assert!(receiver.next_frame().is_err());
+    }
+}
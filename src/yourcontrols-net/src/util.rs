@@ -4,20 +4,26 @@ use dotenv_codegen::dotenv;
 use laminar::Metrics;
 use socket2::{Domain, Socket, Type};
 use std::{
-    net::{IpAddr, SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket},
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket},
     time::{Duration, SystemTime},
 };
-use yourcontrols_types::{AllNeedSync, Error};
+use yourcontrols_types::{AllNeedSync, Error, WeatherSample};
 
+use crate::definition::ChunkedDefinition;
 use crate::messages::Payloads;
+use crate::portmap::PortMapMethod;
+use crate::stream::StreamKind;
 
-pub const MAX_PUNCH_RETRIES: u8 = 5;
 pub const LOOP_SLEEP_TIME_MS: u64 = 5;
 pub const HEARTBEAT_INTERVAL_MANUAL_SECS: f32 = 0.5;
 
 const HEARTBEAT_INTERVAL_MS: u64 = 1000;
 const RENDEZVOUS_SERVER_HOSTNAME: &str = dotenv!("SERVER_HOSTNAME");
 const RENDEZVOUS_PORT: &str = dotenv!("SERVER_PORT");
+const FALLBACK_TCP_PORT: &str = dotenv!("SERVER_TCP_PORT");
+const FALLBACK_WS_PORT: &str = dotenv!("SERVER_WS_PORT");
+const PROBE_PORT: &str = dotenv!("SERVER_PROBE_PORT");
 
 // Types
 pub type ClientSender = Sender<(Payloads, Option<String>)>;
@@ -25,13 +31,45 @@ pub type ClientReceiver = Receiver<(Payloads, Option<String>)>;
 pub type ServerSender = Sender<ReceiveMessage>;
 pub type ServerReceiver = Receiver<ReceiveMessage>;
 
-pub fn get_bind_address(is_ipv6: bool, port: Option<u16>) -> SocketAddr {
-    let bind_string = format!(
-        "{}:{}",
-        if is_ipv6 { "[::]" } else { "0.0.0.0" },
-        port.unwrap_or(0)
-    );
-    bind_string.parse().unwrap()
+// Sockets are bound dual stack, so IPv4 and IPv6 peers can both be reached from the same one
+pub fn get_bind_address(port: Option<u16>) -> SocketAddr {
+    SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port.unwrap_or(0))
+}
+
+// Falls back to IPv4 only where IPv6 is turned off
+pub fn bind_dual_stack(port: Option<u16>) -> io::Result<UdpSocket> {
+    let dual_stack = Socket::new(Domain::IPV6, Type::DGRAM, None).and_then(|socket| {
+        socket.set_only_v6(false)?;
+        socket.bind(&get_bind_address(port).into())?;
+        Ok(socket)
+    });
+
+    match dual_stack {
+        Ok(socket) => Ok(socket.into()),
+        Err(_) => UdpSocket::bind(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port.unwrap_or(0),
+        )),
+    }
+}
+
+// IPv4 peers show up as IPv4-mapped IPv6 addresses on a dual stack socket, everything above the socket sees them as plain IPv4
+pub fn get_canonical_addr(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(ip) => SocketAddr::new(IpAddr::V4(ip), v6.port()),
+            None => addr,
+        },
+        SocketAddr::V4(_) => addr,
+    }
+}
+
+// What a dual stack socket needs to send to an IPv4 peer
+pub fn get_mapped_addr(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V4(v4) => SocketAddr::new(IpAddr::V6(v4.ip().to_ipv6_mapped()), v4.port()),
+        SocketAddr::V6(_) => addr,
+    }
 }
 
 pub fn match_ip_address_to_socket_addr(ip: IpAddr, port: u16) -> SocketAddr {
@@ -55,12 +93,38 @@ pub fn get_addr_from_hostname_and_port(
     Err(Error::MismatchingIpVersion)
 }
 
-pub fn get_rendezvous_server(is_ipv6: bool) -> Result<SocketAddr, Error> {
-    get_addr_from_hostname_and_port(
-        is_ipv6,
-        RENDEZVOUS_SERVER_HOSTNAME,
-        RENDEZVOUS_PORT.parse().unwrap(),
-    )
+// The preferred family if the server has an address in it, the other one otherwise
+fn get_cloud_server_addr(prefer_ipv6: bool, port: u16) -> Result<SocketAddr, Error> {
+    get_addr_from_hostname_and_port(prefer_ipv6, RENDEZVOUS_SERVER_HOSTNAME, port).or_else(|_| {
+        get_addr_from_hostname_and_port(!prefer_ipv6, RENDEZVOUS_SERVER_HOSTNAME, port)
+    })
+}
+
+pub fn get_rendezvous_server(prefer_ipv6: bool) -> Result<SocketAddr, Error> {
+    get_cloud_server_addr(prefer_ipv6, RENDEZVOUS_PORT.parse().unwrap())
+}
+
+// The rendezvous server and its second port on the same address, for telling NAT types apart
+pub fn get_probe_servers(prefer_ipv6: bool) -> Result<(SocketAddr, SocketAddr), Error> {
+    let primary = get_rendezvous_server(prefer_ipv6)?;
+    Ok((
+        primary,
+        SocketAddr::new(primary.ip(), PROBE_PORT.parse().unwrap()),
+    ))
+}
+
+// Where to reach the cloud server when UDP doesn't get through, in the order to try them
+pub fn get_stream_fallback_servers(prefer_ipv6: bool) -> Vec<(StreamKind, SocketAddr)> {
+    [
+        (StreamKind::Tcp, FALLBACK_TCP_PORT),
+        (StreamKind::WebSocket, FALLBACK_WS_PORT),
+    ]
+    .iter()
+    .filter_map(|(kind, port)| {
+        let addr = get_cloud_server_addr(prefer_ipv6, port.parse().ok()?);
+        Some((*kind, addr.ok()?))
+    })
+    .collect()
 }
 
 pub fn get_socket_config(timeout: u64) -> laminar::Config {
@@ -75,17 +139,7 @@ pub fn get_socket_config(timeout: u64) -> laminar::Config {
 }
 
 pub fn get_socket_duplex(port: u16) -> UdpSocket {
-    let socket = Socket::new(Domain::IPV6, Type::DGRAM, None).unwrap();
-    socket.set_only_v6(false).ok();
-    socket
-        .bind(
-            &format!("[::]:{}", port)
-                .parse::<SocketAddr>()
-                .unwrap()
-                .into(),
-        )
-        .unwrap();
-    socket.into()
+    bind_dual_stack(Some(port)).unwrap()
 }
 
 pub fn get_seconds() -> f64 {
@@ -126,13 +180,35 @@ pub fn is_actually_ipv4(addr: SocketAddr) -> bool {
         SocketAddr::V6(v6) => matches!(v6.ip().segments(), [0, 0, 0, 0, 0, 0xFFFF, ..]),
     }
 }
+// Session id the servers know, then the secret only members know. Direct sessions have no id, their code is only the secret
+pub fn get_join_code(session_id: &str, secret: &str) -> String {
+    if session_id.is_empty() {
+        secret.to_string()
+    } else {
+        format!("{}-{}", session_id, secret)
+    }
+}
+
+// Session ids are only letters, so the last dash is always the one between them
+pub fn split_join_code(code: &str) -> (&str, &str) {
+    code.rsplit_once('-').unwrap_or(("", code))
+}
+
 #[derive(Debug)]
 pub enum Event {
     ConnectionEstablished,
     UnablePunchthrough,
     SessionIdFetchFailed,
     ConnectionLost(String),
+    // Dropped out of the session, trying to get back in before the host gives the slot away
+    Reconnecting,
+    Resumed,
+    // The gateway may hand out a different port than the one asked for, that's the one joiners need
+    PortForwarded(PortMapMethod, u16),
+    PortForwardFailed(String),
     Metrics(Metrics),
+    // The joiner got the session key from us, what's sealed for it can be read from now on
+    KeyShared(String),
 }
 
 #[derive(Debug)]
@@ -148,8 +224,12 @@ pub trait TransferClient {
     fn get_receiver(&self) -> &ServerReceiver;
     fn get_server_name(&self) -> &str;
     fn get_session_id(&self) -> Option<String>;
+    // What joiners have to type in, the session id along with the secret
+    fn get_join_code(&self) -> Option<String>;
     // Application specific functions
     fn stop(&mut self, reason: String);
+    // Stops without telling the app, for when another client takes over the session
+    fn close(&mut self);
 
     fn update(&self, data: AllNeedSync, is_unreliable: bool) {
         self.get_transmitter()
@@ -220,15 +300,141 @@ pub trait TransferClient {
             .ok();
     }
 
+    fn request_pause(&self, paused: bool, sim_rate: f64) {
+        self.get_transmitter()
+            .try_send((
+                Payloads::RequestPause {
+                    from: self.get_server_name().to_string(),
+                    paused,
+                    sim_rate,
+                },
+                None,
+            ))
+            .ok();
+    }
+
+    fn set_pause(&self, from: String, paused: bool, sim_rate: f64, target: Option<String>) {
+        self.get_transmitter()
+            .try_send((
+                Payloads::SetPause {
+                    from,
+                    paused,
+                    sim_rate,
+                },
+                target,
+            ))
+            .ok();
+    }
+
+    fn set_weather(&self, weather: WeatherSample) {
+        self.get_transmitter()
+            .try_send((
+                Payloads::SetWeather {
+                    from: self.get_server_name().to_string(),
+                    weather,
+                },
+                None,
+            ))
+            .ok();
+    }
+
     fn send_ready(&self) {
         self.get_transmitter()
             .try_send((Payloads::Ready, None))
             .ok();
     }
 
-    fn send_definitions(&self, bytes: Box<[u8]>, target: String) {
+    fn send_definition_manifest(&self, definition: &ChunkedDefinition, target: String) {
         self.get_transmitter()
-            .try_send((Payloads::AircraftDefinition { bytes }, Some(target)))
+            .try_send((definition.get_manifest(), Some(target)))
+            .ok();
+    }
+
+    fn send_definition_chunks(
+        &self,
+        definition: &ChunkedDefinition,
+        chunks: &[u32],
+        target: String,
+    ) {
+        for index in chunks {
+            if let Some(chunk) = definition.get_chunk(*index) {
+                self.get_transmitter()
+                    .try_send((chunk, Some(target.clone())))
+                    .ok();
+            }
+        }
+    }
+
+    fn request_definition_chunks(&self, hash: String, chunks: Vec<u32>) {
+        self.get_transmitter()
+            .try_send((
+                Payloads::RequestDefinitionChunks {
+                    from: self.get_server_name().to_string(),
+                    hash,
+                    chunks,
+                },
+                None,
+            ))
             .ok();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_join_code_round_trip() {
+        let code = get_join_code("ABCDEFGH", "SECRET23");
+        assert_eq!(code, "ABCDEFGH-SECRET23");
+        assert_eq!(split_join_code(&code), ("ABCDEFGH", "SECRET23"));
+
+        // Direct sessions
+        assert_eq!(
+            split_join_code(&get_join_code("", "SECRET23")),
+            ("", "SECRET23")
+        );
+    }
+
+    #[test]
+    fn test_mapped_addresses_round_trip() {
+        let v4: SocketAddr = "203.0.113.7:5000".parse().unwrap();
+        let mapped: SocketAddr = "[::ffff:203.0.113.7]:5000".parse().unwrap();
+
+        assert_eq!(get_mapped_addr(v4), mapped);
+        assert_eq!(get_canonical_addr(mapped), v4);
+        assert!(is_actually_ipv4(mapped));
+    }
+
+    #[test]
+    fn test_ipv6_addresses_are_left_alone() {
+        let v6: SocketAddr = "[2001:db8::1]:5000".parse().unwrap();
+        assert_eq!(get_mapped_addr(v6), v6);
+        assert_eq!(get_canonical_addr(v6), v6);
+
+        // Loopback is not an IPv4 address in disguise
+        let loopback: SocketAddr = "[::1]:5000".parse().unwrap();
+        assert_eq!(get_canonical_addr(loopback), loopback);
+    }
+
+    #[test]
+    fn test_dual_stack_reaches_ipv4() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let receiver_addr = receiver.local_addr().unwrap();
+
+        let socket = bind_dual_stack(None).unwrap();
+        let target = if socket.local_addr().unwrap().is_ipv6() {
+            get_mapped_addr(receiver_addr)
+        } else {
+            receiver_addr
+        };
+        socket.send_to(b"ping", target).unwrap();
+
+        let mut buf = [0; 4];
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let (len, _) = receiver.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"ping");
+    }
+}
@@ -1,21 +1,26 @@
 use crate::servers::{Client, ServerState, Servers};
+use dotenv::var;
 use laminar::Socket;
 use log::info;
 use semver::Version;
 use std::{
     collections::HashMap,
-    net::SocketAddr,
+    net::{Ipv6Addr, SocketAddr},
     str::FromStr,
     sync::{Arc, Mutex},
     thread::sleep,
     time::{Duration, Instant},
 };
 use yourcontrols_net::{
+    get_seconds,
     get_socket_config,
     get_socket_duplex,
+    ChunkedDefinition,
     Message,
     Payloads,
     SenderReceiver,
+    StreamKind,
+    StreamTransport,
 };
 use yourcontrols_types::Error;
 
@@ -51,7 +56,27 @@ fn process_payload(
     state: &mut ServerState,
     net: &mut SenderReceiver,
 ) {
-    match &payload {
+    let sender = state
+        .clients
+        .values_mut()
+        .find(|client| client.addr == addr);
+
+    // Clients send update times in our time base, sealed ones couldn't be converted here
+    if let (Payloads::Heartbeat { time, echo }, Some(sender)) = (&payload, sender) {
+        sender.clock.on_heartbeat(*time, *echo, get_seconds());
+        return;
+    }
+
+    // Sealed payloads are forwarded as they came, their readable copy only drives the bookkeeping
+    let visible = match &payload {
+        Payloads::Encrypted {
+            visible: Some(visible),
+            ..
+        } => visible.as_ref(),
+        _ => &payload,
+    };
+
+    match visible {
         // Unused
         Payloads::InvalidName { .. }
         | Payloads::RendezvousHandshake { .. }
@@ -64,14 +89,120 @@ fn process_payload(
         | Payloads::RequestHosting { .. }
         | Payloads::PeerEstablished { .. }
         | Payloads::ConnectionDenied { .. }
-        | Payloads::Heartbeat
-        | Payloads::PlayerLeft { .. } => return,
+        | Payloads::Heartbeat { .. }
+        | Payloads::PlayerLeft { .. }
+        // Relays don't hold slots, nobody gets a token to resume with
+        | Payloads::ResumeToken { .. }
+        | Payloads::Resume { .. }
+        | Payloads::Resumed
+        // Only the rendezvous server answers probes
+        | Payloads::NatProbe { .. }
+        | Payloads::NatProbeResponse { .. }
+        // Only the rendezvous server moves sessions
+        | Payloads::RequestHosterFallback { .. }
+        // Joiners couldn't load these from us
+        | Payloads::AircraftDefinition { .. } => return,
         // Used
-        Payloads::AircraftDefinition { bytes } => {
-            state.aircraft_definition = Some(bytes.clone());
+        Payloads::DefinitionManifest {
+            hash,
+            size,
+            chunk_hashes,
+        } => {
+            // Only what the host flies is handed out
+            if !state
+                .clients
+                .values()
+                .any(|client| client.addr == addr && client.is_host)
+            {
+                return;
+            }
+
+            let is_known = state
+                .definition
+                .as_ref()
+                .is_some_and(|definition| definition.get_hash() == hash);
+
+            if !is_known {
+                match ChunkedDefinition::from_manifest(hash.clone(), *size, chunk_hashes.clone()) {
+                    Ok(definition) => state.definition = Some(definition),
+                    Err(e) => {
+                        info!("Ignoring definitions: {}", e);
+                        return;
+                    }
+                }
+            }
+
+            // Whoever hosts now has them, the previous host may have left halfway through.
+            // Passed on even if known, the host sends it again for each joiner that got the key
+            state.definition_source = Some(addr);
+        }
+        Payloads::DefinitionChunk { hash, index, bytes } => {
+            if let Some(definition) = state.definition.as_mut() {
+                if definition.get_hash() == hash && state.definition_source == Some(addr) {
+                    definition.add_chunk(*index, bytes.clone(), Instant::now());
+                }
+            }
+
+            return;
+        }
+        Payloads::RequestDefinitionChunks { hash, chunks, .. } => {
+            // Whatever hasn't arrived from the host yet gets asked for again by the joiner
+            if let Some(definition) = state.definition.as_ref() {
+                if definition.get_hash() == hash {
+                    for index in chunks {
+                        if let Some(chunk) = definition.get_chunk(*index) {
+                            net.send_message(chunk, addr).ok();
+                        }
+                    }
+                }
+            }
+
+            return;
+        }
+        Payloads::Update { .. }
+        | Payloads::RequestPause { .. }
+        | Payloads::SetPause { .. }
+        | Payloads::SetWeather { .. }
+        | Payloads::Encrypted { .. } => {}
+        Payloads::KeyExchange { peer, data } => {
+            // Only members get a key, and only under the name they joined with
+            let Some((sender, from_host)) = state
+                .clients
+                .iter()
+                .find(|(_, client)| client.addr == addr)
+                .map(|(name, client)| (name.clone(), client.is_host))
+            else {
+                return;
+            };
+
+            // The host hands out the session key, everyone else asks it for one
+            let target = if from_host {
+                state.clients.get(peer).filter(|_| *peer != sender)
+            } else {
+                state.clients.values().find(|client| client.is_host)
+            };
+            let peer = if from_host { peer.clone() } else { sender };
+
+            match target {
+                Some(client) => {
+                    net.send_message(
+                        Payloads::KeyExchange {
+                            peer,
+                            data: data.clone(),
+                        },
+                        client.addr,
+                    )
+                    .ok();
+                }
+                // Got here before the host of a moved session, it's passed on once the host is in
+                None if !from_host => {
+                    state.waiting_for_host.insert(peer, data.clone());
+                }
+                None => {}
+            }
+
             return;
         }
-        Payloads::Update { .. } => {}
         Payloads::InitHandshake { name, version } => {
             if let Ok(version) = Version::from_str(version) {
                 let server_version =
@@ -112,7 +243,9 @@ fn process_payload(
             state.clients.insert(name.clone(), Client::new(addr));
 
             // If the client is the first one to connect, give them control and have them "host"
-            if state.in_control == SERVER_NAME {
+            if state.in_control == SERVER_NAME
+                && state.expected_host.is_none_or(|ip| ip == addr.ip())
+            {
                 set_host(name.clone(), state, net);
             }
 
@@ -128,17 +261,6 @@ fn process_payload(
                 net,
             );
 
-            // Send definitions to new client
-            if let Some(bytes) = state.aircraft_definition.as_ref() {
-                net.send_message(
-                    Payloads::AircraftDefinition {
-                        bytes: bytes.clone(),
-                    },
-                    addr,
-                )
-                .ok();
-            }
-
             info!("{} connected to hoster.", name);
 
             return;
@@ -189,12 +311,96 @@ fn process_payload(
     send_to_all(payload, Some(&addr), state, net);
 }
 
+// What the rendezvous server does, for clients that reached us over TCP or WebSocket because their UDP is blocked.
+// Only sessions hosted here can be joined this way
+fn process_stream_rendezvous(
+    addr: SocketAddr,
+    payload: Payloads,
+    servers: &mut Servers,
+    net: &mut SenderReceiver,
+) {
+    let session_id = match payload {
+        Payloads::RequestHosting {
+            self_hosted: false, ..
+        } => {
+            if servers.meta_state.clients_connected.len()
+                >= var("MAX_CLIENT_CONNECTIONS").unwrap().parse().unwrap()
+            {
+                net.send_message(
+                    Payloads::ConnectionDenied {
+                        reason: "Server at capacity.".to_string(),
+                    },
+                    addr,
+                )
+                .ok();
+                return;
+            }
+
+            let session_id = servers.reserve_server(var("SERVER_HOSTNAME").unwrap(), addr);
+            info!("Hosting session over stream as {}", session_id);
+
+            net.send_message(
+                Payloads::HostingReceived {
+                    session_id: session_id.clone(),
+                },
+                addr,
+            )
+            .ok();
+
+            session_id
+        }
+        Payloads::RendezvousHandshake { session_id, .. } => {
+            if !servers.meta_state.active_servers.contains_key(&session_id) {
+                net.send_message(
+                    Payloads::ConnectionDenied {
+                        reason: "Session not found, or can only be joined over UDP.".to_string(),
+                    },
+                    addr,
+                )
+                .ok();
+                return;
+            }
+
+            servers
+                .meta_state
+                .clients_connected
+                .insert(addr, session_id.clone());
+
+            session_id
+        }
+        _ => return,
+    };
+
+    info!("Stream client joining {}", session_id);
+
+    // Already connected to us, any address does as the client sends everything down the same connection
+    net.send_message(Payloads::AttemptConnection { peers: vec![addr] }, addr)
+        .ok();
+}
+
+fn get_stream_binds() -> Vec<(StreamKind, SocketAddr)> {
+    [
+        (StreamKind::Tcp, "HOSTER_TCP_PORT"),
+        (StreamKind::WebSocket, "HOSTER_WS_PORT"),
+    ]
+    .iter()
+    .filter_map(|(kind, var_name)| {
+        let port: u16 = var(var_name).ok()?.parse().ok()?;
+        Some((*kind, SocketAddr::from((Ipv6Addr::UNSPECIFIED, port))))
+    })
+    .collect()
+}
+
 fn set_host(name: String, state: &mut ServerState, net: &mut SenderReceiver) {
     let client = state.clients.get_mut(&name).expect("always there");
     client.is_observer = false;
     client.is_host = true;
 
     net.send_message(Payloads::SetHost, client.addr).ok();
+    for (peer, data) in state.waiting_for_host.drain() {
+        net.send_message(Payloads::KeyExchange { peer, data }, client.addr)
+            .ok();
+    }
     send_to_all(
         Payloads::TransferControl {
             from: state.in_control.clone(),
@@ -211,15 +417,52 @@ fn set_host(name: String, state: &mut ServerState, net: &mut SenderReceiver) {
 fn handle_heartbeats(servers: &mut HashMap<String, ServerState>, net: &mut SenderReceiver) {
     for (_, state) in servers.iter_mut() {
         if state.heartbeat_instant.elapsed().as_secs_f32() < 0.5 {
-            return;
+            continue;
         }
 
-        send_to_all(Payloads::Heartbeat, None, state, net);
+        // Each client gets their own heartbeat echoed back
+        for client in state.clients.values() {
+            net.send_message(
+                Payloads::Heartbeat {
+                    time: get_seconds(),
+                    echo: client.clock.get_echo(),
+                },
+                client.addr,
+            )
+            .ok();
+        }
 
         state.heartbeat_instant = Instant::now();
     }
 }
 
+// Fetches the host's definitions in chunks, the same way joiners do
+fn handle_definition_requests(
+    servers: &mut HashMap<String, ServerState>,
+    net: &mut SenderReceiver,
+) {
+    let now = Instant::now();
+
+    for (_, state) in servers.iter_mut() {
+        let (Some(definition), Some(source)) = (state.definition.as_mut(), state.definition_source)
+        else {
+            continue;
+        };
+
+        if let Some(chunks) = definition.next_request(now) {
+            net.send_message(
+                Payloads::RequestDefinitionChunks {
+                    from: SERVER_NAME.to_string(),
+                    hash: definition.get_hash().to_string(),
+                    chunks,
+                },
+                source,
+            )
+            .ok();
+        }
+    }
+}
+
 fn cleanup(servers: &mut Servers) {
     let server_states = &mut servers.server_states;
     let active_servers = &mut servers.meta_state.active_servers;
@@ -251,6 +494,11 @@ pub fn run_hoster(servers: Arc<Mutex<Servers>>, port: u16) {
 
     let mut net = SenderReceiver::from_socket(socket);
 
+    let stream_binds = get_stream_binds();
+    if !stream_binds.is_empty() {
+        net.add_stream(StreamTransport::listen(stream_binds, 5).expect("Failed to bind!"));
+    }
+
     let mut metrics_data = HashMap::new();
 
     let mut cleanup_timer = Instant::now();
@@ -280,6 +528,13 @@ pub fn run_hoster(servers: Arc<Mutex<Servers>>, port: u16) {
                         }
                     }
 
+                    if net.is_stream_peer(&addr)
+                        && !servers.meta_state.clients_connected.contains_key(&addr)
+                    {
+                        process_stream_rendezvous(addr, payload, &mut servers, &mut net);
+                        continue;
+                    }
+
                     // Get server state for session
                     if let Some(session) = servers.meta_state.clients_connected.get(&addr) {
                         let session = session.clone();
@@ -347,6 +602,7 @@ pub fn run_hoster(servers: Arc<Mutex<Servers>>, port: u16) {
         }
 
         handle_heartbeats(&mut servers.server_states, &mut net);
+        handle_definition_requests(&mut servers.server_states, &mut net);
 
         drop(servers);
 
@@ -41,5 +41,6 @@ pub fn main() {
     run_rendezvous(
         servers,
         dotenv::var("SERVER_PORT").unwrap().parse().unwrap(),
+        dotenv::var("SERVER_PROBE_PORT").unwrap().parse().unwrap(),
     );
 }
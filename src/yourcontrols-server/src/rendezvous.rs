@@ -5,7 +5,7 @@ use dotenv::var;
 use laminar::Socket;
 use log::info;
 use std::{
-    net::{SocketAddr, SocketAddrV4},
+    net::SocketAddr,
     sync::{Arc, Mutex},
     thread::sleep,
     time::{Duration, Instant},
@@ -32,22 +32,41 @@ fn resolve_hoster_address(incoming_addr: SocketAddr, hostname: &str) -> SocketAd
     .unwrap_or_else(|_| var("HOSTER_IP").unwrap().parse().unwrap())
 }
 
-fn correct_ipv6_address(need_ipv4: bool, addr: SocketAddr) -> SocketAddr {
-    if need_ipv4 {
-        if let SocketAddr::V6(addrv6) = addr {
-            return SocketAddr::V4(SocketAddrV4::new(
-                addrv6.ip().to_ipv4().unwrap(),
-                addr.port(),
-            ));
+// Where we saw the peer first, then what it reported for each family
+fn get_candidates(
+    addr: SocketAddr,
+    local_endpoint: Option<SocketAddr>,
+    local_endpoint_v6: Option<SocketAddr>,
+) -> Vec<SocketAddr> {
+    let mut candidates = vec![addr];
+
+    for endpoint in local_endpoint.into_iter().chain(local_endpoint_v6) {
+        if !candidates.contains(&endpoint) {
+            candidates.push(endpoint);
         }
     }
-    addr
+
+    candidates
+}
+
+// Only the candidates the other side has a family for
+fn get_reachable(candidates: &[SocketAddr], other: &[SocketAddr]) -> Vec<SocketAddr> {
+    candidates
+        .iter()
+        .filter(|candidate| {
+            other
+                .iter()
+                .any(|addr| is_actually_ipv4(*addr) == is_actually_ipv4(**candidate))
+        })
+        .copied()
+        .collect()
 }
 
 fn process_message(
     addr: SocketAddr,
     message: Payloads,
     net: &mut SenderReceiver,
+    probe_net: &mut SenderReceiver,
     sessions: &mut Sessions,
     counters: &mut Counters,
     servers: &mut Arc<Mutex<Servers>>,
@@ -56,6 +75,7 @@ fn process_message(
         Payloads::RendezvousHandshake {
             session_id,
             local_endpoint,
+            local_endpoint_v6,
         } => {
             let state = &mut servers.lock().unwrap().meta_state;
 
@@ -67,17 +87,23 @@ fn process_message(
                     counters.get_id_for_addr(&addr.ip()),
                     session_id
                 );
-                // Sanity check ip version matches
-                let server_is_using_ipv4 =
-                    is_actually_ipv4(server_connection_info.hoster_endpoints[0]); // Relies on the 1st element to be external ip
-                let client_is_using_ipv4 = is_actually_ipv4(addr);
+                // Pair on whichever address family both have
+                let hoster_endpoints = &server_connection_info.hoster_endpoints;
+                let client_endpoints = get_candidates(addr, local_endpoint, local_endpoint_v6);
+
+                let to_client = get_reachable(hoster_endpoints, &client_endpoints);
+                let to_hoster = get_reachable(&client_endpoints, hoster_endpoints);
 
-                if server_is_using_ipv4 != client_is_using_ipv4 {
+                if to_client.is_empty() || to_hoster.is_empty() {
                     net.send_message(
                         Payloads::ConnectionDenied {
                             reason: format!(
-                                "Server is using {}",
-                                if server_is_using_ipv4 { "IPv4" } else { "IPv6" }
+                                "Host is only reachable over {}",
+                                if is_actually_ipv4(hoster_endpoints[0]) {
+                                    "IPv4"
+                                } else {
+                                    "IPv6"
+                                }
                             ),
                         },
                         addr,
@@ -86,25 +112,12 @@ fn process_message(
                     return;
                 }
                 // Send data to client
-                net.send_message(
-                    Payloads::AttemptConnection {
-                        peers: server_connection_info.hoster_endpoints.clone(),
-                    },
-                    addr,
-                )
-                .ok();
+                net.send_message(Payloads::AttemptConnection { peers: to_client }, addr)
+                    .ok();
 
                 // Send data to hoster
                 net.send_message(
-                    Payloads::AttemptConnection {
-                        peers: vec![
-                            Some(correct_ipv6_address(client_is_using_ipv4, addr)),
-                            local_endpoint,
-                        ]
-                        .into_iter()
-                        .flatten()
-                        .collect(),
-                    },
+                    Payloads::AttemptConnection { peers: to_hoster },
                     server_connection_info.hoster_addr,
                 )
                 .ok();
@@ -144,19 +157,14 @@ fn process_message(
         Payloads::RequestHosting {
             self_hosted,
             local_endpoint,
+            local_endpoint_v6,
         } => {
             let session_id;
 
             if self_hosted {
                 session_id = sessions.map_session_id_to_socket_info(
                     addr,
-                    vec![
-                        Some(correct_ipv6_address(is_actually_ipv4(addr), addr)),
-                        local_endpoint,
-                    ]
-                    .into_iter()
-                    .flatten()
-                    .collect(),
+                    get_candidates(addr, local_endpoint, local_endpoint_v6),
                 );
                 info!(
                     "Self hosted session created with hoster {} as {}",
@@ -202,16 +210,107 @@ fn process_message(
                 .ok();
         }
 
+        Payloads::RequestHosterFallback { session_id } => {
+            let mut servers = servers.lock().unwrap();
+
+            let Some(members) = sessions.get_session_members(&session_id, &addr) else {
+                net.send_message(
+                    Payloads::ConnectionDenied {
+                        reason: "Session not found.".to_string(),
+                    },
+                    addr,
+                )
+                .ok();
+                return;
+            };
+            // Everyone moves over, the hoster has to have room for all of them
+            if servers.meta_state.clients_connected.len() + members.len()
+                > var("MAX_CLIENT_CONNECTIONS").unwrap().parse().unwrap()
+            {
+                net.send_message(
+                    Payloads::ConnectionDenied {
+                        reason: "Could not connect to the host, and the server is at capacity."
+                            .to_string(),
+                    },
+                    addr,
+                )
+                .ok();
+                return;
+            }
+
+            let server_hostname = var("SERVER_HOSTNAME").unwrap();
+            servers.reserve_server_for_session(
+                server_hostname.clone(),
+                session_id.clone(),
+                members[0].ip(),
+            );
+            sessions.close_session(&session_id);
+
+            info!(
+                "{} could not punch through, moving {} with {} members to the hoster",
+                counters.get_id_for_addr(&addr.ip()),
+                session_id,
+                members.len()
+            );
+
+            for member in members {
+                servers
+                    .meta_state
+                    .unknown_clients
+                    .insert(member.ip(), session_id.clone());
+
+                net.send_message(
+                    Payloads::AttemptHosterConnection {
+                        peer: resolve_hoster_address(member, &server_hostname),
+                    },
+                    member,
+                )
+                .ok();
+            }
+        }
+
+        Payloads::NatProbe { change_port } => {
+            // Answering from the other port shows whether the client's NAT lets in unknown senders
+            let responder = if change_port { probe_net } else { net };
+            responder
+                .send_message(Payloads::NatProbeResponse { observed: addr }, addr)
+                .ok();
+        }
+
         _ => {}
     }
 }
 
-pub fn run_rendezvous(servers: Arc<Mutex<Servers>>, port: u16) {
+// The second port only tells clients where it sees them from, to compare with the first
+fn answer_probes(probe_net: &mut SenderReceiver, counters: &mut Counters) {
+    loop {
+        match probe_net.get_next_message() {
+            Ok(Message::Payload(addr, Payloads::NatProbe { .. })) => {
+                if counters.get_request_count_for(&addr.ip()) > MAX_REQUESTS_PER_HOUR {
+                    continue;
+                }
+                probe_net
+                    .send_message(Payloads::NatProbeResponse { observed: addr }, addr)
+                    .ok();
+                counters.increment_request_counter(addr.ip());
+            }
+            Err(Error::ReadTimeout(_)) => break,
+            _ => {}
+        }
+    }
+}
+
+pub fn run_rendezvous(servers: Arc<Mutex<Servers>>, port: u16, probe_port: u16) {
     let socket = Socket::from_udp_socket(get_socket_duplex(port), get_socket_config(3))
         .expect("Failed to bind!");
     info!("Server started on {}!", socket.local_addr().unwrap());
 
+    let probe_socket = Socket::from_udp_socket(get_socket_duplex(probe_port), get_socket_config(3))
+        .expect("Failed to bind probe port!");
+    info!("Probe started on {}!", probe_socket.local_addr().unwrap());
+
     let mut net = SenderReceiver::from_socket(socket);
+    let mut probe_net = SenderReceiver::from_socket(probe_socket);
     let mut sessions = Sessions::new();
 
     let mut counters = Counters::new();
@@ -220,6 +319,9 @@ pub fn run_rendezvous(servers: Arc<Mutex<Servers>>, port: u16) {
 
     loop {
         net.poll();
+        probe_net.poll();
+
+        answer_probes(&mut probe_net, &mut counters);
 
         loop {
             match net.get_next_message() {
@@ -232,6 +334,7 @@ pub fn run_rendezvous(servers: Arc<Mutex<Servers>>, port: u16) {
                         addr,
                         message,
                         &mut net,
+                        &mut probe_net,
                         &mut sessions,
                         &mut counters,
                         &mut servers,
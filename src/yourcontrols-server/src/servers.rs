@@ -3,6 +3,7 @@ use std::{
     net::{IpAddr, SocketAddr},
     time::Instant,
 };
+use yourcontrols_net::{ChunkedDefinition, PeerClock};
 
 use crate::util::{get_random_id, SESSION_ID_LENGTH};
 
@@ -10,6 +11,8 @@ pub struct Client {
     pub addr: SocketAddr,
     pub is_observer: bool,
     pub is_host: bool,
+    // Offset of the client's clock, echoed so the client can send its update times in ours
+    pub clock: PeerClock,
 }
 
 impl Client {
@@ -18,13 +21,20 @@ impl Client {
             addr,
             is_observer: false,
             is_host: false,
+            clock: PeerClock::new(),
         }
     }
 }
 
 pub struct ServerState {
     pub clients: HashMap<String, Client>,
-    pub aircraft_definition: Option<Box<[u8]>>,
+    // Fetched from the host once, then handed out to everyone who joins
+    pub definition: Option<ChunkedDefinition>,
+    pub definition_source: Option<SocketAddr>,
+    // Sessions moved here from self hosting keep their host, whoever gets here first
+    pub expected_host: Option<IpAddr>,
+    // Key requests from members that got here before the host, by name
+    pub waiting_for_host: HashMap<String, Vec<u8>>,
     pub in_control: String,
     pub heartbeat_instant: Instant,
     pub started_at: Instant,
@@ -35,7 +45,10 @@ impl ServerState {
         Self {
             clients: HashMap::new(),
             in_control: "SERVER".to_string(),
-            aircraft_definition: None,
+            definition: None,
+            definition_source: None,
+            expected_host: None,
+            waiting_for_host: HashMap::new(),
             heartbeat_instant: Instant::now(),
             started_at: Instant::now(),
         }
@@ -97,6 +110,23 @@ impl Servers {
         id
     }
 
+    // Takes over a self hosted session under the same id, so the join code everyone has still works
+    pub fn reserve_server_for_session(
+        &mut self,
+        hostname: String,
+        session_id: String,
+        host_ip: IpAddr,
+    ) {
+        let mut state = ServerState::new();
+        state.expected_host = Some(host_ip);
+
+        self.meta_state
+            .active_servers
+            .insert(session_id.clone(), ServerInfo::new(hostname));
+
+        self.server_states.insert(session_id, state);
+    }
+
     pub fn remove_server(&mut self, session_id: &String) {
         self.meta_state.active_servers.remove(session_id);
         self.server_states.remove(session_id);
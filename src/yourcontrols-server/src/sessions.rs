@@ -62,6 +62,27 @@ impl Sessions {
         self.connected_sessions.remove(addr)
     }
 
+    // The hoster and everyone that joined through us, if the client asking is one of them
+    pub fn get_session_members(
+        &self,
+        session_id: &str,
+        addr: &SocketAddr,
+    ) -> Option<Vec<SocketAddr>> {
+        if self.connected_sessions.get(addr).map(String::as_str) != Some(session_id) {
+            return None;
+        }
+
+        let mut members = vec![self.session_info.get(session_id)?.hoster_addr];
+        members.extend(
+            self.connected_sessions
+                .iter()
+                .filter(|(_, connected_session_id)| *connected_session_id == session_id)
+                .map(|(addr, _)| *addr),
+        );
+
+        Some(members)
+    }
+
     pub fn socket_is_hosting(&self, socket: &SocketAddr) -> bool {
         self.hosting_sessions.contains_left(socket)
     }
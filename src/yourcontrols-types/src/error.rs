@@ -13,9 +13,12 @@ pub enum Error {
     LocalAddrNotFound,
     LocalAddrNotIPv4(String),
     AddPortError(igd::AddPortError),
+    CryptoError(String),
+    InvalidPayload(String),
 
     ReadTimeout(TryRecvError),
     // Port forwarding
+    PortMapError(String),
 
     // Definitions
     YamlError(serde_yaml::Error, String),
@@ -25,6 +28,7 @@ pub enum Error {
     IncludeError(String, String),
 
     MissingMapping(String),
+    InvalidDefinitionTransfer(String),
     // Serialization
     JSONSerializeError(serde_json::Error),
     NetDecodeError(rmp_serde::decode::Error),
@@ -51,6 +55,9 @@ impl Display for Error {
             Error::LocalAddrNotFound => write!(f, "Could not get local address."),
             Error::AddPortError(e) => write!(f, "Could not add port: {}", e),
             Error::LocalAddrNotIPv4(parse_string) => write!(f, "{} is not IPv4", parse_string),
+            Error::CryptoError(e) => write!(f, "Encryption failed: {}", e),
+            Error::InvalidPayload(e) => write!(f, "Rejected payload: {}", e),
+            Error::PortMapError(e) => write!(f, "Could not map port: {}", e),
 
             Error::MissingField(s) => write!(f, r#"Missing field "{}""#, s),
             Error::InvalidSyncType(s) => write!(f, r#"Invalid type "{}""#, s),
@@ -64,6 +71,9 @@ impl Display for Error {
                 "No definition exists for {}. Do you have matching .yaml files?",
                 mapping_name
             ),
+            Error::InvalidDefinitionTransfer(e) => {
+                write!(f, "Aircraft definitions did not arrive intact: {}", e)
+            }
 
             Error::JSONSerializeError(e) => {
                 write!(f, "Could not serialize/deserialize! Reason: {}", e)
@@ -2,6 +2,7 @@ mod error;
 
 pub use error::Error;
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, fmt::Display};
 
@@ -27,12 +28,13 @@ pub enum Event {
     },
 }
 
-#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, PartialOrd)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq, PartialOrd)]
 pub enum VarReaderTypes {
     Bool(bool),
     I32(i32),
     I64(i64),
     F64(f64),
+    String(String),
 }
 
 impl VarReaderTypes {
@@ -42,6 +44,7 @@ impl VarReaderTypes {
             VarReaderTypes::I32(v) => *v as f64,
             VarReaderTypes::I64(v) => *v as f64,
             VarReaderTypes::F64(v) => *v,
+            VarReaderTypes::String(v) => v.parse().unwrap_or(0.0),
         }
     }
 }
@@ -53,20 +56,52 @@ impl Display for VarReaderTypes {
             VarReaderTypes::I32(v) => f.write_str(&v.to_string()),
             VarReaderTypes::I64(v) => f.write_str(&v.to_string()),
             VarReaderTypes::F64(v) => f.write_str(&v.to_string()),
+            VarReaderTypes::String(v) => f.write_str(v),
         }
     }
 }
 
+// Lamport timestamp of a shared var update, ties are broken by the peer that made the change
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VarStamp {
+    pub clock: u64,
+    pub peer: u32,
+}
+
+// Conditions around the host's aircraft, published so everyone can fly in the same weather
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub struct WeatherSample {
+    // Millibars
+    pub ambient_pressure: f64,
+    // Knots
+    pub wind_velocity: f64,
+    // Degrees true, where the wind is coming from
+    pub wind_direction: f64,
+    // Celsius
+    pub temperature: f64,
+    // Altimeter setting in millibars
+    pub kohlsman: f64,
+}
+
 // Name of variable and the value of it
 pub type VarMap = HashMap<String, VarReaderTypes>;
 // Name of the event the DWORD data associated with it with how many times it got triggered (not a map as the event could've got triggered multiple times before the data could get send)
 pub type EventData = Vec<Event>;
 
+// Most vars or events of one kind an update may carry, well past what any aircraft syncs
+pub const MAX_SYNC_ENTRIES: usize = 16 * 1024;
+
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct AllNeedSync {
     pub avars: VarMap,
     pub lvars: VarMap,
     pub events: EventData,
+    // Stamps for shared vars in avars/lvars
+    #[serde(default)]
+    pub stamps: HashMap<String, VarStamp>,
+    // Values jumped rather than changed smoothly, set them directly instead of interpolating
+    #[serde(default)]
+    pub snap: bool,
 }
 
 impl AllNeedSync {
@@ -78,10 +113,19 @@ impl AllNeedSync {
         self.avars.len() == 0 && self.lvars.len() == 0 && self.events.len() == 0
     }
 
+    pub fn is_within_limits(&self) -> bool {
+        self.avars.len() <= MAX_SYNC_ENTRIES
+            && self.lvars.len() <= MAX_SYNC_ENTRIES
+            && self.events.len() <= MAX_SYNC_ENTRIES
+            && self.stamps.len() <= MAX_SYNC_ENTRIES
+    }
+
     pub fn clear(&mut self) {
         self.avars.clear();
         self.lvars.clear();
         self.events.clear();
+        self.stamps.clear();
+        self.snap = false;
     }
 
     // Filter the variables
@@ -98,6 +142,7 @@ impl AllNeedSync {
         F: Fn(&str) -> bool,
     {
         let mut filtered = AllNeedSync::new();
+        filtered.snap = self.snap;
 
         macro_rules! filter_or_op {
             ($name: expr, $op: block) => {
@@ -132,6 +177,9 @@ impl AllNeedSync {
         self.lvars
             .retain(|name, var| filter_or_insert!(name, lvars, var));
 
+        self.stamps
+            .retain(|name, stamp| filter_or_insert!(name, stamps, stamp));
+
         self.events.retain(|event| match event {
             Event::JSEvent { name } => filter_or_push!(name, events, event),
             Event::JSInput { id, .. } => filter_or_push!(id, events, event),
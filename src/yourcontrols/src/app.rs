@@ -1,3 +1,4 @@
+use crate::emulator::EmulatorValue;
 use crate::simconfig;
 
 use base64::Engine;
@@ -15,6 +16,7 @@ use std::{
     },
     thread,
 };
+use yourcontrols_net::{DiscoveredSession, NatType};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -24,6 +26,35 @@ pub enum ConnectionMethod {
     CloudServer,
 }
 
+impl ConnectionMethod {
+    // Best way to host from behind this NAT
+    pub fn recommended_for(nat_type: NatType) -> Self {
+        if nat_type == NatType::Open {
+            ConnectionMethod::Direct
+        } else if nat_type.can_hole_punch() {
+            ConnectionMethod::CloudServer
+        } else {
+            ConnectionMethod::Relay
+        }
+    }
+
+    // Why this method is unlikely to work from behind this NAT
+    pub fn get_nat_warning(&self, nat_type: NatType, is_host: bool) -> Option<&'static str> {
+        match self {
+            ConnectionMethod::Direct if is_host && !nat_type.accepts_unsolicited() => {
+                Some("Others can only reach a Direct server from behind this NAT if the port gets forwarded.")
+            }
+            ConnectionMethod::CloudServer if is_host && !nat_type.can_hole_punch() => {
+                Some("Cloud P2P can't get through this NAT, Cloud Host is recommended.")
+            }
+            ConnectionMethod::CloudServer if !nat_type.can_hole_punch() => {
+                Some("Joining a Cloud P2P session may fail from behind this NAT, the host can use Cloud Host instead.")
+            }
+            _ => None,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type", rename_all = "camelCase")]
 pub enum AppMessage {
@@ -55,6 +86,9 @@ pub enum AppMessage {
         sim: String,
     },
     Disconnect,
+    // Join screen is looking for Direct servers on the LAN
+    StartLanDiscovery,
+    StopLanDiscovery,
     Startup,
     RunUpdater,
     ForceTakeControl,
@@ -71,7 +105,7 @@ pub enum AppMessage {
     },
     EmulatorSetVar {
         name: String,
-        value: f64,
+        value: EmulatorValue,
     },
 }
 
@@ -207,6 +241,14 @@ impl App {
         self.invoke("connected", None);
     }
 
+    pub fn reconnecting(&self) {
+        self.invoke("reconnecting", None);
+    }
+
+    pub fn resumed(&self) {
+        self.invoke("resumed", None);
+    }
+
     pub fn server_fail(&self, reason: &str) {
         self.invoke("server_fail", Some(reason));
     }
@@ -227,6 +269,24 @@ impl App {
         self.invoke("server", None);
     }
 
+    pub fn port_forwarded(&self, method: &str, external_port: u16) {
+        self.invoke(
+            "port_forwarded",
+            Some(
+                json!({
+                    "method": method,
+                    "externalPort": external_port,
+                })
+                .to_string()
+                .as_str(),
+            ),
+        )
+    }
+
+    pub fn port_forward_failed(&self, reason: &str) {
+        self.invoke("port_forward_failed", Some(reason));
+    }
+
     pub fn set_session_code(&self, code: &str) {
         self.invoke("session", Some(code));
     }
@@ -283,7 +343,7 @@ impl App {
         self.invoke("config_msg", Some(value));
     }
 
-    pub fn send_network(&self, metrics: &Metrics) {
+    pub fn send_network(&self, metrics: &Metrics, buffer_depth: usize, playout_delay: f64) {
         self.invoke(
             "metrics",
             Some(
@@ -293,7 +353,9 @@ impl App {
                     "sentBandwidth": metrics.sent_kbps,
                     "receiveBandwidth": metrics.receive_kbps,
                     "packetLoss": metrics.packet_loss,
-                    "ping": metrics.rtt/2.0
+                    "ping": metrics.rtt/2.0,
+                    "bufferDepth": buffer_depth,
+                    "playoutDelay": playout_delay
                 })
                 .to_string()
                 .as_str(),
@@ -301,6 +363,42 @@ impl App {
         )
     }
 
+    pub fn nat_detected(&self, nat_type: NatType, recommended: &ConnectionMethod) {
+        self.invoke(
+            "nat_type",
+            Some(
+                json!({
+                    "natType": nat_type.to_string(),
+                    "recommended": recommended,
+                })
+                .to_string()
+                .as_str(),
+            ),
+        )
+    }
+
+    pub fn lan_sessions(&self, sessions: &[DiscoveredSession]) {
+        self.invoke("lan_sessions", Some(json!(sessions).to_string().as_str()));
+    }
+
+    pub fn definition_progress(&self, received: u32, total: u32) {
+        self.invoke(
+            "definition_progress",
+            Some(
+                json!({
+                    "received": received,
+                    "total": total,
+                })
+                .to_string()
+                .as_str(),
+            ),
+        )
+    }
+
+    pub fn weather_warning(&self, warnings: &[String]) {
+        self.invoke("weather", Some(json!(warnings).to_string().as_str()));
+    }
+
     pub fn set_host(&self) {
         self.invoke("host", None);
     }
@@ -1,4 +1,4 @@
-use clap::{value_parser, Parser, ValueEnum};
+use clap::{value_parser, Parser, Subcommand, ValueEnum};
 
 use crate::app::ConnectionMethod;
 use crate::simconfig::Config;
@@ -65,6 +65,18 @@ struct Cli {
 
     #[arg(long, help = "Log to the terminal in addition to log.txt.")]
     log_console: bool,
+
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+}
+
+#[derive(Subcommand, Debug)]
+enum CliCommand {
+    #[command(about = "Print the JSON Schema for definition files and exit.")]
+    DefinitionSchema {
+        #[arg(long, help = "Write the schema to this file instead of stdout.")]
+        output: Option<String>,
+    },
 }
 
 #[derive(Copy, Clone, Debug, ValueEnum)]
@@ -111,6 +123,17 @@ impl CliWrapper {
         self.cli.log_console
     }
 
+    pub fn is_definition_schema_command(&self) -> bool {
+        matches!(self.cli.command, Some(CliCommand::DefinitionSchema { .. }))
+    }
+
+    pub fn definition_schema_output(&self) -> Option<&str> {
+        match self.cli.command.as_ref() {
+            Some(CliCommand::DefinitionSchema { output }) => output.as_deref(),
+            None => None,
+        }
+    }
+
     pub fn connection_method(&self) -> ConnectionMethod {
         match self.cli.connection_method {
             CliConnectionMethod::Direct => ConnectionMethod::Direct,
@@ -1,45 +1,138 @@
-#![allow(dead_code)]
-
 use simconnect::SimConnector;
 use yourcontrols_types::{VarMap, VarReaderTypes};
 
 use crate::sync::transfer::AircraftVars;
 use crate::util::InDataTypes;
 
+const ALTITUDE_VAR: &str = "PLANE ALTITUDE";
+const VELOCITY_VARS: [&str; 3] = ["VELOCITY BODY X", "VELOCITY BODY Y", "VELOCITY BODY Z"];
+const WIND_VARS: [&str; 3] = ["AIRCRAFT WIND X", "AIRCRAFT WIND Y", "AIRCRAFT WIND Z"];
+// Sent alongside the altitude so the receiver knows what to correct against
+pub const GROUND_ALTITUDE_REF: &str = "CORRECTOR GROUND ALTITUDE";
+pub const SEA_LEVEL_PRESSURE_REF: &str = "CORRECTOR SEA LEVEL PRESSURE";
+// Altitude change for one millibar near sea level
+const FEET_PER_MILLIBAR: f64 = 27.3;
+
 #[derive(Default)]
 struct Current {
-    wind_z: f64,
+    wind: [f64; 3],
+    ground_altitude: Option<f64>,
+    sea_level_pressure: Option<f64>,
 }
 
-// In order to synchronize groundspeed in different winds, the Z component is removed remotely and added locally
-// Because A:PLANE ALT ABOVE GROUND is not directly setable by execute_calculate_code, the logic is done in this struct instead where the ground altitude is removed remotely below 1000 feet, and added back locally.
+// In order to synchronize groundspeed in different winds, the wind components are removed remotely and added locally
+// Because A:PLANE ALT ABOVE GROUND is not directly setable by execute_calculate_code, the logic is done in this struct instead where the ground altitude is removed remotely below some height above ground, and added back locally.
+// Above that the altitude is corrected for the difference in sea level pressure instead, so both altimeters read the same
 pub struct Corrector {
     avars: AircraftVars,
     current: Current,
+    wind: bool,
+    terrain_below_agl: Option<f64>,
+    barometric: bool,
 }
 
 impl Corrector {
-    pub fn new(define_id: u32) -> Self {
+    pub fn new(
+        define_id: u32,
+        wind: bool,
+        terrain_below_agl: Option<f64>,
+        barometric: bool,
+    ) -> Self {
         let mut avars = AircraftVars::new(define_id);
 
-        avars.add_var("AIRCRAFT WIND Z", "Feet per second", InDataTypes::F64);
+        for var_name in WIND_VARS {
+            avars.add_var(var_name, "Feet per second", InDataTypes::F64);
+        }
+        avars.add_var("GROUND ALTITUDE", "Feet", InDataTypes::F64);
+        avars.add_var("SEA LEVEL PRESSURE", "Millibars", InDataTypes::F64);
 
         Self {
             avars,
             current: Current::default(),
+            wind,
+            terrain_below_agl,
+            barometric,
+        }
+    }
+
+    // Vars that need to travel with the altitude
+    pub fn get_reference_vars(&self) -> Vec<&'static str> {
+        let mut vars = Vec::new();
+
+        if self.terrain_below_agl.is_some() {
+            vars.push(GROUND_ALTITUDE_REF);
+        }
+
+        if self.barometric {
+            vars.push(SEA_LEVEL_PRESSURE_REF);
         }
+
+        vars
     }
 
     pub fn remove_components(&self, data: &mut VarMap) {
-        if let Some(VarReaderTypes::F64(velocity)) = data.get_mut("VELOCITY BODY Z") {
-            *velocity -= self.current.wind_z
+        if self.wind {
+            for (var_name, wind) in VELOCITY_VARS.iter().zip(self.current.wind) {
+                if let Some(VarReaderTypes::F64(velocity)) = data.get_mut(*var_name) {
+                    *velocity -= wind
+                }
+            }
+        }
+
+        if !data.contains_key(ALTITUDE_VAR) {
+            return;
+        }
+
+        if let (Some(_), Some(ground)) = (self.terrain_below_agl, self.current.ground_altitude) {
+            data.insert(GROUND_ALTITUDE_REF.to_string(), VarReaderTypes::F64(ground));
+        }
+
+        if let (true, Some(pressure)) = (self.barometric, self.current.sea_level_pressure) {
+            data.insert(
+                SEA_LEVEL_PRESSURE_REF.to_string(),
+                VarReaderTypes::F64(pressure),
+            );
         }
     }
 
     pub fn add_components(&self, data: &mut VarMap) {
-        if let Some(VarReaderTypes::F64(velocity)) = data.get_mut("VELOCITY BODY Z") {
-            *velocity += self.current.wind_z
+        if self.wind {
+            for (var_name, wind) in VELOCITY_VARS.iter().zip(self.current.wind) {
+                if let Some(VarReaderTypes::F64(velocity)) = data.get_mut(*var_name) {
+                    *velocity += wind
+                }
+            }
+        }
+
+        let remote_ground = data.remove(GROUND_ALTITUDE_REF).map(|x| x.get_as_f64());
+        let remote_pressure = data.remove(SEA_LEVEL_PRESSURE_REF).map(|x| x.get_as_f64());
+
+        let Some(VarReaderTypes::F64(altitude)) = data.get_mut(ALTITUDE_VAR) else {
+            return;
+        };
+
+        let mut offset = 0.0;
+        // How much the ground is followed instead of the pressure, all of it on the ground and none at the threshold
+        let mut terrain_weight = 0.0;
+
+        if let (Some(threshold), Some(remote), Some(local)) = (
+            self.terrain_below_agl,
+            remote_ground,
+            self.current.ground_altitude,
+        ) {
+            terrain_weight = (1.0 - (*altitude - remote) / threshold).clamp(0.0, 1.0);
+            offset += terrain_weight * (local - remote);
+        }
+
+        if let (true, Some(remote), Some(local)) = (
+            self.barometric,
+            remote_pressure,
+            self.current.sea_level_pressure,
+        ) {
+            offset += (1.0 - terrain_weight) * (local - remote) * FEET_PER_MILLIBAR;
         }
+
+        *altitude += offset;
     }
 
     pub fn on_connected(&self, conn: &SimConnector) {
@@ -62,9 +155,131 @@ impl Corrector {
         }
 
         if let Ok(data) = self.avars.read_vars(data) {
-            if let Some(VarReaderTypes::F64(velocity)) = data.get("AIRCRAFT WIND Z") {
-                self.current.wind_z = *velocity;
+            self.on_vars_change(&data);
+        }
+    }
+
+    fn on_vars_change(&mut self, data: &VarMap) {
+        for (wind, var_name) in self.current.wind.iter_mut().zip(WIND_VARS) {
+            if let Some(VarReaderTypes::F64(velocity)) = data.get(var_name) {
+                *wind = *velocity;
             }
         }
+
+        if let Some(VarReaderTypes::F64(altitude)) = data.get("GROUND ALTITUDE") {
+            self.current.ground_altitude = Some(*altitude);
+        }
+
+        if let Some(VarReaderTypes::F64(pressure)) = data.get("SEA LEVEL PRESSURE") {
+            self.current.sea_level_pressure = Some(*pressure);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_corrector(ground_altitude: f64, sea_level_pressure: f64, wind: [f64; 3]) -> Corrector {
+        let mut corrector = Corrector::new(2, true, Some(1000.0), true);
+
+        let mut data = VarMap::new();
+        for (var_name, wind) in WIND_VARS.iter().zip(wind) {
+            data.insert(var_name.to_string(), VarReaderTypes::F64(wind));
+        }
+        data.insert(
+            "GROUND ALTITUDE".to_string(),
+            VarReaderTypes::F64(ground_altitude),
+        );
+        data.insert(
+            "SEA LEVEL PRESSURE".to_string(),
+            VarReaderTypes::F64(sea_level_pressure),
+        );
+        corrector.on_vars_change(&data);
+
+        corrector
+    }
+
+    fn altitude(altitude: f64) -> VarMap {
+        let mut data = VarMap::new();
+        data.insert(ALTITUDE_VAR.to_string(), VarReaderTypes::F64(altitude));
+        data
+    }
+
+    fn transfer(from: &Corrector, to: &Corrector, mut data: VarMap) -> VarMap {
+        from.remove_components(&mut data);
+        to.add_components(&mut data);
+        data
+    }
+
+    fn get(data: &VarMap, var_name: &str) -> f64 {
+        data.get(var_name).unwrap().get_as_f64()
+    }
+
+    #[test]
+    fn test_wind_components() {
+        let sender = get_corrector(0.0, 1013.0, [1.0, 2.0, 10.0]);
+        let receiver = get_corrector(0.0, 1013.0, [-1.0, 0.0, -5.0]);
+
+        let mut data = VarMap::new();
+        for var_name in VELOCITY_VARS {
+            data.insert(var_name.to_string(), VarReaderTypes::F64(100.0));
+        }
+
+        let data = transfer(&sender, &receiver, data);
+
+        assert_eq!(get(&data, "VELOCITY BODY X"), 98.0);
+        assert_eq!(get(&data, "VELOCITY BODY Y"), 98.0);
+        assert_eq!(get(&data, "VELOCITY BODY Z"), 85.0);
+    }
+
+    #[test]
+    fn test_ground_follows_terrain() {
+        // Receiver's runway sits 30 feet higher
+        let sender = get_corrector(500.0, 1013.0, [0.0; 3]);
+        let receiver = get_corrector(530.0, 1013.0, [0.0; 3]);
+
+        // Sitting on the runway
+        let data = transfer(&sender, &receiver, altitude(500.0));
+
+        assert_eq!(get(&data, ALTITUDE_VAR), 530.0);
+        // References never reach the sim
+        assert!(!data.contains_key(GROUND_ALTITUDE_REF));
+        assert!(!data.contains_key(SEA_LEVEL_PRESSURE_REF));
+    }
+
+    #[test]
+    fn test_blends_into_pressure_altitude() {
+        // 10 millibars higher pressure at the receiver
+        let sender = get_corrector(500.0, 1003.0, [0.0; 3]);
+        let receiver = get_corrector(530.0, 1013.0, [0.0; 3]);
+
+        // Halfway up the blend
+        let data = transfer(&sender, &receiver, altitude(1000.0));
+        assert!((get(&data, ALTITUDE_VAR) - (1000.0 + 15.0 + 136.5)).abs() < 1e-9);
+
+        // Above it only the pressure counts
+        let data = transfer(&sender, &receiver, altitude(10000.0));
+        assert!((get(&data, ALTITUDE_VAR) - 10273.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_disabled_corrections() {
+        let mut sender = get_corrector(500.0, 1003.0, [0.0, 0.0, 10.0]);
+        let mut receiver = get_corrector(530.0, 1013.0, [0.0; 3]);
+        for corrector in [&mut sender, &mut receiver] {
+            corrector.wind = false;
+            corrector.terrain_below_agl = None;
+            corrector.barometric = false;
+        }
+
+        let mut data = altitude(505.0);
+        data.insert("VELOCITY BODY Z".to_string(), VarReaderTypes::F64(100.0));
+
+        let data = transfer(&sender, &receiver, data);
+
+        assert_eq!(get(&data, ALTITUDE_VAR), 505.0);
+        assert_eq!(get(&data, "VELOCITY BODY Z"), 100.0);
+        assert_eq!(data.len(), 2);
     }
 }
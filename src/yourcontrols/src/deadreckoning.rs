@@ -0,0 +1,405 @@
+use std::{
+    collections::HashMap,
+    f64::consts::PI,
+    time::{Duration, Instant},
+};
+
+use crate::{sync::gaugecommunicator::InterpolateData, util::Vector3};
+
+const EARTH_RADIUS_FEET: f64 = 20_902_231.0;
+// Updates are only considered late after this many of the usual intervals
+const LATE_INTERVALS: f64 = 2.0;
+// Or after this many seconds, so the sim frame rate doesn't trigger extrapolation
+const MIN_LATE_SECS: f64 = 0.1;
+// Smoothing for the update interval estimate
+const INTERVAL_GAIN: f64 = 0.1;
+// Extrapolated updates are never sent more often than this
+const MIN_EXTRAPOLATION_INTERVAL_SECS: f64 = 0.02;
+
+#[derive(Clone, Copy)]
+enum Role {
+    Latitude,
+    Longitude,
+    Altitude,
+    // Wraps within a half circle either way
+    Angle,
+    // Wraps within a full circle
+    Heading,
+    Velocity,
+}
+
+fn get_role(var_name: &str) -> Option<Role> {
+    Some(match var_name {
+        "PLANE LATITUDE" => Role::Latitude,
+        "PLANE LONGITUDE" => Role::Longitude,
+        "PLANE ALTITUDE" => Role::Altitude,
+        "PLANE PITCH DEGREES" | "PLANE BANK DEGREES" => Role::Angle,
+        "PLANE HEADING DEGREES TRUE" | "PLANE HEADING DEGREES GYRO" => Role::Heading,
+        "VELOCITY BODY X" | "VELOCITY BODY Y" | "VELOCITY BODY Z" | "VELOCITY WORLD X"
+        | "VELOCITY WORLD Y" | "VELOCITY WORLD Z" | "VERTICAL SPEED" => Role::Velocity,
+        _ => return None,
+    })
+}
+
+// Rotates a body relative vector (x right, y up, z forward) into world axes (x east, y up, z north)
+// The sim's pitch is positive nose down and bank is positive to the left, all in radians
+fn body_to_world(body: Vector3, pitch: f64, bank: f64, heading: f64) -> Vector3 {
+    let pitch = -pitch;
+    let bank = -bank;
+    // Roll about the longitudinal axis
+    let rolled = Vector3 {
+        x: body.x * bank.cos() - body.y * bank.sin(),
+        y: body.x * bank.sin() + body.y * bank.cos(),
+        z: body.z,
+    };
+    // Pitch about the lateral axis
+    let pitched = Vector3 {
+        x: rolled.x,
+        y: rolled.y * pitch.cos() + rolled.z * pitch.sin(),
+        z: rolled.z * pitch.cos() - rolled.y * pitch.sin(),
+    };
+    // Heading about the vertical axis, clockwise from north
+    Vector3 {
+        x: pitched.x * heading.cos() + pitched.z * heading.sin(),
+        y: pitched.y,
+        z: pitched.z * heading.cos() - pitched.x * heading.sin(),
+    }
+}
+
+// Extrapolates position, attitude and velocities while unreliable updates are missing, and blends away the error once they resume
+pub struct DeadReckoning {
+    max_extrapolation: Duration,
+    correction_time: Duration,
+    // Size of a full circle in the angle units of the definitions
+    full_circle: f64,
+    // Latest known value and per second rate of every tracked var
+    values: HashMap<String, f64>,
+    rates: HashMap<String, f64>,
+    last_time: Option<f64>,
+    last_arrival: Instant,
+    interval: f64,
+    // What got sent while extrapolating, compared against the next real update
+    extrapolated: Option<HashMap<String, f64>>,
+    last_extrapolated: Instant,
+    correction: HashMap<String, f64>,
+    correction_start: Instant,
+}
+
+impl DeadReckoning {
+    pub fn new(max_extrapolation: Duration, correction_time: Duration, in_radians: bool) -> Self {
+        Self {
+            max_extrapolation,
+            correction_time,
+            full_circle: if in_radians { 2.0 * PI } else { 360.0 },
+            values: HashMap::new(),
+            rates: HashMap::new(),
+            last_time: None,
+            last_arrival: Instant::now(),
+            interval: 0.0,
+            extrapolated: None,
+            last_extrapolated: Instant::now(),
+            correction: HashMap::new(),
+            correction_start: Instant::now(),
+        }
+    }
+
+    fn difference(&self, role: Role, from: f64, to: f64) -> f64 {
+        match role {
+            Role::Latitude | Role::Longitude | Role::Angle | Role::Heading => {
+                let half = self.full_circle * 0.5;
+                (to - from + half).rem_euclid(self.full_circle) - half
+            }
+            Role::Altitude | Role::Velocity => to - from,
+        }
+    }
+
+    fn normalize(&self, role: Role, value: f64) -> f64 {
+        match role {
+            Role::Heading => value.rem_euclid(self.full_circle),
+            Role::Latitude | Role::Longitude | Role::Angle => {
+                let half = self.full_circle * 0.5;
+                (value + half).rem_euclid(self.full_circle) - half
+            }
+            Role::Altitude | Role::Velocity => value,
+        }
+    }
+
+    fn angle_to_radians(&self, value: f64) -> f64 {
+        value / self.full_circle * 2.0 * PI
+    }
+
+    fn radians_to_angle(&self, value: f64) -> f64 {
+        value / (2.0 * PI) * self.full_circle
+    }
+
+    // Records a received update and applies any remaining correction to it
+    pub fn on_sample(&mut self, time: f64, data: &mut [InterpolateData], now: Instant) {
+        let dt = self.last_time.map(|last| time - last).unwrap_or(0.0);
+
+        if self.last_time.is_some() {
+            let arrival_interval = now.duration_since(self.last_arrival).as_secs_f64();
+            self.interval += (arrival_interval - self.interval) * INTERVAL_GAIN;
+        }
+
+        self.last_time = Some(time);
+        self.last_arrival = now;
+
+        let extrapolated = self.extrapolated.take();
+        if extrapolated.is_some() {
+            self.correction.clear();
+            self.correction_start = now;
+        }
+
+        for entry in data.iter() {
+            let role = match get_role(&entry.name) {
+                Some(role) => role,
+                None => continue,
+            };
+
+            if let Some(previous) = self.values.get(&entry.name) {
+                if dt > 0.001 {
+                    let rate = self.difference(role, *previous, entry.value) / dt;
+                    self.rates.insert(entry.name.clone(), rate);
+                }
+            }
+
+            if let Some(sent) = extrapolated.as_ref().and_then(|x| x.get(&entry.name)) {
+                let error = self.difference(role, entry.value, *sent);
+                self.correction.insert(entry.name.clone(), error);
+            }
+
+            self.values.insert(entry.name.clone(), entry.value);
+        }
+
+        self.apply_correction(data, now);
+    }
+
+    // Shrinks linearly so the error is gone after the correction time
+    fn apply_correction(&self, data: &mut [InterpolateData], now: Instant) {
+        let elapsed = now.duration_since(self.correction_start).as_secs_f64();
+        let remaining = 1.0 - elapsed / self.correction_time.as_secs_f64().max(0.001);
+
+        if remaining <= 0.0 {
+            return;
+        }
+
+        for entry in data.iter_mut() {
+            if let (Some(error), Some(role)) =
+                (self.correction.get(&entry.name), get_role(&entry.name))
+            {
+                entry.value = self.normalize(role, entry.value + error * remaining);
+            }
+        }
+    }
+
+    fn get_value(&self, var_name: &str) -> Option<f64> {
+        self.values.get(var_name).copied()
+    }
+
+    // Velocity over ground in feet per second, east/up/north
+    fn get_world_velocity(&self) -> Option<Vector3> {
+        if let Some(forward) = self.get_value("VELOCITY BODY Z") {
+            let body = Vector3 {
+                x: self.get_value("VELOCITY BODY X").unwrap_or(0.0),
+                y: self.get_value("VELOCITY BODY Y").unwrap_or(0.0),
+                z: forward,
+            };
+
+            return Some(body_to_world(
+                body,
+                self.angle_to_radians(self.get_value("PLANE PITCH DEGREES").unwrap_or(0.0)),
+                self.angle_to_radians(self.get_value("PLANE BANK DEGREES").unwrap_or(0.0)),
+                self.angle_to_radians(self.get_value("PLANE HEADING DEGREES TRUE")?),
+            ));
+        }
+
+        Some(Vector3 {
+            x: self.get_value("VELOCITY WORLD X")?,
+            y: self.get_value("VERTICAL SPEED").unwrap_or(0.0),
+            z: self.get_value("VELOCITY WORLD Z")?,
+        })
+    }
+
+    // Returns the time and values to send if updates are overdue
+    pub fn extrapolate(&mut self, now: Instant) -> Option<(f64, Vec<InterpolateData>)> {
+        let last_time = self.last_time?;
+        let elapsed = now.duration_since(self.last_arrival);
+        let late_after = (self.interval * LATE_INTERVALS).max(MIN_LATE_SECS);
+
+        if elapsed.as_secs_f64() < late_after || elapsed > self.max_extrapolation {
+            return None;
+        }
+
+        // Keep to about the rate updates normally come in at
+        let send_interval = self.interval.max(MIN_EXTRAPOLATION_INTERVAL_SECS);
+        if self.extrapolated.is_some()
+            && now.duration_since(self.last_extrapolated).as_secs_f64() < send_interval
+        {
+            return None;
+        }
+
+        let dt = elapsed.as_secs_f64();
+        let velocity = self.get_world_velocity();
+        let mut extrapolated = HashMap::new();
+
+        for (name, value) in self.values.iter() {
+            let role = match get_role(name) {
+                Some(role) => role,
+                None => continue,
+            };
+
+            let rate = self.rates.get(name).copied().unwrap_or(0.0);
+
+            let new_value = match (role, velocity) {
+                (Role::Latitude, Some(velocity)) => {
+                    value + self.radians_to_angle(velocity.z * dt / EARTH_RADIUS_FEET)
+                }
+                (Role::Longitude, Some(velocity)) => {
+                    let latitude =
+                        self.angle_to_radians(self.get_value("PLANE LATITUDE").unwrap_or(0.0));
+                    // Meridians converge towards the poles
+                    let radius = EARTH_RADIUS_FEET * latitude.cos().max(0.01);
+                    value + self.radians_to_angle(velocity.x * dt / radius)
+                }
+                (Role::Altitude, Some(velocity)) => value + velocity.y * dt,
+                _ => value + rate * dt,
+            };
+
+            extrapolated.insert(name.clone(), self.normalize(role, new_value));
+        }
+
+        let mut data: Vec<InterpolateData> = extrapolated
+            .iter()
+            .map(|(name, value)| InterpolateData {
+                name: name.clone(),
+                value: *value,
+            })
+            .collect();
+
+        self.apply_correction(&mut data, now);
+
+        // Correction is part of what the gauge was sent
+        for entry in data.iter() {
+            extrapolated.insert(entry.name.clone(), entry.value);
+        }
+
+        self.extrapolated = Some(extrapolated);
+        self.last_extrapolated = now;
+
+        Some((last_time + dt, data))
+    }
+
+    pub fn reset(&mut self) {
+        self.values.clear();
+        self.rates.clear();
+        self.correction.clear();
+        self.last_time = None;
+        self.extrapolated = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(name: &str, value: f64) -> InterpolateData {
+        InterpolateData {
+            name: name.to_string(),
+            value,
+        }
+    }
+
+    fn assert_close(a: Vector3, b: Vector3) {
+        assert!((a.x - b.x).abs() < 1e-9, "{:?} != {:?}", a, b);
+        assert!((a.y - b.y).abs() < 1e-9, "{:?} != {:?}", a, b);
+        assert!((a.z - b.z).abs() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_body_to_world() {
+        let forward = Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: 1.0,
+        };
+
+        // Level heading north
+        assert_close(body_to_world(forward, 0.0, 0.0, 0.0), forward);
+        // Heading east
+        assert_close(
+            body_to_world(forward, 0.0, 0.0, PI / 2.0),
+            Vector3 {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            },
+        );
+        // Nose straight up, pitch is negative in the sim
+        assert_close(
+            body_to_world(forward, -PI / 2.0, 0.0, 0.0),
+            Vector3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+        );
+    }
+
+    #[test]
+    fn test_angle_wrapping() {
+        let dead_reckoning =
+            DeadReckoning::new(Duration::from_secs(1), Duration::from_secs(1), false);
+
+        assert_eq!(dead_reckoning.difference(Role::Heading, 350.0, 10.0), 20.0);
+        assert_eq!(dead_reckoning.difference(Role::Heading, 10.0, 350.0), -20.0);
+        assert_eq!(dead_reckoning.normalize(Role::Heading, 365.0), 5.0);
+        assert_eq!(dead_reckoning.normalize(Role::Angle, 190.0), -170.0);
+    }
+
+    #[test]
+    fn test_waits_for_late_updates() {
+        let mut dead_reckoning =
+            DeadReckoning::new(Duration::from_millis(300), Duration::from_secs(1), false);
+
+        let now = Instant::now();
+
+        // Nothing to extrapolate from
+        assert!(dead_reckoning.extrapolate(now).is_none());
+
+        dead_reckoning.on_sample(0.0, &mut [sample("PLANE ALTITUDE", 1000.0)], now);
+        assert!(dead_reckoning.extrapolate(now).is_none());
+
+        assert!(dead_reckoning
+            .extrapolate(now + Duration::from_millis(150))
+            .is_some());
+
+        // Past the window it stops rather than drifting away
+        assert!(dead_reckoning
+            .extrapolate(now + Duration::from_millis(350))
+            .is_none());
+    }
+
+    #[test]
+    fn test_extrapolates_and_corrects() {
+        let mut dead_reckoning =
+            DeadReckoning::new(Duration::from_secs(1), Duration::from_secs(10), false);
+
+        let now = Instant::now();
+
+        dead_reckoning.on_sample(0.0, &mut [sample("PLANE HEADING DEGREES GYRO", 0.0)], now);
+        dead_reckoning.on_sample(1.0, &mut [sample("PLANE HEADING DEGREES GYRO", 10.0)], now);
+
+        let (time, data) = dead_reckoning
+            .extrapolate(now + Duration::from_millis(150))
+            .unwrap();
+        // Turning at 10 degrees per second
+        assert!((time - 1.15).abs() < 1e-9);
+        let sent = data[0].value;
+        assert!((sent - 11.5).abs() < 1e-9);
+
+        // The next update starts where the extrapolation left off instead of jumping back
+        let mut next = [sample("PLANE HEADING DEGREES GYRO", 10.5)];
+        dead_reckoning.on_sample(1.2, &mut next, now + Duration::from_millis(200));
+        assert!((next[0].value - sent).abs() < 1e-9);
+    }
+}
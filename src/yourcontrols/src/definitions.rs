@@ -1,4 +1,6 @@
 use indexmap::IndexMap;
+use log::debug;
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde_yaml::{self, Value};
 use simconnect::SimConnector;
@@ -8,27 +10,43 @@ use std::{
     fs::File,
     mem::swap,
     path::Path,
-    time::Instant,
+    time::{Duration, Instant},
 };
 
 use crate::{
     corrector::Corrector,
+    deadreckoning::DeadReckoning,
     sync::{
+        budget::{estimate_var_size, Priority, SendBudget},
+        conflict::SharedVarClock,
+        echo::EchoSuppressor,
         freezer::Freezer,
         gaugecommunicator::{GetResult, InterpolateData, InterpolationType},
         jscommunicator::{JSCommunicator, JSPayloads},
+        pause::{PauseState, PauseSync, SIM_RATE_VAR},
         transfer::{AircraftVars, Events, LVarSyncer},
     },
     syncdefs::{
-        CustomCalculator, LocalVarProxy, MultiplyDifferenceLocalVarSet, NumDigitSet, NumIncrement,
-        NumSet, ResetWhenEquals, Syncable, ToggleSwitch,
+        CustomCalculator, InputEvent, LocalVarProxy, MultiplyDifferenceLocalVarSet, NumDigitSet,
+        NumIncrement, NumSet, ResetWhenEquals, RotarySelector, Syncable, ToggleSwitch,
     },
+    teleport::{TeleportDetector, SLEW_VAR},
     util::{Category, InDataTypes},
+    varreader::fit_string,
+    weather::WeatherSync,
 };
 
-use crate::emulator::{EmulatorState, EmulatorVarInfo, EmulatorVarSource};
+use crate::emulator::{EmulatorState, EmulatorValue, EmulatorVarInfo, EmulatorVarSource};
 
-use yourcontrols_types::{AllNeedSync, Error, Event, EventData, VarMap, VarReaderTypes};
+use yourcontrols_types::{
+    AllNeedSync, Error, Event, EventData, VarMap, VarReaderTypes, WeatherSample,
+};
+
+// Budget shared by all var updates, events are never held back
+const SEND_BYTES_PER_SECOND: u32 = 64 * 1024;
+const SEND_PACKETS_PER_SECOND: u32 = 120;
+// How long a var has to sit still before a change smaller than its deadband gets sent
+const DEADBAND_SETTLE_TIME: Duration = Duration::from_millis(500);
 
 // Checks if a field in a Value exists, otherwise will return an error with the name of the field
 macro_rules! check_and_return_field {
@@ -80,25 +98,17 @@ macro_rules! execute_mapping {
                 ActionType::VarOnly => $var_only_action
                 _ => {}
             }
+
+            // Strings can only be set directly
+            VarReaderTypes::String(_) => match &mut $mapping.action {
+                ActionType::VarOnly => $var_only_action
+                _ => {}
+            }
             _ => {}
         }
     }
 }
 
-fn set_did_write_recently(map: &mut HashMap<String, Instant>, data_name: &str) {
-    if let Some(instant) = map.get_mut(data_name) {
-        *instant = Instant::now();
-    } else {
-        map.insert(data_name.to_string(), Instant::now());
-    }
-}
-
-fn check_did_write_recently(map: &mut HashMap<String, Instant>, data_name: &str) -> bool {
-    map.get(data_name)
-        .map(|x| x.elapsed().as_secs() < 1)
-        .unwrap_or(false)
-}
-
 fn get_data_type_from_string(string: &str) -> Result<InDataTypes, Error> {
     Ok(match string {
         "i32" => InDataTypes::I32,
@@ -109,16 +119,16 @@ fn get_data_type_from_string(string: &str) -> Result<InDataTypes, Error> {
 }
 
 fn evalute_condition_values(condition: &Condition, value: &VarReaderTypes) -> bool {
-    if let Some(data) = condition.equals {
-        return *value == data;
+    if let Some(data) = &condition.equals {
+        return value == data;
     }
 
-    if let Some(data) = condition.greater_than {
-        return *value > data;
+    if let Some(data) = &condition.greater_than {
+        return value > data;
     }
 
-    if let Some(data) = condition.less_than {
-        return *value < data;
+    if let Some(data) = &condition.less_than {
+        return value < data;
     }
 
     false
@@ -220,7 +230,7 @@ pub struct SyncPermission {
 }
 
 // Serde types
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct EventEntry {
     event_name: String,
     #[serde(default)]
@@ -230,24 +240,25 @@ struct EventEntry {
     condition: Option<Condition>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct VarData {
     var_name: String,
     var_units: Option<String>,
     var_type: InDataTypes,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct Condition {
     var: Option<VarData>,
     equals: Option<VarReaderTypes>,
     greater_than: Option<VarReaderTypes>,
     less_than: Option<VarReaderTypes>,
     #[serde(flatten)]
+    #[schemars(with = "ConditionExpressionSchema")]
     other: Option<Box<ConditionExpression>>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 enum ConditionExpression {
     And(Condition),
@@ -255,7 +266,7 @@ enum ConditionExpression {
 }
 
 // Describes an aircraft variable to listen for changes
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct VarEntry {
     var_name: String,
     var_units: Option<String>,
@@ -267,9 +278,13 @@ struct VarEntry {
     unreliable: bool,
     #[serde(default)]
     cancel_h_events: bool,
+    // Smallest change from the last queued value worth sending
+    #[serde(alias = "min_delta")]
+    deadband: Option<f64>,
+    priority: Option<Priority>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct ToggleSwitchGenericEntry {
     var_name: String,
     var_units: Option<String>,
@@ -286,7 +301,7 @@ struct ToggleSwitchGenericEntry {
     cancel_h_events: bool,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct NumSetGenericEntry<T> {
     var_name: String,
     var_units: Option<String>,
@@ -306,9 +321,12 @@ struct NumSetGenericEntry<T> {
     swap_event_name: Option<String>,
     #[serde(default)]
     unreliable: bool,
+    #[serde(alias = "min_delta")]
+    deadband: Option<f64>,
+    priority: Option<Priority>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct NumIncrementEntry<T> {
     var_name: String,
     var_units: Option<String>,
@@ -330,7 +348,7 @@ struct NumIncrementEntry<T> {
     use_calculator: bool,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct NumDigitSetEntry {
     var_name: String,
     var_units: Option<String>,
@@ -341,7 +359,60 @@ struct NumDigitSetEntry {
     cancel_h_events: bool,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
+struct RotarySelectorEntry {
+    var_name: String,
+    var_units: Option<String>,
+    positions: i32,
+    #[serde(default)]
+    wrap: bool,
+    inc_event: String,
+    dec_event: String,
+    event_param: Option<u32>,
+    // Milliseconds to wait between each inc/dec event
+    step_delay: Option<u64>,
+    #[serde(default)]
+    use_calculator: bool,
+    condition: Option<Condition>,
+    #[serde(default)]
+    cancel_h_events: bool,
+}
+
+// Describes an ordered list of steps to run on the receiving side whenever the var changes
+#[derive(Deserialize, JsonSchema)]
+struct SequenceEntry {
+    var_name: String,
+    var_units: Option<String>,
+    var_type: Option<InDataTypes>,
+    on_change: Vec<SequenceStepEntry>,
+    condition: Option<Condition>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct SequenceStepEntry {
+    #[serde(flatten)]
+    action: SequenceStepActionEntry,
+    // Milliseconds to wait after the previous step
+    delay: Option<u64>,
+    condition: Option<Condition>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum SequenceStepActionEntry {
+    // K: events (the default) or H: events
+    Event {
+        event_name: String,
+        event_param: Option<u32>,
+    },
+    Var {
+        var_name: String,
+        var_units: Option<String>,
+        value: f64,
+    },
+}
+
+#[derive(Deserialize, JsonSchema)]
 struct CustomCalculatorEntry {
     get: String,
     set: String,
@@ -350,7 +421,23 @@ struct CustomCalculatorEntry {
     cancel_h_events: bool,
 }
 
-#[derive(Deserialize)]
+// Describes a MSFS 2024 input event (B: var), read with (B:name) and set through one of its bindings
+#[derive(Deserialize, JsonSchema)]
+struct InputEventEntry {
+    event_name: String,
+    #[serde(default = "default_input_event_binding")]
+    set_binding: String,
+    condition: Option<Condition>,
+    interpolate: Option<InterpolationType>,
+    #[serde(default)]
+    cancel_h_events: bool,
+}
+
+fn default_input_event_binding() -> String {
+    "Set".to_string()
+}
+
+#[derive(Deserialize, JsonSchema)]
 struct LocalVarProxyEntry {
     var_name: String,
     target: String,
@@ -359,7 +446,7 @@ struct LocalVarProxyEntry {
     condition: Option<Condition>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct MultiplyDifferenceLocalVarEntry {
     var_name: String,
     target: String,
@@ -370,7 +457,7 @@ struct MultiplyDifferenceLocalVarEntry {
     condition: Option<Condition>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct ResetWhenEqualsEntry {
     var_name: String,
     target: String,
@@ -378,7 +465,7 @@ struct ResetWhenEqualsEntry {
     condition: Option<Condition>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct ProgramActionEntry {
     var_name: String,
     var_units: Option<String>,
@@ -387,18 +474,136 @@ struct ProgramActionEntry {
     action: ProgramAction,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct ProgramActionEventEntry {
     event_name: String,
     action: ProgramAction,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, JsonSchema, Clone)]
 pub enum ProgramAction {
     TakeControls,
     TransferControls,
 }
 
+// Schema only types, these mirror what parse_var and parse_yaml accept so editors can validate definition files
+#[derive(JsonSchema)]
+#[serde(rename_all = "lowercase")]
+#[allow(dead_code)]
+enum NumberDataType {
+    I32,
+    F64,
+}
+
+// NumSet reads var_type, condition and cancel_h_events from the raw value before casting into NumSetGenericEntry
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+struct NumSetEntry {
+    var_type: NumberDataType,
+    #[serde(flatten)]
+    entry: NumSetGenericEntry<f64>,
+    #[serde(default)]
+    cancel_h_events: bool,
+}
+
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+struct NumIncrementEntryWithType {
+    var_type: NumberDataType,
+    #[serde(flatten)]
+    entry: NumIncrementEntry<f64>,
+    conditions: Option<Condition>,
+    #[serde(default)]
+    cancel_h_events: bool,
+}
+
+// Extrapolates position and attitude over lost unreliable updates
+#[derive(Deserialize, JsonSchema)]
+struct DeadReckoningEntry {
+    // Milliseconds to keep extrapolating for before holding the last values
+    max_extrapolation: Option<u64>,
+    // Milliseconds to blend away the extrapolation error once updates resume
+    correction_time: Option<u64>,
+    // Units the physics vars are defined in
+    #[serde(default)]
+    angle_units: AngleUnits,
+}
+
+// Corrects for differences in wind, terrain and weather between peers
+#[derive(Deserialize, JsonSchema)]
+struct PhysicsCorrectionEntry {
+    #[serde(default)]
+    wind: bool,
+    // Feet above ground below which the altitude follows the local terrain
+    terrain_below_agl: Option<f64>,
+    #[serde(default)]
+    barometric: bool,
+}
+
+// Tells peers to set the position directly when the aircraft jumps
+#[derive(Deserialize, JsonSchema)]
+struct SnapDetectionEntry {
+    // Feet the aircraft has to move between two frames to count as a jump
+    max_position_jump: Option<f64>,
+    #[serde(default)]
+    angle_units: AngleUnits,
+}
+
+#[derive(Deserialize, JsonSchema, PartialEq, Default)]
+enum AngleUnits {
+    #[default]
+    Degrees,
+    Radians,
+}
+
+#[derive(JsonSchema)]
+#[serde(tag = "type")]
+#[allow(dead_code)]
+enum DefinitionEntry {
+    #[serde(rename = "var")]
+    Var(VarEntry),
+    #[serde(rename = "event")]
+    Event(EventEntry),
+    ToggleSwitch(ToggleSwitchGenericEntry),
+    NumSet(NumSetEntry),
+    NumIncrement(NumIncrementEntryWithType),
+    NumDigitSet(NumDigitSetEntry),
+    RotarySelector(RotarySelectorEntry),
+    CustomCalculator(CustomCalculatorEntry),
+    ProgramAction(ProgramActionEntry),
+    LocalVarProxy(LocalVarProxyEntry),
+    ResetWhenEquals(ResetWhenEqualsEntry),
+    MultiplyDifferenceLocalVar(MultiplyDifferenceLocalVarEntry),
+    ProgramActionEvent(ProgramActionEventEntry),
+    InputEvent(InputEventEntry),
+    Sequence(SequenceEntry),
+    DeadReckoning(DeadReckoningEntry),
+    SnapDetection(SnapDetectionEntry),
+    PhysicsCorrection(PhysicsCorrectionEntry),
+}
+
+// The flattened and/or keys of a condition are both optional
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+struct ConditionExpressionSchema {
+    and: Option<Box<Condition>>,
+    or: Option<Box<Condition>>,
+}
+
+// A definition file maps categories (shared, master, server, init) to entries, alongside the include and ignore lists
+pub fn get_definition_schema_string() -> String {
+    let mut schema = schemars::schema_for!(HashMap<String, Vec<DefinitionEntry>>);
+    let string_list = schemars::gen::SchemaGenerator::default().subschema_for::<Vec<String>>();
+
+    let properties = &mut schema.schema.object().properties;
+    properties.insert("include".to_string(), string_list.clone());
+    properties.insert("ignore".to_string(), string_list);
+
+    schema.schema.metadata().title = Some("YourControls definition file".to_string());
+
+    serde_json::to_string_pretty(&schema).unwrap()
+}
+
 struct EventMapping {
     use_calculator: bool,
 }
@@ -409,9 +614,55 @@ enum ActionType {
     Bool(Box<dyn Syncable<bool>>),
     ProgramAction(ProgramAction),
     Event(EventMapping),
+    // Index into the sequences
+    Sequence(usize),
     VarOnly,
 }
 
+impl ActionType {
+    fn get_readback_path(&self, from: &VarReaderTypes, to: &VarReaderTypes) -> Option<Vec<f64>> {
+        match (self, from, to) {
+            (_, VarReaderTypes::String(_), _) | (_, _, VarReaderTypes::String(_)) => None,
+            (ActionType::F64(action), from, to) => {
+                action.get_readback_path(from.get_as_f64(), to.get_as_f64())
+            }
+            (ActionType::I32(action), from, to) => {
+                action.get_readback_path(from.get_as_f64() as i32, to.get_as_f64() as i32)
+            }
+            (ActionType::Bool(action), from, to) => {
+                action.get_readback_path(from.get_as_f64() != 0.0, to.get_as_f64() != 0.0)
+            }
+            _ => None,
+        }
+    }
+}
+
+enum SequenceAction {
+    KeyEvent {
+        event_name: String,
+        event_param: u32,
+    },
+    HEvent(String),
+    SetVar {
+        var_name: String,
+        var_units: Option<String>,
+        value: f64,
+    },
+}
+
+struct SequenceStep {
+    action: SequenceAction,
+    delay: Duration,
+    condition: Option<Condition>,
+}
+
+struct QueuedSequenceStep {
+    sequence_index: usize,
+    step_index: usize,
+    value: VarReaderTypes,
+    execute_at: Instant,
+}
+
 struct Period {
     time: f64,
     last_update: Option<Instant>,
@@ -443,6 +694,64 @@ impl Period {
     }
 }
 
+struct Deadband {
+    min_delta: f64,
+    last_queued: Option<f64>,
+    // Drift that stayed within the deadband, sent once the var stops moving
+    pending: Option<(f64, Instant)>,
+}
+
+impl Deadband {
+    fn new(min_delta: f64) -> Self {
+        Self {
+            min_delta,
+            last_queued: None,
+            pending: None,
+        }
+    }
+
+    fn do_update(&mut self, value: f64, now: Instant) -> bool {
+        match self.last_queued {
+            Some(last) if (value - last).abs() < self.min_delta => {
+                let moved = match self.pending {
+                    Some((pending, _)) => pending != value,
+                    None => value != last,
+                };
+
+                if moved {
+                    self.pending = Some((value, now));
+                }
+
+                false
+            }
+            _ => {
+                self.last_queued = Some(value);
+                self.pending = None;
+                true
+            }
+        }
+    }
+
+    // The value the var came to rest at, if it hasn't moved since DEADBAND_SETTLE_TIME
+    fn take_settled(&mut self, now: Instant) -> Option<f64> {
+        let (value, changed_at) = self.pending?;
+
+        if now.duration_since(changed_at) < DEADBAND_SETTLE_TIME {
+            return None;
+        }
+
+        self.pending = None;
+        self.last_queued = Some(value);
+
+        Some(value)
+    }
+
+    fn reset(&mut self) {
+        self.last_queued = None;
+        self.pending = None;
+    }
+}
+
 struct Mapping {
     action: ActionType,
     condition: Option<Condition>,
@@ -476,12 +785,23 @@ pub struct Definitions {
     categories: HashMap<String, Category>,
     // Vars that shouldn't update every tick
     periods: HashMap<String, Period>,
+    // Vars that only send changes bigger than some amount
+    deadbands: HashMap<String, Deadband>,
+    // Which vars get sent first when over the send budget
+    priorities: HashMap<String, Priority>,
+    send_budget: SendBudget,
     // Value to hold the current queue
     current_sync: AllNeedSync,
-    // Keep track of which definitions just got written so we don't sync them again
-    last_written: HashMap<String, Instant>,
+    // Keep track of what the sim should read back from remote writes so we don't sync them again
+    echo_suppressor: EchoSuppressor,
+    // Orders changes to shared vars so both pilots settle on the same value
+    shared_clock: SharedVarClock,
     // Helper struct to calculate velocity and correct plane/ground altitude
-    physics_corrector: Corrector,
+    physics_corrector: Option<Corrector>,
+    // Fills in position and attitude when unreliable updates go missing
+    dead_reckoning: Option<DeadReckoning>,
+    // Notices slewing and teleports so peers don't interpolate across the world
+    teleport_detector: Option<TeleportDetector>,
     // Delay events by 100ms in order for them to get synced correctly
     event_queue: VecDeque<Event>,
     event_timer: Instant,
@@ -492,6 +812,11 @@ pub struct Definitions {
     do_not_sync: HashSet<String>,
     // Vars that need interpolation
     interpolate_vars: HashSet<String>,
+    // Vars with actions that need to be polled every step
+    polled_vars: HashSet<String>,
+    // Steps for each Sequence entry, and the steps waiting to be run
+    sequences: Vec<Vec<SequenceStep>>,
+    sequence_queue: VecDeque<QueuedSequenceStep>,
     // For indicating that an event has been triggered and the control should be transferred to the next person
     pending_action: Option<ProgramAction>,
     // Track known variable types for emulator UI
@@ -500,6 +825,10 @@ pub struct Definitions {
     time: Instant,
     // Freezer struct to handle freezing the sim when control is lost
     freezer: Freezer,
+    // Keeps pause and sim rate in step with the crew
+    pause_sync: PauseSync,
+    // Compares our weather with the host's
+    weather_sync: WeatherSync,
 }
 
 fn get_category_from_string(category: &str) -> Result<Category, Error> {
@@ -530,9 +859,12 @@ impl Definitions {
             jstransfer: JSCommunicator::new(),
             avarstransfer: AircraftVars::new(1),
 
-            last_written: HashMap::new(),
+            echo_suppressor: EchoSuppressor::new(),
+            shared_clock: SharedVarClock::new(),
 
-            physics_corrector: Corrector::new(2),
+            physics_corrector: None,
+            dead_reckoning: None,
+            teleport_detector: None,
 
             current_sync: AllNeedSync::new(),
             event_queue: VecDeque::new(),
@@ -544,12 +876,36 @@ impl Definitions {
 
             categories: HashMap::new(),
             periods: HashMap::new(),
+            deadbands: HashMap::new(),
+            priorities: HashMap::new(),
+            send_budget: SendBudget::new(SEND_BYTES_PER_SECOND, SEND_PACKETS_PER_SECOND),
             interpolate_vars: HashSet::new(),
+            polled_vars: HashSet::new(),
+            sequences: Vec::new(),
+            sequence_queue: VecDeque::new(),
 
             pending_action: None,
             emulator: EmulatorState::new(),
             time: Instant::now(),
             freezer: Freezer::new(),
+            pause_sync: PauseSync::new(),
+            weather_sync: WeatherSync::new(3),
+        }
+    }
+
+    fn add_send_options(
+        &mut self,
+        var_name: &str,
+        deadband: Option<f64>,
+        priority: Option<Priority>,
+    ) {
+        if let Some(deadband) = deadband {
+            self.deadbands
+                .insert(var_name.to_string(), Deadband::new(deadband));
+        }
+
+        if let Some(priority) = priority {
+            self.priorities.insert(var_name.to_string(), priority);
         }
     }
 
@@ -584,6 +940,8 @@ impl Definitions {
             self.periods.insert(var_name.clone(), Period::new(period));
         }
 
+        self.add_send_options(&var_name, var.deadband, var.priority);
+
         self.add_mapping(
             var_name,
             Mapping {
@@ -654,6 +1012,11 @@ impl Definitions {
         var_type: InDataTypes,
     ) -> Result<(String, VarType), Error> {
         if var_name.starts_with("L:") {
+            // Local vars are always numbers
+            if let InDataTypes::String = var_type {
+                return Err(Error::InvalidSyncType(format!("string for {}", var_name)));
+            }
+
             // Keep var_name with L: in it to pass to execute_calculator code
             self.add_local_variable(category, var_name, var_units)?;
 
@@ -663,6 +1026,11 @@ impl Definitions {
             Ok((var_name.to_string(), VarType::LocalVar))
         } else {
             let actual_var_name = get_real_var_name(var_name);
+            // Strings are not measured in any units, SimConnect rejects a STRING256 that has one
+            let var_units = match var_type {
+                InDataTypes::String => Some(""),
+                _ => var_units,
+            };
 
             if let Some(var_units) = var_units {
                 self.add_aircraft_variable(category, &actual_var_name, var_units, var_type)?;
@@ -685,7 +1053,7 @@ impl Definitions {
         self.emulator.get_var_value(id)
     }
 
-    pub fn apply_emulator_value(&mut self, id: &str, value: f64) -> Result<(), Error> {
+    pub fn apply_emulator_value(&mut self, id: &str, value: EmulatorValue) -> Result<(), Error> {
         self.emulator.apply_value(id, value, &mut self.current_sync)
     }
 
@@ -693,19 +1061,16 @@ impl Definitions {
         &mut self,
         conn: &SimConnector,
         id: &str,
-        value: f64,
+        value: EmulatorValue,
         sync_permission: &SyncPermission,
     ) -> Result<(), Error> {
         let mut data = AllNeedSync::new();
         self.emulator.apply_value(id, value, &mut data)?;
         data.filter(|var_name| self.can_sync(var_name, sync_permission));
         self.write_event_data(data.events)?;
-        self.write_aircraft_data(
-            conn,
-            data.avars,
-            Instant::now().duration_since(self.time).as_secs_f64(),
-        );
-        self.write_local_data(conn, data.lvars)
+        let time = Instant::now().duration_since(self.time).as_secs_f64();
+        self.write_aircraft_data(conn, data.avars, time, data.snap);
+        self.write_local_data(conn, data.lvars, time)
     }
 
     fn process_new_condition(&mut self, condition: &mut Condition) -> Result<(), Error> {
@@ -813,6 +1178,8 @@ impl Definitions {
         let (var_string, _) =
             self.add_var_string(category, &var.var_name, var.var_units.as_deref(), data_type)?;
 
+        self.add_send_options(&var_string, var.deadband, var.priority);
+
         if let Some(interpolate_type) = var.interpolate {
             self.lvarstransfer.transfer.add_interpolate_mapping(
                 &format!("K:{}", &var.event_name),
@@ -1015,6 +1382,144 @@ impl Definitions {
         Ok(())
     }
 
+    fn add_rotary_selector(
+        &mut self,
+        category: &str,
+        var: RotarySelectorEntry,
+    ) -> Result<(), Error> {
+        let (var_string, _) = self.add_var_string(
+            category,
+            &var.var_name,
+            var.var_units.as_deref(),
+            InDataTypes::I32,
+        )?;
+
+        let step_delay = Duration::from_millis(var.step_delay.unwrap_or(0));
+        let mut mapping = RotarySelector::new(var.positions, var.wrap, step_delay);
+
+        if let Some(event_param) = var.event_param {
+            mapping.set_event_param(event_param);
+        }
+
+        if var.use_calculator {
+            mapping.set_event_names(var.inc_event, var.dec_event);
+        } else {
+            let inc_event_id = self.events.get_or_map_event_id(&var.inc_event, false);
+            let dec_event_id = self.events.get_or_map_event_id(&var.dec_event, false);
+
+            mapping.set_event_ids(inc_event_id, dec_event_id);
+        }
+
+        // Steps are spread out over time
+        if !step_delay.is_zero() {
+            self.polled_vars.insert(var_string.clone());
+        }
+
+        self.add_mapping(
+            var_string,
+            Mapping {
+                action: ActionType::I32(Box::new(mapping)),
+                condition: var.condition,
+                cancel_h_events: var.cancel_h_events,
+            },
+        )
+    }
+
+    fn add_dead_reckoning(&mut self, var: DeadReckoningEntry) -> Result<(), Error> {
+        self.dead_reckoning = Some(DeadReckoning::new(
+            Duration::from_millis(var.max_extrapolation.unwrap_or(1000)),
+            Duration::from_millis(var.correction_time.unwrap_or(500)),
+            var.angle_units == AngleUnits::Radians,
+        ));
+
+        Ok(())
+    }
+
+    fn add_physics_correction(&mut self, var: PhysicsCorrectionEntry) -> Result<(), Error> {
+        let corrector = Corrector::new(2, var.wind, var.terrain_below_agl, var.barometric);
+
+        // Have to arrive in the same packet as the altitude
+        for var_name in corrector.get_reference_vars() {
+            self.unreliable_vars.insert(var_name.to_string());
+        }
+
+        self.physics_corrector = Some(corrector);
+
+        Ok(())
+    }
+
+    fn add_snap_detection(&mut self, var: SnapDetectionEntry) -> Result<(), Error> {
+        self.teleport_detector = Some(TeleportDetector::new(
+            var.max_position_jump.unwrap_or(5000.0),
+            var.angle_units == AngleUnits::Radians,
+        ));
+
+        Ok(())
+    }
+
+    fn add_sequence(&mut self, category: &str, var: SequenceEntry) -> Result<(), Error> {
+        let (var_string, _) = self.add_var_string(
+            category,
+            &var.var_name,
+            var.var_units.as_deref(),
+            var.var_type.unwrap_or(InDataTypes::F64),
+        )?;
+
+        let mut steps = Vec::new();
+
+        for step in var.on_change {
+            let action = match step.action {
+                SequenceStepActionEntry::Event {
+                    event_name,
+                    event_param,
+                } => {
+                    if event_name.starts_with("H:") {
+                        SequenceAction::HEvent(event_name)
+                    } else {
+                        let event_name = event_name.strip_prefix("K:").unwrap_or(&event_name);
+                        self.events.get_or_map_event_id(event_name, false);
+
+                        SequenceAction::KeyEvent {
+                            event_name: event_name.to_string(),
+                            event_param: event_param.unwrap_or(0),
+                        }
+                    }
+                }
+                SequenceStepActionEntry::Var {
+                    var_name,
+                    var_units,
+                    value,
+                } => SequenceAction::SetVar {
+                    var_name,
+                    var_units,
+                    value,
+                },
+            };
+
+            let mut condition = step.condition;
+            if let Some(condition) = &mut condition {
+                self.process_new_condition(condition)?;
+            }
+
+            steps.push(SequenceStep {
+                action,
+                delay: Duration::from_millis(step.delay.unwrap_or(0)),
+                condition,
+            });
+        }
+
+        self.sequences.push(steps);
+
+        self.add_mapping(
+            var_string,
+            Mapping {
+                action: ActionType::Sequence(self.sequences.len() - 1),
+                condition: var.condition,
+                ..Default::default()
+            },
+        )
+    }
+
     fn add_custom_calculator(
         &mut self,
         category: &str,
@@ -1061,6 +1566,41 @@ impl Definitions {
         )
     }
 
+    fn add_input_event(&mut self, category: &str, var: InputEventEntry) -> Result<(), Error> {
+        let event_name = var.event_name.trim();
+        let event_name = event_name.strip_prefix("B:").unwrap_or(event_name);
+
+        let var_name = format!("B:{}", event_name);
+        let set_string = format!("B:{}_{}", event_name, var.set_binding);
+
+        self.add_local_variable(category, &var_name, None)?;
+        self.emulator
+            .register_var(&var_name, InDataTypes::F64, EmulatorVarSource::Local);
+        self.lvarstransfer.input_events.register(event_name);
+
+        if let Some(interpolate) = var.interpolate {
+            self.interpolate_vars.insert(var_name.clone());
+            self.lvarstransfer.transfer.add_interpolate_mapping(
+                &set_string,
+                var_name.clone(),
+                None,
+                interpolate,
+            );
+        }
+
+        self.add_mapping(
+            var_name,
+            Mapping {
+                action: ActionType::F64(Box::new(InputEvent::new(
+                    event_name.to_string(),
+                    set_string,
+                ))),
+                condition: var.condition,
+                cancel_h_events: var.cancel_h_events,
+            },
+        )
+    }
+
     fn add_multiply_difference_local_var(
         &mut self,
         category: &str,
@@ -1175,6 +1715,7 @@ impl Definitions {
             "NUMSET" => self.add_num_set(&category, try_cast_yaml!(value))?,
             "NUMINCREMENT" => self.add_num_increment(&category, try_cast_yaml!(value))?,
             "NUMDIGITSET" => self.add_num_digit_set(&category, try_cast_yaml!(value))?,
+            "ROTARYSELECTOR" => self.add_rotary_selector(&category, try_cast_yaml!(value))?,
             "CUSTOMCALCULATOR" => self.add_custom_calculator(&category, try_cast_yaml!(value))?,
             "PROGRAMACTION" => self.add_program_action(&category, try_cast_yaml!(value))?,
             "LOCALVARPROXY" => self.add_local_var_proxy(&category, try_cast_yaml!(value))?,
@@ -1185,6 +1726,11 @@ impl Definitions {
             "PROGRAMACTIONEVENT" => {
                 self.add_program_action_event(&category, try_cast_yaml!(value))?
             }
+            "INPUTEVENT" => self.add_input_event(&category, try_cast_yaml!(value))?,
+            "SEQUENCE" => self.add_sequence(&category, try_cast_yaml!(value))?,
+            "DEADRECKONING" => self.add_dead_reckoning(try_cast_yaml!(value))?,
+            "SNAPDETECTION" => self.add_snap_detection(try_cast_yaml!(value))?,
+            "PHYSICSCORRECTION" => self.add_physics_correction(try_cast_yaml!(value))?,
             _ => return Err(Error::InvalidSyncType(type_str.to_string())),
         };
 
@@ -1263,7 +1809,9 @@ impl Definitions {
     }
 
     fn process_local_var(&mut self, result: GetResult) {
-        let mut should_write = !check_did_write_recently(&mut self.last_written, &result.var_name)
+        let mut should_write = !self
+            .echo_suppressor
+            .is_echo(&result.var_name, &VarReaderTypes::F64(result.value))
             && !self.do_not_sync.contains(&result.var_name);
 
         if let Some(mappings) = self.mappings.get_mut(&result.var_name) {
@@ -1287,10 +1835,15 @@ impl Definitions {
             }
         }
 
+        if let Some(deadband) = self.deadbands.get_mut(&result.var_name) {
+            should_write = should_write && deadband.do_update(result.value, Instant::now());
+        }
+
         if !should_write {
             return;
         }
 
+        self.stamp_shared_var(&result.var_name);
         self.current_sync
             .lvars
             .insert(result.var_name.clone(), VarReaderTypes::F64(result.value));
@@ -1350,6 +1903,16 @@ impl Definitions {
 
     // Processes event data name and the additional dword data
     pub fn process_event_data(&mut self, data: &simconnect::SIMCONNECT_RECV_EVENT) {
+        if self.pause_sync.process_event_data(data) {
+            return;
+        }
+
+        if let Some(teleport_detector) = self.teleport_detector.as_mut() {
+            if teleport_detector.process_event_data(data) {
+                return;
+            }
+        }
+
         // Not for us
         if data.uGroupID != self.events.group_id {
             return;
@@ -1385,14 +1948,14 @@ impl Definitions {
                 if mapping.cancel_h_events {
                     self.event_cancel_timer = Instant::now();
                 }
-
-                // Check timer
-                if check_did_write_recently(&mut self.last_written, &event_name) {
-                    return;
-                }
             }
         }
 
+        // Triggered by a remote event
+        if should_write && self.echo_suppressor.is_event_echo(&event_name, data.dwData) {
+            return;
+        }
+
         if should_write {
             self.current_sync.events.push(Event::KeyEvent {
                 name: event_name,
@@ -1408,21 +1971,29 @@ impl Definitions {
         conn: &SimConnector,
         data: &simconnect::SIMCONNECT_RECV_SIMOBJECT_DATA,
     ) {
-        // self.physics_corrector.process_sim_object_data(data);
+        if let Some(physics_corrector) = self.physics_corrector.as_mut() {
+            physics_corrector.process_sim_object_data(data);
+        }
+
+        self.weather_sync.process_sim_object_data(data);
+
         if self.avarstransfer.define_id != data.dwDefineID {
             return;
         }
         // Data might be bad/config files don't line up
         if let Ok(data) = self.avarstransfer.read_vars(data) {
-            // Remove some computed components
-            // self.physics_corrector.remove_components(&mut data);
             self.freezer
                 .on_vars_change(&data, conn, &self.lvarstransfer.transfer);
+            self.pause_sync.on_vars_change(&data);
+
+            if let Some(teleport_detector) = self.teleport_detector.as_mut() {
+                teleport_detector.on_vars_change(&data);
+            }
 
             // Update all syncactions with the changed values
             for (var_name, value) in &data {
                 // Determine if this variable should be updated
-                let mut should_write = !check_did_write_recently(&mut self.last_written, var_name)
+                let mut should_write = !self.echo_suppressor.is_echo(var_name, value)
                     && !self.do_not_sync.contains(var_name);
                 // Set current var syncactions
                 if let Some(mappings) = self.mappings.get_mut(var_name) {
@@ -1447,10 +2018,18 @@ impl Definitions {
                     should_write = should_write && period.do_update();
                 }
 
+                if let Some(deadband) = self.deadbands.get_mut(var_name) {
+                    should_write =
+                        should_write && deadband.do_update(value.get_as_f64(), Instant::now());
+                }
+
                 if should_write {
+                    self.stamp_shared_var(var_name);
                     // Queue data for reading
-                    self.current_sync.avars.insert(var_name.clone(), *value);
-                    self.emulator.record_last_known(var_name, *value);
+                    self.current_sync
+                        .avars
+                        .insert(var_name.clone(), value.clone());
+                    self.emulator.record_last_known(var_name, value.clone());
                 }
             }
         }
@@ -1480,6 +2059,8 @@ impl Definitions {
             .ok_or_else(|| Error::MissingMapping(name.clone()))?
         {
             if let ActionType::Event(mapping) = &mapping.action {
+                self.echo_suppressor.expect_event(&name, value);
+
                 if mapping.use_calculator {
                     self.lvarstransfer.set_unchecked(
                         conn,
@@ -1534,26 +2115,242 @@ impl Definitions {
         self.jstransfer.write_payload(JSPayloads::RequestTime, None);
     }
 
+    fn poll_actions(&mut self, conn: &SimConnector) {
+        for var_name in self.polled_vars.iter() {
+            if let Some(mappings) = self.mappings.get_mut(var_name) {
+                for mapping in mappings {
+                    match &mut mapping.action {
+                        ActionType::Bool(action) => action.poll(conn, &mut self.lvarstransfer),
+                        ActionType::I32(action) => action.poll(conn, &mut self.lvarstransfer),
+                        ActionType::F64(action) => action.poll(conn, &mut self.lvarstransfer),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    fn queue_sequence(
+        &mut self,
+        var_name: &str,
+        sequence_index: usize,
+        value: VarReaderTypes,
+        now: Instant,
+    ) {
+        // Only run when the value actually differs from what the sim has
+        let current = if var_name.starts_with("L:") {
+            self.lvarstransfer
+                .get_var(var_name)
+                .map(VarReaderTypes::F64)
+        } else {
+            self.avarstransfer.get_var(var_name).cloned()
+        };
+
+        if current.as_ref() == Some(&value) {
+            return;
+        }
+
+        let mut execute_at = now;
+
+        for (step_index, step) in self.sequences[sequence_index].iter().enumerate() {
+            execute_at += step.delay;
+
+            self.sequence_queue.push_back(QueuedSequenceStep {
+                sequence_index,
+                step_index,
+                value: value.clone(),
+                execute_at,
+            });
+        }
+    }
+
+    fn execute_sequence_step(&mut self, conn: &SimConnector, queued: QueuedSequenceStep) {
+        let step = &self.sequences[queued.sequence_index][queued.step_index];
+
+        if !evaluate_conditions(
+            &self.lvarstransfer,
+            &self.avarstransfer,
+            step.condition.as_ref(),
+            &queued.value,
+            None,
+        ) {
+            return;
+        }
+
+        match &step.action {
+            SequenceAction::KeyEvent {
+                event_name,
+                event_param,
+            } => {
+                self.echo_suppressor.expect_event(event_name, *event_param);
+                self.events
+                    .trigger_event(conn, event_name, *event_param)
+                    .ok();
+            }
+            SequenceAction::HEvent(event_name) => {
+                self.lvarstransfer.set_unchecked(conn, event_name, None, "")
+            }
+            SequenceAction::SetVar {
+                var_name,
+                var_units,
+                value,
+            } => {
+                self.lvarstransfer.set_unchecked(
+                    conn,
+                    var_name,
+                    var_units.as_deref(),
+                    &value.to_string(),
+                );
+                self.echo_suppressor.expect_value(
+                    var_name,
+                    VarReaderTypes::F64(*value),
+                    self.lvarstransfer
+                        .get_var(var_name)
+                        .map(VarReaderTypes::F64)
+                        .as_ref(),
+                );
+            }
+        }
+    }
+
+    // Steps of the same sequence are queued in order, so the first due step is always next
+    fn take_due_sequence_step(&mut self, now: Instant) -> Option<QueuedSequenceStep> {
+        let index = self
+            .sequence_queue
+            .iter()
+            .position(|queued| queued.execute_at <= now)?;
+
+        self.sequence_queue.remove(index)
+    }
+
+    fn process_sequences(&mut self, conn: &SimConnector) {
+        let now = Instant::now();
+
+        while let Some(queued) = self.take_due_sequence_step(now) {
+            self.execute_sequence_step(conn, queued);
+        }
+    }
+
     pub fn step(&mut self, conn: &SimConnector) -> Result<(), Error> {
         self.process_js_data();
+        self.lvarstransfer.input_events.poll();
+        self.poll_actions(conn);
+        self.process_sequences(conn);
+        self.send_settled_deadbands(Instant::now());
+        self.extrapolate_physics(conn);
         self.process_events(conn)
     }
 
+    fn send_settled_deadbands(&mut self, now: Instant) {
+        let settled: Vec<String> = self
+            .deadbands
+            .iter_mut()
+            .filter_map(|(var_name, deadband)| deadband.take_settled(now).map(|_| var_name.clone()))
+            .collect();
+
+        for var_name in settled {
+            // Send what the sim reads now, in the var's own type
+            if let Some(value) = self.avarstransfer.get_var(&var_name) {
+                let value = value.clone();
+                self.current_sync
+                    .avars
+                    .insert(var_name.clone(), value.clone());
+                self.emulator.record_last_known(&var_name, value);
+            } else if let Some(value) = self.lvarstransfer.get_var(&var_name) {
+                self.current_sync
+                    .lvars
+                    .insert(var_name.clone(), VarReaderTypes::F64(value));
+                self.emulator
+                    .record_last_known(&var_name, VarReaderTypes::F64(value));
+            } else {
+                continue;
+            }
+
+            self.stamp_shared_var(&var_name);
+        }
+    }
+
+    fn extrapolate_physics(&mut self, conn: &SimConnector) {
+        if self.pause_sync.is_paused() {
+            return;
+        }
+
+        let dead_reckoning = match self.dead_reckoning.as_mut() {
+            Some(dead_reckoning) => dead_reckoning,
+            None => return,
+        };
+
+        if let Some((time, data)) = dead_reckoning.extrapolate(Instant::now()) {
+            self.lvarstransfer
+                .transfer
+                .send_new_interpolation_data(conn, time, &data);
+        }
+    }
+
     fn split_unreliable(&self, data: &mut AllNeedSync) -> AllNeedSync {
         data.filter_keep(|name| {
             self.interpolate_vars.contains(name) || self.unreliable_vars.contains(name)
         })
     }
 
-    fn filter_all_sync(
-        &self,
-        mut data: AllNeedSync,
-        sync_permission: &SyncPermission,
-    ) -> (Option<AllNeedSync>, Option<AllNeedSync>) {
-        // Filter out based on what the client's current permissions are
-        data.filter(|name| self.can_sync(name, sync_permission));
+    fn get_priority(&self, var_name: &str) -> Priority {
+        match self.priorities.get(var_name) {
+            Some(priority) => *priority,
+            None if self.interpolate_vars.contains(var_name) => Priority::FlightControls,
+            None => Priority::Systems,
+        }
+    }
+
+    // Puts vars that don't fit in the send budget back in the queue, where newer values replace them
+    fn apply_send_budget(&mut self, data: &mut AllNeedSync) {
+        self.send_budget.refill();
+
+        let mut names: Vec<(Priority, String)> = data
+            .avars
+            .keys()
+            .chain(data.lvars.keys())
+            .map(|name| (self.get_priority(name), name.clone()))
+            .collect();
+        names.sort();
+
+        let has_packet = self.send_budget.has_packet();
+        let mut deferred = 0;
+
+        for (priority, name) in names {
+            if has_packet
+                && self
+                    .send_budget
+                    .try_spend_bytes(estimate_var_size(&name), priority)
+            {
+                continue;
+            }
+
+            if let Some(value) = data.avars.remove(&name) {
+                self.current_sync.avars.insert(name.clone(), value);
+            }
+            if let Some(value) = data.lvars.remove(&name) {
+                self.current_sync.lvars.insert(name.clone(), value);
+            }
+            if let Some(stamp) = data.stamps.remove(&name) {
+                self.current_sync.stamps.insert(name, stamp);
+            }
+
+            deferred += 1;
+        }
+
+        if deferred > 0 {
+            debug!("[SYNC] Over send budget, deferred {} vars", deferred);
+        }
+    }
+
+    fn filter_all_sync(&self, mut data: AllNeedSync) -> (Option<AllNeedSync>, Option<AllNeedSync>) {
         // Split into interpolated vs non interpolated values - used for reliable/unreliable transmissions
-        let regular = self.split_unreliable(&mut data);
+        // Jumps all go reliably, a lost one would have peers interpolate across the world
+        let regular = if data.snap {
+            std::mem::take(&mut data)
+        } else {
+            self.split_unreliable(&mut data)
+        };
         // Convert into options
         let unreliable = if data.is_empty() { None } else { Some(data) };
         let regular = if regular.is_empty() {
@@ -1571,7 +2368,30 @@ impl Definitions {
     ) -> (Option<AllNeedSync>, Option<AllNeedSync>) {
         let mut data = AllNeedSync::new();
         std::mem::swap(&mut self.current_sync, &mut data);
-        self.filter_all_sync(data, sync_permission)
+        // Filter out based on what the client's current permissions are
+        data.filter(|name| self.can_sync(name, sync_permission));
+
+        if let Some(teleport_detector) = self.teleport_detector.as_mut() {
+            data.snap = teleport_detector.take_should_snap();
+        }
+
+        // Nothing can be held back from a jump, it has to arrive in one piece
+        if !data.snap {
+            self.apply_send_budget(&mut data);
+        }
+
+        // Remove some computed components
+        if let Some(physics_corrector) = self.physics_corrector.as_ref() {
+            physics_corrector.remove_components(&mut data.avars);
+        }
+
+        let (unreliable, regular) = self.filter_all_sync(data);
+
+        for _ in unreliable.iter().chain(regular.iter()) {
+            self.send_budget.spend_packet();
+        }
+
+        (unreliable, regular)
     }
 
     fn can_sync(&self, var_name: &str, sync_permission: &SyncPermission) -> bool {
@@ -1585,23 +2405,89 @@ impl Definitions {
         }
     }
 
+    // Tells the echo suppressor what the sim will read back while a remote value gets applied
+    fn expect_readback(
+        &mut self,
+        var_name: &str,
+        target: &VarReaderTypes,
+        from: Option<&VarReaderTypes>,
+        interpolated: bool,
+    ) {
+        if interpolated {
+            self.echo_suppressor
+                .expect_interpolation(var_name, target.clone(), from);
+            return;
+        }
+
+        // Selectors and the like know which way they'll turn to get there
+        let path = from.and_then(|from| {
+            self.mappings
+                .get(var_name)?
+                .iter()
+                .filter(|mapping| {
+                    evaluate_conditions(
+                        &self.lvarstransfer,
+                        &self.avarstransfer,
+                        mapping.condition.as_ref(),
+                        target,
+                        None,
+                    )
+                })
+                .find_map(|mapping| mapping.action.get_readback_path(from, target))
+        });
+
+        match path {
+            Some(path) => self
+                .echo_suppressor
+                .expect_steps(var_name, target.clone(), path.into()),
+            None => self
+                .echo_suppressor
+                .expect_value(var_name, target.clone(), from),
+        }
+    }
+
     #[allow(unused_variables)]
-    fn write_aircraft_data(&mut self, conn: &SimConnector, data: VarMap, time: f64) {
+    fn write_aircraft_data(
+        &mut self,
+        conn: &SimConnector,
+        mut data: VarMap,
+        time: f64,
+        snap: bool,
+    ) {
         if data.is_empty() {
             return;
         }
 
+        // The aircraft jumped, anything in flight or extrapolated is stale
+        if snap {
+            self.lvarstransfer.transfer.stop_interpolation(conn);
+
+            if let Some(dead_reckoning) = self.dead_reckoning.as_mut() {
+                dead_reckoning.reset();
+            }
+        }
+
         let mut to_sync = VarMap::new();
         to_sync.reserve(data.len());
 
         let mut interpolation_data = Vec::new();
+        let mut sequences = Vec::new();
 
         // Add some local computed components
-        // self.physics_corrector.add_components(&mut data);
+        if let Some(physics_corrector) = self.physics_corrector.as_ref() {
+            physics_corrector.add_components(&mut data);
+        }
 
         // Only sync vars that are defined as so
-        for (var_name, data) in data {
-            set_did_write_recently(&mut self.last_written, &var_name);
+        for (var_name, mut data) in data {
+            // Expect the string as the sim will store it, or the readback would look like a change
+            if let VarReaderTypes::String(value) = &mut data {
+                fit_string(value);
+            }
+
+            let from = self.avarstransfer.get_var(&var_name).cloned();
+            let interpolated = self.interpolate_vars.contains(&var_name) && !snap;
+            self.expect_readback(&var_name, &data, from.as_ref(), interpolated);
 
             // Otherwise sync them using defined events
             if let Some(mappings) = self.mappings.get_mut(&var_name) {
@@ -1616,6 +2502,11 @@ impl Definitions {
                         continue;
                     }
 
+                    if let ActionType::Sequence(index) = mapping.action {
+                        sequences.push((var_name.clone(), index, data.clone()));
+                        continue;
+                    }
+
                     execute_mapping!(
                         new_value,
                         action,
@@ -1623,7 +2514,7 @@ impl Definitions {
                         mapping,
                         { action.set_new(new_value, conn, &mut self.lvarstransfer) },
                         {
-                            if self.interpolate_vars.contains(&var_name) {
+                            if self.interpolate_vars.contains(&var_name) && !snap {
                                 // Queue data for interpolation
                                 interpolation_data.push(InterpolateData {
                                     name: var_name.clone(),
@@ -1631,7 +2522,7 @@ impl Definitions {
                                 });
                             } else {
                                 // Set data right away
-                                to_sync.insert(var_name.clone(), data);
+                                to_sync.insert(var_name.clone(), data.clone());
                             }
                         },
                         {}
@@ -1640,7 +2531,12 @@ impl Definitions {
             }
         }
 
-        if !interpolation_data.is_empty() {
+        // Nothing should be moving while paused
+        if !interpolation_data.is_empty() && !self.pause_sync.is_paused() {
+            if let Some(dead_reckoning) = self.dead_reckoning.as_mut() {
+                dead_reckoning.on_sample(time, &mut interpolation_data, Instant::now());
+            }
+
             self.lvarstransfer.transfer.send_new_interpolation_data(
                 conn,
                 time,
@@ -1651,11 +2547,34 @@ impl Definitions {
         if !to_sync.is_empty() {
             self.avarstransfer.set_vars(conn, &to_sync);
         }
+
+        for (var_name, index, value) in sequences {
+            self.queue_sequence(&var_name, index, value, Instant::now());
+        }
     }
 
     #[allow(unused_variables)]
-    fn write_local_data(&mut self, conn: &SimConnector, data: VarMap) -> Result<(), Error> {
+    fn write_local_data(
+        &mut self,
+        conn: &SimConnector,
+        data: VarMap,
+        time: f64,
+    ) -> Result<(), Error> {
+        let mut interpolation_data = Vec::new();
+        let mut sequences = Vec::new();
+
         for (var_name, value) in &data {
+            let from = self
+                .lvarstransfer
+                .get_var(var_name)
+                .map(VarReaderTypes::F64);
+            let interpolated = self.interpolate_vars.contains(var_name)
+                && self
+                    .lvarstransfer
+                    .transfer
+                    .has_interpolate_mapping(var_name);
+            self.expect_readback(var_name, value, from.as_ref(), interpolated);
+
             match self.mappings.get_mut(var_name) {
                 Some(mappings) => {
                     for mapping in mappings {
@@ -1669,6 +2588,25 @@ impl Definitions {
                             continue;
                         }
 
+                        if let ActionType::Sequence(index) = mapping.action {
+                            sequences.push((var_name.clone(), index, value.clone()));
+                            continue;
+                        }
+
+                        // Input events are interpolated by the gauge through their set binding
+                        if self.interpolate_vars.contains(var_name)
+                            && self
+                                .lvarstransfer
+                                .transfer
+                                .has_interpolate_mapping(var_name)
+                        {
+                            interpolation_data.push(InterpolateData {
+                                name: var_name.clone(),
+                                value: value.get_as_f64(),
+                            });
+                            continue;
+                        }
+
                         execute_mapping!(
                             new_value,
                             action,
@@ -1681,14 +2619,24 @@ impl Definitions {
                             },
                             {}
                         );
-
-                        set_did_write_recently(&mut self.last_written, var_name);
                     }
                 }
                 None => return Err(Error::MissingMapping(var_name.clone())),
             }
         }
 
+        if !interpolation_data.is_empty() && !self.pause_sync.is_paused() {
+            self.lvarstransfer.transfer.send_new_interpolation_data(
+                conn,
+                time,
+                &interpolation_data,
+            );
+        }
+
+        for (var_name, index, value) in sequences {
+            self.queue_sequence(&var_name, index, value, Instant::now());
+        }
+
         Ok(())
     }
 
@@ -1700,6 +2648,47 @@ impl Definitions {
         Ok(())
     }
 
+    fn stamp_shared_var(&mut self, var_name: &str) {
+        if self.categories.get(var_name) != Some(&Category::Shared) {
+            return;
+        }
+
+        let stamp = self.shared_clock.stamp_local(var_name);
+        self.current_sync.stamps.insert(var_name.to_string(), stamp);
+    }
+
+    // Drops shared vars that lost to a newer local change, unstamped vars (older clients, full syncs) are always applied
+    fn resolve_shared_conflicts(&mut self, data: &mut AllNeedSync) {
+        for (var_name, stamp) in std::mem::take(&mut data.stamps) {
+            if self.shared_clock.receive(&var_name, stamp) {
+                continue;
+            }
+
+            data.avars.remove(&var_name);
+            data.lvars.remove(&var_name);
+
+            // Resend our value so the other side converges on it too
+            let local_stamp = match self.shared_clock.get_local_stamp(&var_name) {
+                Some(stamp) => stamp,
+                None => continue,
+            };
+
+            if let Some(value) = self.avarstransfer.get_var(&var_name) {
+                self.current_sync
+                    .avars
+                    .insert(var_name.clone(), value.clone());
+            } else if let Some(value) = self.lvarstransfer.get_var(&var_name) {
+                self.current_sync
+                    .lvars
+                    .insert(var_name.clone(), VarReaderTypes::F64(value));
+            } else {
+                continue;
+            }
+
+            self.current_sync.stamps.insert(var_name, local_stamp);
+        }
+    }
+
     pub fn on_receive_data(
         &mut self,
         conn: &SimConnector,
@@ -1709,12 +2698,13 @@ impl Definitions {
     ) -> Result<(), Error> {
         self.emulator.update_from_sync(&data);
         data.filter(|name| self.can_sync(name, sync_permission));
+        self.resolve_shared_conflicts(&mut data);
 
         // In this specific order
         // Aircraft var data should overwrite any event data
         self.write_event_data(data.events)?;
-        self.write_aircraft_data(conn, data.avars, time);
-        self.write_local_data(conn, data.lvars)?;
+        self.write_aircraft_data(conn, data.avars, time, data.snap);
+        self.write_local_data(conn, data.lvars, time)?;
 
         Ok(())
     }
@@ -1728,11 +2718,33 @@ impl Definitions {
     ) -> Result<(), ()> {
         self.freezer.register_vars(&mut self.avarstransfer);
         self.freezer.set_is_client(is_client);
+        self.pause_sync.register_vars(&mut self.avarstransfer);
+        self.do_not_sync.insert(SIM_RATE_VAR.to_string());
+
+        if let Some(teleport_detector) = self.teleport_detector.as_ref() {
+            teleport_detector.register_vars(&mut self.avarstransfer);
+            self.do_not_sync.insert(SLEW_VAR.to_string());
+        }
 
         self.avarstransfer.on_connected(conn);
         self.events.on_connected(conn);
         self.lvarstransfer.on_connected(conn);
-        self.physics_corrector.on_connected(conn);
+
+        // Hashes differ per aircraft, look them up for the one just loaded
+        if self.lvarstransfer.input_events.has_registered() {
+            self.lvarstransfer.input_events.enumerate();
+        }
+
+        if let Some(physics_corrector) = self.physics_corrector.as_ref() {
+            physics_corrector.on_connected(conn);
+        }
+
+        self.pause_sync.on_connected(conn);
+        self.weather_sync.on_connected(conn);
+
+        if let Some(teleport_detector) = self.teleport_detector.as_ref() {
+            teleport_detector.on_connected(conn);
+        }
 
         // Might be running another instance
         if !skip_sim_connect {
@@ -1760,7 +2772,7 @@ impl Definitions {
     }
 
     pub fn get_all_current(&self) -> AllNeedSync {
-        let avars = self
+        let mut avars = self
             .avarstransfer
             .get_all_vars()
             .clone()
@@ -1768,7 +2780,10 @@ impl Definitions {
             .filter(|(x, _)| !self.do_not_sync.contains(x))
             .collect();
 
-        // self.physics_corrector.remove_components(&mut avars);
+        // Remove some computed components
+        if let Some(physics_corrector) = self.physics_corrector.as_ref() {
+            physics_corrector.remove_components(&mut avars);
+        }
 
         AllNeedSync {
             avars,
@@ -1780,12 +2795,23 @@ impl Definitions {
                 .map(|(k, v)| (k, VarReaderTypes::F64(v)))
                 .collect(),
             events: EventData::new(),
+            stamps: HashMap::new(),
+            // Peers could be anywhere before a full sync
+            snap: true,
         }
     }
 
     pub fn reset_sync(&mut self) {
         self.current_sync.clear();
-        self.last_written.clear();
+        self.echo_suppressor.clear();
+
+        for deadband in self.deadbands.values_mut() {
+            deadband.reset();
+        }
+
+        if let Some(dead_reckoning) = self.dead_reckoning.as_mut() {
+            dead_reckoning.reset();
+        }
     }
 
     pub fn get_number_avars(&self) -> usize {
@@ -1820,4 +2846,235 @@ impl Definitions {
     pub fn has_control(&self) -> bool {
         self.freezer.has_control()
     }
+
+    // Pause or sim rate changed in our sim and should be sent out
+    pub fn get_next_pause_change(&mut self) -> Option<PauseState> {
+        self.pause_sync.get_next_change()
+    }
+
+    pub fn get_pause_state(&self) -> PauseState {
+        self.pause_sync.get_state()
+    }
+
+    pub fn set_pause_state(&mut self, conn: &SimConnector, state: PauseState) {
+        let was_paused = self.pause_sync.is_paused();
+
+        self.pause_sync
+            .apply(conn, &self.lvarstransfer.transfer, state);
+
+        if state.paused && !was_paused {
+            self.lvarstransfer.transfer.stop_interpolation(conn);
+        }
+
+        // Don't extrapolate over the time spent paused
+        if let Some(dead_reckoning) = self.dead_reckoning.as_mut() {
+            dead_reckoning.reset();
+        }
+    }
+
+    // Our weather if it should be published again
+    pub fn get_next_weather_publish(&mut self) -> Option<WeatherSample> {
+        self.weather_sync.get_next_publish()
+    }
+
+    // Returns the differences to the host's weather that are left
+    pub fn sync_weather(
+        &mut self,
+        conn: &SimConnector,
+        weather: &WeatherSample,
+        apply: bool,
+    ) -> Vec<String> {
+        self.weather_sync
+            .on_remote_weather(conn, &self.lvarstransfer.transfer, weather, apply)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs::read_dir, path::PathBuf};
+
+    fn get_yaml_files(dir: &Path, files: &mut Vec<PathBuf>) {
+        for entry in read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+
+            if path.is_dir() {
+                get_yaml_files(&path, files);
+            } else if path.extension().map(|x| x == "yaml").unwrap_or(false) {
+                files.push(path);
+            }
+        }
+    }
+
+    #[test]
+    fn test_definitions_match_schema() {
+        let schema: serde_json::Value =
+            serde_json::from_str(&get_definition_schema_string()).unwrap();
+        let schema = jsonschema::JSONSchema::compile(&schema).unwrap();
+
+        let mut files = Vec::new();
+        get_yaml_files(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("../../definitions"),
+            &mut files,
+        );
+        assert!(!files.is_empty());
+
+        let mut failed = Vec::new();
+
+        for file in files {
+            let value: serde_json::Value = serde_yaml::from_reader(File::open(&file).unwrap())
+                .unwrap_or_else(|e| panic!("{}: {}", file.display(), e));
+
+            if let Err(errors) = schema.validate(&value) {
+                for error in errors {
+                    failed.push(format!(
+                        "{} at {}: {}",
+                        file.display(),
+                        error.instance_path,
+                        error
+                    ));
+                }
+            };
+        }
+
+        assert!(failed.is_empty(), "{}", failed.join("\n"));
+    }
+
+    fn get_sequence_definitions() -> Definitions {
+        let mut definitions = Definitions::new();
+        let entry: SequenceEntry = serde_yaml::from_str(
+            r#"
+            var_name: L:STARTER
+            on_change:
+              - event_name: TOGGLE_STARTER1
+              - var_name: L:FUEL_PUMP
+                value: 1.0
+                delay: 500
+              - event_name: H:IGNITION
+                delay: 250
+            "#,
+        )
+        .unwrap();
+
+        definitions.add_sequence("shared", entry).unwrap();
+        definitions
+    }
+
+    fn take_due_steps(definitions: &mut Definitions, now: Instant) -> Vec<(usize, usize)> {
+        let mut steps = Vec::new();
+
+        while let Some(queued) = definitions.take_due_sequence_step(now) {
+            steps.push((queued.sequence_index, queued.step_index));
+        }
+
+        steps
+    }
+
+    #[test]
+    fn test_sequence_steps_run_in_order() {
+        let mut definitions = get_sequence_definitions();
+        let now = Instant::now();
+
+        definitions.queue_sequence("L:STARTER", 0, VarReaderTypes::F64(1.0), now);
+
+        // Delays add up, each step waits on the one before it
+        assert_eq!(take_due_steps(&mut definitions, now), vec![(0, 0)]);
+        assert!(take_due_steps(&mut definitions, now + Duration::from_millis(499)).is_empty());
+        assert_eq!(
+            take_due_steps(&mut definitions, now + Duration::from_millis(500)),
+            vec![(0, 1)]
+        );
+        assert_eq!(
+            take_due_steps(&mut definitions, now + Duration::from_millis(750)),
+            vec![(0, 2)]
+        );
+    }
+
+    #[test]
+    fn test_sequence_completes() {
+        let mut definitions = get_sequence_definitions();
+        let now = Instant::now();
+
+        // Changed twice, both runs go through in full and in order
+        definitions.queue_sequence("L:STARTER", 0, VarReaderTypes::F64(1.0), now);
+        definitions.queue_sequence(
+            "L:STARTER",
+            0,
+            VarReaderTypes::F64(0.0),
+            now + Duration::from_millis(100),
+        );
+
+        assert_eq!(
+            take_due_steps(&mut definitions, now + Duration::from_secs(1)),
+            vec![(0, 0), (0, 1), (0, 2), (0, 0), (0, 1), (0, 2)]
+        );
+        assert!(definitions.sequence_queue.is_empty());
+    }
+
+    fn read_local_var(definitions: &mut Definitions, var_name: &str, value: f64) {
+        definitions.process_local_var(GetResult {
+            var_name: var_name.to_string(),
+            value,
+        });
+    }
+
+    #[test]
+    fn test_local_change_sent_while_remote_write_settles() {
+        let mut definitions = Definitions::new();
+
+        // A remote knob turn from 2 to 5, stepped through by the sim
+        definitions.echo_suppressor.expect_value(
+            "L:KNOB",
+            VarReaderTypes::F64(5.0),
+            Some(&VarReaderTypes::F64(2.0)),
+        );
+        read_local_var(&mut definitions, "L:KNOB", 3.0);
+        assert!(definitions.current_sync.lvars.is_empty());
+
+        // The pilot turns it back before it gets there
+        read_local_var(&mut definitions, "L:KNOB", 2.0);
+        assert_eq!(
+            definitions.current_sync.lvars.get("L:KNOB"),
+            Some(&VarReaderTypes::F64(2.0))
+        );
+
+        // A remote lever move, the pilot grabs it halfway
+        definitions.echo_suppressor.expect_value(
+            "L:LEVER",
+            VarReaderTypes::F64(0.8),
+            Some(&VarReaderTypes::F64(0.2)),
+        );
+        read_local_var(&mut definitions, "L:LEVER", 0.5);
+        assert_eq!(
+            definitions.current_sync.lvars.get("L:LEVER"),
+            Some(&VarReaderTypes::F64(0.5))
+        );
+    }
+
+    #[test]
+    fn test_deadband_sends_settled_value() {
+        let mut deadband = Deadband::new(1.0);
+        let now = Instant::now();
+
+        assert!(deadband.do_update(10.0, now));
+        // Creeps by less than the deadband each time
+        assert!(!deadband.do_update(10.4, now + Duration::from_millis(100)));
+        assert!(!deadband.do_update(10.8, now + Duration::from_millis(200)));
+        assert_eq!(
+            deadband.take_settled(now + Duration::from_millis(600)),
+            None
+        );
+
+        // Stays put, so the final value goes out once
+        assert_eq!(
+            deadband.take_settled(now + Duration::from_millis(700)),
+            Some(10.8)
+        );
+        assert_eq!(deadband.take_settled(now + Duration::from_secs(2)), None);
+
+        // Measured from what was last sent now
+        assert!(!deadband.do_update(11.5, now + Duration::from_secs(2)));
+        assert!(deadband.do_update(11.9, now + Duration::from_secs(2)));
+        assert_eq!(deadband.take_settled(now + Duration::from_secs(5)), None);
+    }
 }
@@ -1,7 +1,8 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 use crate::util::InDataTypes;
+use crate::varreader::fit_string;
 use yourcontrols_types::{AllNeedSync, Error, VarReaderTypes};
 
 #[derive(Serialize)]
@@ -10,7 +11,15 @@ pub struct EmulatorVarInfo {
     pub name: String,
     pub display_name: String,
     pub var_type: String,
-    pub value: Option<f64>,
+    pub value: Option<EmulatorValue>,
+}
+
+// Numbers are shown as a number input, strings as a text input
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(untagged)]
+pub enum EmulatorValue {
+    Number(f64),
+    Text(String),
 }
 
 #[derive(Clone, Copy)]
@@ -53,11 +62,11 @@ impl EmulatorState {
 
     pub fn update_from_sync(&mut self, data: &AllNeedSync) {
         for (name, value) in &data.avars {
-            self.record_last_known(name, *value);
+            self.record_last_known(name, value.clone());
         }
 
         for (name, value) in &data.lvars {
-            self.record_last_known(name, *value);
+            self.record_last_known(name, value.clone());
         }
     }
 
@@ -75,7 +84,7 @@ impl EmulatorState {
                     value: self
                         .last_known_values
                         .get(&entry.name)
-                        .map(var_reader_to_f64),
+                        .map(var_reader_to_emulator_value),
                 }
             })
             .collect();
@@ -96,15 +105,15 @@ impl EmulatorState {
             value: self
                 .last_known_values
                 .get(&entry.name)
-                .map(var_reader_to_f64)
-                .or(Some(0.0)),
+                .map(var_reader_to_emulator_value)
+                .or_else(|| Some(default_emulator_value(entry.var_type))),
         })
     }
 
     pub fn apply_value(
         &mut self,
         id: &str,
-        value: f64,
+        value: EmulatorValue,
         current_sync: &mut AllNeedSync,
     ) -> Result<(), Error> {
         let entry = self
@@ -112,19 +121,34 @@ impl EmulatorState {
             .get(id)
             .ok_or_else(|| Error::MissingMapping(id.to_string()))?;
 
-        let typed_value = match entry.var_type {
-            InDataTypes::I32 => VarReaderTypes::I32(value as i32),
-            InDataTypes::I64 => VarReaderTypes::I64(value as i64),
-            InDataTypes::F64 => VarReaderTypes::F64(value),
-            InDataTypes::Bool => VarReaderTypes::Bool(value != 0.0),
+        let typed_value = match (entry.var_type, value) {
+            (InDataTypes::String, EmulatorValue::Text(mut value)) => {
+                fit_string(&mut value);
+                VarReaderTypes::String(value)
+            }
+            (InDataTypes::String, EmulatorValue::Number(value)) => {
+                VarReaderTypes::String(value.to_string())
+            }
+            (var_type, EmulatorValue::Number(value)) => number_to_var_reader(var_type, value),
+            (var_type, EmulatorValue::Text(value)) => number_to_var_reader(
+                var_type,
+                value
+                    .trim()
+                    .parse()
+                    .map_err(|_| Error::InvalidSyncType(value.clone()))?,
+            ),
         };
 
         match entry.source {
             EmulatorVarSource::Aircraft => {
-                current_sync.avars.insert(entry.name.clone(), typed_value);
+                current_sync
+                    .avars
+                    .insert(entry.name.clone(), typed_value.clone());
             }
             EmulatorVarSource::Local => {
-                current_sync.lvars.insert(entry.name.clone(), typed_value);
+                current_sync
+                    .lvars
+                    .insert(entry.name.clone(), typed_value.clone());
             }
         }
 
@@ -140,21 +164,40 @@ fn var_type_to_string(var_type: InDataTypes) -> &'static str {
         InDataTypes::I64 => "i64",
         InDataTypes::F64 => "f64",
         InDataTypes::Bool => "bool",
+        InDataTypes::String => "string",
+    }
+}
+
+fn number_to_var_reader(var_type: InDataTypes, value: f64) -> VarReaderTypes {
+    match var_type {
+        InDataTypes::I32 => VarReaderTypes::I32(value as i32),
+        InDataTypes::I64 => VarReaderTypes::I64(value as i64),
+        InDataTypes::F64 => VarReaderTypes::F64(value),
+        InDataTypes::Bool => VarReaderTypes::Bool(value != 0.0),
+        InDataTypes::String => VarReaderTypes::String(value.to_string()),
+    }
+}
+
+fn default_emulator_value(var_type: InDataTypes) -> EmulatorValue {
+    match var_type {
+        InDataTypes::String => EmulatorValue::Text(String::new()),
+        _ => EmulatorValue::Number(0.0),
     }
 }
 
-fn var_reader_to_f64(value: &VarReaderTypes) -> f64 {
+fn var_reader_to_emulator_value(value: &VarReaderTypes) -> EmulatorValue {
     match value {
-        VarReaderTypes::F64(v) => *v,
-        VarReaderTypes::I32(v) => *v as f64,
-        VarReaderTypes::I64(v) => *v as f64,
+        VarReaderTypes::F64(v) => EmulatorValue::Number(*v),
+        VarReaderTypes::I32(v) => EmulatorValue::Number(*v as f64),
+        VarReaderTypes::I64(v) => EmulatorValue::Number(*v as f64),
         VarReaderTypes::Bool(v) => {
             if *v {
-                1.0
+                EmulatorValue::Number(1.0)
             } else {
-                0.0
+                EmulatorValue::Number(0.0)
             }
         }
+        VarReaderTypes::String(v) => EmulatorValue::Text(v.clone()),
     }
 }
 
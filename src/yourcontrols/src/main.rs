@@ -5,6 +5,7 @@ mod app;
 mod cli;
 mod clientmanager;
 mod corrector;
+mod deadreckoning;
 mod definitions;
 mod emulator;
 mod paths;
@@ -12,14 +13,16 @@ mod program;
 mod simconfig;
 mod sync;
 mod syncdefs;
+mod teleport;
 mod update;
 mod util;
 mod varreader;
+mod weather;
 
 use cli::CliWrapper;
 use program::Program;
 use simplelog::{CombinedLogger, Config, LevelFilter, SharedLogger, SimpleLogger, WriteLogger};
-use std::{env, fs::File};
+use std::{env, fs, fs::File};
 
 const LOG_FILENAME: &str = "log.txt";
 
@@ -27,6 +30,17 @@ fn main() {
     let cli: CliWrapper = CliWrapper::new();
     let is_dev_build = cfg!(debug_assertions);
 
+    if cli.is_definition_schema_command() {
+        let schema = definitions::get_definition_schema_string();
+
+        match cli.definition_schema_output() {
+            Some(path) => fs::write(path, schema).expect("Could not write schema file"),
+            None => println!("{}", schema),
+        }
+
+        return;
+    }
+
     if !is_dev_build {
         // Set CWD to application directory
         let exe_path = env::current_exe();
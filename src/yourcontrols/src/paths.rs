@@ -42,4 +42,9 @@ impl DefinitionPathResolver {
     pub fn get_fs_2024_configs() -> io::Result<Vec<String>> {
         Self::get_filenames("FS2024")
     }
+
+    /// Gets where definitions received from a host are kept, by the hash of their contents.
+    pub fn get_cached_definition(hash: &str) -> PathBuf {
+        PathBuf::from(format!("definitions/cache/{}.bin", hash))
+    }
 }
@@ -9,6 +9,7 @@ use crate::update::Updater;
 
 mod app_loop;
 mod emulator_runtime;
+mod jitter;
 mod network;
 mod simconnect;
 mod state;
@@ -86,7 +87,12 @@ impl Program {
                         NetworkController::poll(&mut self.network, &mut net_ctx);
                     }
 
-                    SyncController::tick(&mut self.sync, &mut self.network, &mut self.sim);
+                    SyncController::tick(
+                        &mut self.sync,
+                        &mut self.network,
+                        &mut self.sim,
+                        &self.config,
+                    );
                 }
             }
 
@@ -1,5 +1,7 @@
+use crossbeam_channel::{unbounded, Receiver};
 use log::{error, info};
-use yourcontrols_net::{Client, Server};
+use std::{path::Path, thread};
+use yourcontrols_net::{detect_nat_type, Client, LanBrowser, NatType, Server};
 
 use crate::app::{App, AppMessage, ConnectionMethod};
 use crate::cli::CliWrapper;
@@ -18,6 +20,9 @@ pub struct AppState {
     pub(crate) app_interface: App,
     pub(crate) installer_spawned: bool,
     pub(crate) definitions_to_load: String,
+    pub(crate) nat_type: Option<NatType>,
+    nat_detection: Option<Receiver<Result<NatType, String>>>,
+    lan_browser: Option<LanBrowser>,
 }
 
 impl AppState {
@@ -26,6 +31,9 @@ impl AppState {
             app_interface,
             installer_spawned: false,
             definitions_to_load: String::new(),
+            nat_type: None,
+            nat_detection: None,
+            lan_browser: None,
         }
     }
 }
@@ -46,6 +54,9 @@ impl AppController {
         if let Ok(msg) = state.app_interface.get_next_message() {
             AppHandler::handle_message(msg, state, ctx);
         }
+
+        AppHandler::handle_nat_detection(state);
+        AppHandler::handle_lan_discovery(state);
     }
 }
 
@@ -82,6 +93,9 @@ impl AppHandler {
             } => {
                 let connected = Self::connect_to_sim(state, ctx);
 
+                Self::warn_for_nat(state, &method, false);
+                state.lan_browser = None;
+
                 if connected {
                     // Display attempting to start server
                     state.app_interface.attempt();
@@ -119,6 +133,22 @@ impl AppHandler {
                     ctx.config.write();
                 }
             }
+            AppMessage::StartLanDiscovery => {
+                if state.lan_browser.is_some() {
+                    return;
+                }
+
+                match LanBrowser::bind() {
+                    Ok(browser) => {
+                        info!("[NETWORK] Looking for sessions on the LAN.");
+                        state.lan_browser = Some(browser);
+                    }
+                    Err(e) => error!("[NETWORK] Could not look for LAN sessions! Reason: {}", e),
+                }
+            }
+            AppMessage::StopLanDiscovery => {
+                state.lan_browser = None;
+            }
             AppMessage::Disconnect => {
                 info!("[NETWORK] Request to disconnect.");
                 if let Some(client) = ctx.network.transfer_client.as_mut() {
@@ -213,6 +243,7 @@ impl AppHandler {
                     info!("[UPDATER] Version {} in use.", app_version)
                 }
 
+                Self::start_nat_detection(state);
                 Self::handle_ui_startup(state, ctx);
                 EmulatorController::set_enabled(
                     &mut ctx.program_state.emulator,
@@ -363,6 +394,9 @@ impl AppHandler {
         // Display attempting to start server
         state.app_interface.attempt();
 
+        Self::warn_for_nat(state, &server_params.method, true);
+        state.lan_browser = None;
+
         let name = ctx.config.name.clone();
         let conn_timeout = ctx.config.conn_timeout;
         let version = ctx.updater.get_version_string();
@@ -372,11 +406,9 @@ impl AppHandler {
                 let mut server = Box::new(Server::new(name.clone(), version.clone(), conn_timeout));
 
                 let result = match server_params.method {
-                    ConnectionMethod::Direct => server.start(
-                        server_params.is_ipv6,
-                        ctx.config.port,
-                        server_params.use_upnp,
-                    ),
+                    ConnectionMethod::Direct => {
+                        server.start(ctx.config.port, server_params.use_upnp)
+                    }
                     ConnectionMethod::CloudServer => {
                         server.start_with_hole_punching(server_params.is_ipv6)
                     }
@@ -385,6 +417,9 @@ impl AppHandler {
 
                 match result {
                     Ok(_) => {
+                        if let ConnectionMethod::Direct = server_params.method {
+                            Self::advertise_on_lan(state, ctx, &server);
+                        }
                         // Assign server as transfer client
                         ctx.network.transfer_client = Some(server);
                         info!("[NETWORK] Server started.");
@@ -414,6 +449,75 @@ impl AppHandler {
         ctx.config.write();
     }
 
+    // Probing takes a few seconds, the result is picked up in poll
+    fn start_nat_detection(state: &mut AppState) {
+        if state.nat_type.is_some() || state.nat_detection.is_some() {
+            return;
+        }
+
+        let (tx, rx) = unbounded();
+        state.nat_detection = Some(rx);
+
+        thread::spawn(move || {
+            tx.send(detect_nat_type(false).map_err(|e| e.to_string()))
+                .ok();
+        });
+    }
+
+    fn handle_nat_detection(state: &mut AppState) {
+        let result = match state.nat_detection.as_ref().map(|rx| rx.try_recv()) {
+            Some(Ok(result)) => result,
+            _ => return,
+        };
+
+        state.nat_detection = None;
+
+        match result {
+            Ok(nat_type) => {
+                let recommended = ConnectionMethod::recommended_for(nat_type);
+                info!(
+                    "[NETWORK] NAT type is {}, {:?} is recommended for hosting.",
+                    nat_type, recommended
+                );
+
+                state.app_interface.nat_detected(nat_type, &recommended);
+                state.nat_type = Some(nat_type);
+            }
+            Err(e) => info!("[NETWORK] Could not detect NAT type: {}", e),
+        }
+    }
+
+    fn advertise_on_lan(state: &AppState, ctx: &AppContext<'_>, server: &Server) {
+        let aircraft = Path::new(&state.definitions_to_load)
+            .file_stem()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        if let Err(e) = server.advertise_on_lan(ctx.config.port, aircraft) {
+            error!("[NETWORK] Could not advertise on LAN! Reason: {}", e);
+        }
+    }
+
+    fn handle_lan_discovery(state: &mut AppState) {
+        let Some(browser) = state.lan_browser.as_mut() else {
+            return;
+        };
+
+        if browser.poll() {
+            state.app_interface.lan_sessions(&browser.get_sessions());
+        }
+    }
+
+    fn warn_for_nat(state: &AppState, method: &ConnectionMethod, is_host: bool) {
+        let Some(nat_type) = state.nat_type else {
+            return;
+        };
+
+        if let Some(warning) = method.get_nat_warning(nat_type, is_host) {
+            info!("[NETWORK] {} NAT: {}", nat_type, warning);
+        }
+    }
+
     fn handle_auto_start(state: &mut AppState, ctx: &mut AppContext<'_>) {
         if !ctx.cli.start_server() || ctx.program_state.auto_start_pending {
             return;
@@ -3,6 +3,7 @@ use std::time::{Duration, Instant};
 
 use crate::app::App;
 use crate::definitions::{Definitions, SyncPermission};
+use crate::emulator::EmulatorValue;
 use simconnect::SimConnector;
 use yourcontrols_net::TransferClient;
 
@@ -11,7 +12,7 @@ const EMULATOR_TICK: Duration = Duration::from_millis(200);
 pub struct EmulatorRuntimeState {
     pub enabled: bool,
     pub tracked: HashSet<String>,
-    pub last_sent: HashMap<String, f64>,
+    pub last_sent: HashMap<String, EmulatorValue>,
     pub last_tick: Instant,
 }
 
@@ -103,7 +104,7 @@ impl EmulatorController {
         state: &EmulatorRuntimeState,
         ctx: &mut EmulatorSetContext<'_>,
         var_id: &str,
-        value: f64,
+        value: EmulatorValue,
     ) {
         if !state.enabled {
             return;
@@ -156,12 +157,14 @@ impl EmulatorController {
 
         for var_id in state.tracked.iter() {
             if let Some(info) = definitions.get_emulator_var_value(var_id) {
-                if let Some(value) = info.value {
-                    let should_send = state
-                        .last_sent
-                        .get(var_id)
-                        .map(|last| (last - value).abs() > f64::EPSILON)
-                        .unwrap_or(true);
+                if let Some(value) = info.value.clone() {
+                    let should_send = match (state.last_sent.get(var_id), &value) {
+                        (Some(EmulatorValue::Number(last)), EmulatorValue::Number(value)) => {
+                            (last - value).abs() > f64::EPSILON
+                        }
+                        (Some(last), value) => last != value,
+                        (None, _) => true,
+                    };
 
                     if should_send {
                         if let Ok(payload) = serde_json::to_string(&info) {
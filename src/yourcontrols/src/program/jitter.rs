@@ -0,0 +1,191 @@
+use std::{collections::VecDeque, time::Instant};
+
+// Bounds on how long updates are held back before being applied, in seconds
+const MIN_PLAYOUT_DELAY: f64 = 0.01;
+const MAX_PLAYOUT_DELAY: f64 = 0.25;
+// Playout delay covers this many times the measured jitter
+const JITTER_MULTIPLIER: f64 = 3.0;
+// Smoothing for the jitter estimate, same as RFC 3550
+const JITTER_GAIN: f64 = 1.0 / 16.0;
+// Lets the transit baseline creep up if the route to the peer gets slower
+const BASELINE_GAIN: f64 = 0.002;
+// Anything past this is stale by the time it would be played, oldest are dropped first
+const MAX_DEPTH: usize = 64;
+
+struct BufferedUpdate<T> {
+    time: f64,
+    item: T,
+}
+
+// Holds unreliable updates from one peer and releases them in sender time order, delayed just enough to absorb network jitter
+pub struct JitterBuffer<T> {
+    epoch: Instant,
+    // Sorted by sender time
+    queue: VecDeque<BufferedUpdate<T>>,
+    // Lowest seen difference between local arrival and sender time, the sender clock offset plus the fastest transit
+    baseline: Option<f64>,
+    last_transit: Option<f64>,
+    jitter: f64,
+    // Sender time of the last released update, anything older arriving afterwards is stale
+    last_played: Option<f64>,
+}
+
+impl<T> JitterBuffer<T> {
+    pub fn new() -> Self {
+        Self {
+            epoch: Instant::now(),
+            queue: VecDeque::new(),
+            baseline: None,
+            last_transit: None,
+            jitter: 0.0,
+            last_played: None,
+        }
+    }
+
+    fn now(&self) -> f64 {
+        self.epoch.elapsed().as_secs_f64()
+    }
+
+    pub fn push(&mut self, time: f64, item: T) {
+        let now = self.now();
+        self.push_at(now, time, item);
+    }
+
+    pub fn pop_ready(&mut self) -> Option<(f64, T)> {
+        let now = self.now();
+        self.pop_ready_at(now)
+    }
+
+    fn push_at(&mut self, now: f64, time: f64, item: T) {
+        let transit = now - time;
+
+        if let Some(last_transit) = self.last_transit {
+            self.jitter += ((transit - last_transit).abs() - self.jitter) * JITTER_GAIN;
+        }
+        self.last_transit = Some(transit);
+
+        self.baseline = Some(match self.baseline {
+            Some(baseline) if transit > baseline => baseline + (transit - baseline) * BASELINE_GAIN,
+            _ => transit,
+        });
+
+        if self.last_played.map_or(false, |played| time <= played) {
+            return;
+        }
+
+        let index = self
+            .queue
+            .iter()
+            .rposition(|buffered| buffered.time <= time)
+            .map_or(0, |index| index + 1);
+        self.queue.insert(index, BufferedUpdate { time, item });
+
+        if self.queue.len() > MAX_DEPTH {
+            self.queue.pop_front();
+        }
+    }
+
+    // An update from the same sender was applied right away, so anything older must not overwrite it
+    pub fn skip_to(&mut self, time: f64) {
+        self.queue.retain(|buffered| buffered.time > time);
+
+        if self.last_played.map_or(true, |played| played < time) {
+            self.last_played = Some(time);
+        }
+    }
+
+    fn pop_ready_at(&mut self, now: f64) -> Option<(f64, T)> {
+        let playout_at = self.queue.front()?.time + self.baseline? + self.playout_delay();
+
+        if playout_at > now {
+            return None;
+        }
+
+        let buffered = self.queue.pop_front()?;
+        self.last_played = Some(buffered.time);
+
+        Some((buffered.time, buffered.item))
+    }
+
+    // Seconds an update is held back after the fastest possible arrival
+    pub fn playout_delay(&self) -> f64 {
+        (self.jitter * JITTER_MULTIPLIER).clamp(MIN_PLAYOUT_DELAY, MAX_PLAYOUT_DELAY)
+    }
+
+    pub fn depth(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reorders_by_sender_time() {
+        let mut buffer = JitterBuffer::new();
+
+        buffer.push_at(100.0, 0.0, "a");
+        buffer.push_at(100.06, 0.05, "c");
+        buffer.push_at(100.07, 0.03, "b");
+
+        // Nothing is released before the playout delay
+        assert!(buffer.pop_ready_at(100.0).is_none());
+        assert_eq!(buffer.depth(), 3);
+
+        assert_eq!(buffer.pop_ready_at(101.0), Some((0.0, "a")));
+        assert_eq!(buffer.pop_ready_at(101.0), Some((0.03, "b")));
+        assert_eq!(buffer.pop_ready_at(101.0), Some((0.05, "c")));
+        assert!(buffer.pop_ready_at(101.0).is_none());
+    }
+
+    #[test]
+    fn test_drops_stale_updates() {
+        let mut buffer = JitterBuffer::new();
+
+        buffer.push_at(100.0, 0.0, "a");
+        buffer.push_at(100.05, 0.05, "b");
+        assert_eq!(buffer.pop_ready_at(101.0), Some((0.0, "a")));
+        assert_eq!(buffer.pop_ready_at(101.0), Some((0.05, "b")));
+
+        // Arrived after a newer update was already applied
+        buffer.push_at(101.0, 0.03, "late");
+        assert_eq!(buffer.depth(), 0);
+        assert!(buffer.pop_ready_at(102.0).is_none());
+    }
+
+    #[test]
+    fn test_drops_updates_older_than_reliable() {
+        let mut buffer = JitterBuffer::new();
+
+        buffer.push_at(100.0, 0.0, "a");
+        buffer.push_at(100.1, 0.1, "c");
+
+        // A reliable update sent between the two got applied
+        buffer.skip_to(0.05);
+        assert_eq!(buffer.depth(), 1);
+
+        // Older samples arriving later are dropped too
+        buffer.push_at(100.2, 0.03, "b");
+        assert_eq!(buffer.depth(), 1);
+
+        assert_eq!(buffer.pop_ready_at(101.0), Some((0.1, "c")));
+        assert!(buffer.pop_ready_at(101.0).is_none());
+    }
+
+    #[test]
+    fn test_delay_adapts_to_jitter() {
+        let mut steady = JitterBuffer::new();
+        let mut jittery = JitterBuffer::new();
+
+        for i in 0..50 {
+            let time = i as f64 * 0.05;
+            steady.push_at(100.0 + time, time, ());
+            jittery.push_at(100.0 + time + if i % 2 == 0 { 0.0 } else { 0.04 }, time, ());
+        }
+
+        assert_eq!(steady.playout_delay(), MIN_PLAYOUT_DELAY);
+        assert!(jittery.playout_delay() > 0.05);
+        assert!(jittery.playout_delay() <= MAX_PLAYOUT_DELAY);
+    }
+}
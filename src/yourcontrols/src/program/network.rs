@@ -1,28 +1,47 @@
+use std::collections::HashMap;
+use std::fs;
 use std::net::IpAddr;
 use std::time::Instant;
 
-use log::{error, info};
-use yourcontrols_net::{Client, Event, Payloads, ReceiveMessage, TransferClient};
+use log::{error, info, warn};
+use yourcontrols_net::{
+    hash_definition, split_join_code, ChunkedDefinition, Client, Event, Payloads, ReceiveMessage,
+    TransferClient,
+};
+use yourcontrols_types::AllNeedSync;
 
 use crate::app::App;
 use crate::app::ConnectionMethod;
 use crate::cli::CliWrapper;
 use crate::clientmanager::ClientManager;
 use crate::definitions::SyncPermission;
-use crate::simconfig::Config;
+use crate::paths::DefinitionPathResolver;
+use crate::simconfig::{Config, PausePolicy, WeatherSyncMode};
+use crate::sync::pause::PauseState;
 use crate::update::Updater;
 use crate::util::get_hostname_ip;
 
 use super::emulator_runtime::EmulatorController;
 use super::emulator_runtime::EmulatorRuntimeState;
+use super::jitter::JitterBuffer;
 use super::simconnect::{SimAction, SimState};
 use super::sync::SyncState;
 
 pub struct NetworkState {
     pub(crate) clients: ClientManager,
     pub(crate) transfer_client: Option<Box<dyn TransferClient>>,
+    // Connecting to the hosted server the session moved to, takes over from transfer_client once it's in
+    pub(crate) pending_client: Option<Box<dyn TransferClient>>,
     pub(crate) observing: bool,
     pub(crate) should_set_none_client: bool,
+    // Unreliable updates waiting to be applied, per sender
+    pub(crate) playout: HashMap<String, JitterBuffer<AllNeedSync>>,
+    // Host: what joiners ask chunks of. Joiner: what's arriving from the host
+    pub(crate) definition_upload: Option<ChunkedDefinition>,
+    pub(crate) definition_download: Option<ChunkedDefinition>,
+    pub(crate) loaded_definition_hash: Option<String>,
+    // The host the definitions came from left, whoever took over may fly something else
+    pub(crate) host_left: bool,
 }
 
 impl NetworkState {
@@ -30,14 +49,32 @@ impl NetworkState {
         Self {
             clients: ClientManager::new(),
             transfer_client: None,
+            pending_client: None,
             observing: false,
             should_set_none_client: false,
+            playout: HashMap::new(),
+            definition_upload: None,
+            definition_download: None,
+            loaded_definition_hash: None,
+            host_left: false,
         }
     }
 
     pub fn has_client(&self) -> bool {
         self.transfer_client.is_some()
     }
+
+    pub fn get_buffer_depth(&self) -> usize {
+        self.playout.values().map(|buffer| buffer.depth()).sum()
+    }
+
+    // Longest delay any sender's updates are held back for, in milliseconds
+    pub fn get_playout_delay(&self) -> f64 {
+        self.playout
+            .values()
+            .map(|buffer| buffer.playout_delay() * 1000.0)
+            .fold(0.0, f64::max)
+    }
 }
 
 pub struct NetworkContext<'a> {
@@ -62,6 +99,10 @@ impl NetworkController {
             NetworkHandler::handle_message(&mut client, message, state, ctx);
         }
 
+        NetworkHandler::poll_pending_client(&mut client, state, ctx);
+        NetworkHandler::play_buffered_updates(&mut client, state, ctx);
+        NetworkHandler::request_definition_chunks(&mut client, state);
+
         state.transfer_client = Some(client);
     }
 
@@ -77,8 +118,17 @@ impl NetworkController {
             return;
         }
 
+        if let Some(mut pending_client) = state.pending_client.take() {
+            pending_client.close();
+        }
+
         state.transfer_client = None;
         state.should_set_none_client = false;
+        state.playout.clear();
+        state.definition_upload = None;
+        state.definition_download = None;
+        state.loaded_definition_hash = None;
+        state.host_left = false;
         sync.ready_to_process_data = false;
         sync.connection_time = None;
         sim.conn.close();
@@ -88,7 +138,7 @@ impl NetworkController {
     pub fn start_client(
         timeout: u64,
         username: String,
-        session_id: Option<String>,
+        join_code: Option<String>,
         version: String,
         isipv6: bool,
         ip: Option<IpAddr>,
@@ -98,6 +148,9 @@ impl NetworkController {
     ) -> Result<Client, String> {
         let mut client = Client::new(username, version, timeout);
 
+        let join_code = join_code.unwrap_or_default();
+        let (session_id, secret) = split_join_code(&join_code);
+
         let client_result = match method {
             ConnectionMethod::Direct => {
                 // Get either hostname ip or defined ip
@@ -110,10 +163,16 @@ impl NetworkController {
                     None => ip.unwrap(),
                 };
                 // A port must've been passed with direct connect
-                client.start(actual_ip, port.unwrap(), session_id)
+                // Servers joined directly have no session id, the hoster does
+                let session_id = if session_id.is_empty() {
+                    None
+                } else {
+                    Some(session_id.to_string())
+                };
+                client.start(actual_ip, port.unwrap(), session_id, secret.to_string())
             }
             ConnectionMethod::CloudServer => {
-                client.start_with_hole_punch(session_id.unwrap(), isipv6)
+                client.start_with_hole_punch(session_id.to_string(), secret.to_string(), isipv6)
             }
             ConnectionMethod::Relay => panic!("Never should be reached!"),
         };
@@ -161,7 +220,15 @@ impl NetworkHandler {
             | Payloads::InvalidName
             | Payloads::RequestHosting { .. }
             | Payloads::InitHandshake { .. }
-            | Payloads::Heartbeat => {}
+            | Payloads::KeyExchange { .. }
+            | Payloads::Encrypted { .. }
+            | Payloads::ResumeToken { .. }
+            | Payloads::Resume { .. }
+            | Payloads::Resumed
+            | Payloads::NatProbe { .. }
+            | Payloads::NatProbeResponse { .. }
+            | Payloads::RequestHosterFallback { .. }
+            | Payloads::Heartbeat { .. } => {}
             // Used
             Payloads::Update {
                 data,
@@ -181,27 +248,34 @@ impl NetworkHandler {
                     );
                 }
 
-                if !state.clients.is_observer(&from) && ctx.sync.ready_to_process_data {
-                    match ctx.sim.definitions.on_receive_data(
-                        &ctx.sim.conn,
-                        data,
-                        time,
-                        &SyncPermission {
-                            is_server: state.clients.client_is_server(&from),
-                            is_master: state.clients.client_has_control(&from),
-                            is_init: true,
-                        },
-                    ) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            client.stop(e.to_string());
-                        }
+                if is_unreliable {
+                    // Smoothed out and applied in order once the playout delay passes
+                    if ctx.sync.ready_to_process_data {
+                        state
+                            .playout
+                            .entry(from)
+                            .or_insert_with(JitterBuffer::new)
+                            .push(time, data);
                     }
+                } else {
+                    // Buffered updates are from before the jump
+                    if data.snap {
+                        state.playout.remove(&from);
+                    } else {
+                        state
+                            .playout
+                            .entry(from.clone())
+                            .or_insert_with(JitterBuffer::new)
+                            .skip_to(time);
+                    }
+
+                    Self::apply_update(client, &from, data, time, state, ctx);
                 }
             }
             Payloads::TransferControl { from, to } => {
                 // Someone is transferring controls to us
                 ctx.sim.definitions.reset_sync();
+                state.playout.clear();
                 if to == client.get_server_name() {
                     info!("[CONTROL] Taking control from {}", from);
                     ctx.sim.take_control();
@@ -232,16 +306,9 @@ impl NetworkHandler {
                 // This should be before the if statement as server_started counts the number of clients connected
                 state.clients.add_client(name.clone());
 
-                if client.is_host() {
-                    client.send_definitions(
-                        ctx.sim.definitions.get_buffer_bytes().into_boxed_slice(),
-                        name.clone(),
-                    );
-
-                    if ctx.config.instructor_mode {
-                        is_observer = true;
-                        client.set_observer(name.clone(), true);
-                    }
+                if client.is_host() && ctx.config.instructor_mode {
+                    is_observer = true;
+                    client.set_observer(name.clone(), true);
                 }
 
                 ctx.app.new_connection(&name);
@@ -262,12 +329,22 @@ impl NetworkHandler {
                 // Request time update to sync
                 if client.is_host() {
                     ctx.sim.definitions.request_time();
+
+                    let pause = ctx.sim.definitions.get_pause_state();
+                    client.set_pause(
+                        client.get_server_name().to_string(),
+                        pause.paused,
+                        pause.sim_rate,
+                        None,
+                    );
                 }
             }
             Payloads::PlayerLeft { name } => {
                 info!("[NETWORK] {} lost connection.", name);
 
+                state.host_left |= state.clients.client_is_server(&name);
                 state.clients.remove_client(&name);
+                state.playout.remove(&name);
                 // User may have been in control
                 if state.clients.client_has_control(&name) {
                     state.clients.set_no_control();
@@ -294,6 +371,7 @@ impl NetworkHandler {
 
                     if !state.observing {
                         ctx.sim.definitions.reset_sync();
+                        state.playout.clear();
                     }
                 } else {
                     info!("[CONTROL] {} is observing? {}", to, is_observer);
@@ -304,55 +382,37 @@ impl NetworkHandler {
             Payloads::SetHost => {
                 ctx.app.set_host();
                 // Host was set which means successfully established connection to hoster, need to send definitions
-                client.send_definitions(
-                    ctx.sim.definitions.get_buffer_bytes().into_boxed_slice(),
-                    client.get_server_name().to_string(),
-                );
+                let target = client.get_server_name().to_string();
+                Self::share_definitions(client, target, state, ctx);
             }
             Payloads::ConnectionDenied { reason } => {
                 client.stop(format!("Connection denied: {}", reason));
             }
             Payloads::AircraftDefinition { bytes } => {
-                match ctx.sim.definitions.load_config_from_bytes(bytes) {
-                    Ok(_) => {
-                        info!("[DEFINITIONS] Loaded and mapped {} aircraft vars, {} local vars, and {} events from the server.",
-                        ctx.sim.definitions.get_number_avars(), ctx.sim.definitions.get_number_lvars(), ctx.sim.definitions.get_number_events());
-
-                        let skip_sim_connect = ctx.cli.skip_sim_connect();
-                        let def_connect_result = ctx
-                            .sim
-                            .definitions
-                            .on_connected(&ctx.sim.conn, skip_sim_connect);
-                        if let Err(()) = def_connect_result {
-                            client.stop(
-                                "Error starting WS server. Do you have another YourControls open?"
-                                    .to_string(),
-                            )
-                        }
-                        // Freeze aircraft
-                        ctx.sim.lose_control();
-                    }
-                    Err(e) => {
-                        error!(
-                            "[DEFINITIONS] Could not load server sent configuration file: {}",
-                            e
-                        );
+                Self::load_definitions(client, bytes, ctx);
+            }
+            Payloads::DefinitionManifest {
+                hash,
+                size,
+                chunk_hashes,
+            } => {
+                Self::on_definition_manifest(client, hash, size, chunk_hashes, state, ctx);
+            }
+            Payloads::RequestDefinitionChunks { from, hash, chunks } => {
+                if let Some(definition) = state.definition_upload.as_ref() {
+                    if client.is_host() && definition.get_hash() == hash {
+                        client.send_definition_chunks(definition, &chunks, from);
                     }
                 }
-
-                EmulatorController::send_vars_if_enabled(
-                    ctx.emulator,
-                    &ctx.sim.definitions,
-                    ctx.app,
-                );
-                // Start the connection timer to wait to send the ready payload
-                ctx.sync.connection_time = Some(Instant::now());
+            }
+            Payloads::DefinitionChunk { hash, index, bytes } => {
+                Self::on_definition_chunk(client, hash, index, bytes, state, ctx);
             }
             Payloads::AttemptHosterConnection { peer } => {
                 match NetworkController::start_client(
                     ctx.config.conn_timeout,
                     client.get_server_name().to_string(),
-                    client.get_session_id(),
+                    client.get_join_code(),
                     ctx.updater.get_version().to_string(),
                     false,
                     Some(peer.ip()),
@@ -362,7 +422,12 @@ impl NetworkHandler {
                 ) {
                     Ok(new_client) => {
                         info!("[NETWORK] New client started to connect to hosted server.");
-                        *client = Box::new(new_client);
+                        // Hosts get here too when their session moves to the hoster
+                        if let Some(mut pending_client) =
+                            state.pending_client.replace(Box::new(new_client))
+                        {
+                            pending_client.close();
+                        }
                     }
                     Err(e) => {
                         ctx.app.client_fail(e.to_string().as_str());
@@ -370,6 +435,88 @@ impl NetworkHandler {
                     }
                 };
             }
+            Payloads::RequestPause {
+                from,
+                paused,
+                sim_rate,
+            } => {
+                // The host decides for everyone
+                if !client.is_host() {
+                    return;
+                }
+
+                let state = match PauseState::new(paused, sim_rate) {
+                    Some(state) => state,
+                    None => {
+                        warn!("[PAUSE] Ignoring invalid sim rate from {}", from);
+                        return;
+                    }
+                };
+
+                if ctx.config.pause_policy == PausePolicy::Anyone {
+                    info!(
+                        "[PAUSE] {} set paused: {}, sim rate: {}",
+                        from, state.paused, state.sim_rate
+                    );
+                    ctx.sim.definitions.set_pause_state(&ctx.sim.conn, state);
+                    client.set_pause(from, state.paused, state.sim_rate, None);
+                } else {
+                    // Put them back in line with us
+                    info!("[PAUSE] Ignoring pause change from {}", from);
+                    let pause = ctx.sim.definitions.get_pause_state();
+                    client.set_pause(
+                        client.get_server_name().to_string(),
+                        pause.paused,
+                        pause.sim_rate,
+                        Some(from),
+                    );
+                }
+            }
+            Payloads::SetPause {
+                from,
+                paused,
+                sim_rate,
+            } => {
+                if client.is_host() || !ctx.sync.ready_to_process_data {
+                    return;
+                }
+
+                let state = match PauseState::new(paused, sim_rate) {
+                    Some(state) => state,
+                    None => {
+                        warn!("[PAUSE] Ignoring invalid sim rate from {}", from);
+                        return;
+                    }
+                };
+
+                info!(
+                    "[PAUSE] {} set paused: {}, sim rate: {}",
+                    from, state.paused, state.sim_rate
+                );
+                ctx.sim.definitions.set_pause_state(&ctx.sim.conn, state);
+            }
+            Payloads::SetWeather { from, weather } => {
+                if client.is_host() || !ctx.sync.ready_to_process_data {
+                    return;
+                }
+
+                let apply = match ctx.config.weather_sync {
+                    WeatherSyncMode::Off => return,
+                    WeatherSyncMode::Warn => false,
+                    WeatherSyncMode::Apply => true,
+                };
+
+                let warnings = ctx
+                    .sim
+                    .definitions
+                    .sync_weather(&ctx.sim.conn, &weather, apply);
+
+                if !warnings.is_empty() {
+                    info!("[WEATHER] Differs from {}: {}", from, warnings.join(", "));
+                }
+
+                ctx.app.weather_warning(&warnings);
+            }
             Payloads::SetSelfObserver { name } => {
                 if client.is_host() {
                     state.clients.set_observer(&name, true);
@@ -380,6 +527,252 @@ impl NetworkHandler {
         }
     }
 
+    fn apply_update(
+        client: &mut Box<dyn TransferClient>,
+        from: &str,
+        data: AllNeedSync,
+        time: f64,
+        state: &NetworkState,
+        ctx: &mut NetworkContext<'_>,
+    ) {
+        if state.clients.is_observer(from) || !ctx.sync.ready_to_process_data {
+            return;
+        }
+
+        let permission = SyncPermission {
+            is_server: state.clients.client_is_server(from),
+            is_master: state.clients.client_has_control(from),
+            is_init: true,
+        };
+
+        if let Err(e) = ctx
+            .sim
+            .definitions
+            .on_receive_data(&ctx.sim.conn, data, time, &permission)
+        {
+            client.stop(e.to_string());
+        }
+    }
+
+    fn load_definitions(
+        client: &mut Box<dyn TransferClient>,
+        bytes: Box<[u8]>,
+        ctx: &mut NetworkContext<'_>,
+    ) {
+        match ctx.sim.definitions.load_config_from_bytes(bytes) {
+            Ok(_) => {
+                info!("[DEFINITIONS] Loaded and mapped {} aircraft vars, {} local vars, and {} events from the server.",
+                ctx.sim.definitions.get_number_avars(), ctx.sim.definitions.get_number_lvars(), ctx.sim.definitions.get_number_events());
+
+                let skip_sim_connect = ctx.cli.skip_sim_connect();
+                let def_connect_result = ctx
+                    .sim
+                    .definitions
+                    .on_connected(&ctx.sim.conn, skip_sim_connect);
+                if let Err(()) = def_connect_result {
+                    client.stop(
+                        "Error starting WS server. Do you have another YourControls open?"
+                            .to_string(),
+                    )
+                }
+                // Freeze aircraft
+                ctx.sim.lose_control();
+            }
+            Err(e) => {
+                error!(
+                    "[DEFINITIONS] Could not load server sent configuration file: {}",
+                    e
+                );
+            }
+        }
+
+        EmulatorController::send_vars_if_enabled(ctx.emulator, &ctx.sim.definitions, ctx.app);
+        // Start the connection timer to wait to send the ready payload
+        ctx.sync.connection_time = Some(Instant::now());
+    }
+
+    fn share_definitions(
+        client: &mut Box<dyn TransferClient>,
+        target: String,
+        state: &mut NetworkState,
+        ctx: &mut NetworkContext<'_>,
+    ) {
+        let definition = ChunkedDefinition::from_bytes(&ctx.sim.definitions.get_buffer_bytes());
+        client.send_definition_manifest(&definition, target);
+        state.definition_upload = Some(definition);
+    }
+
+    // A copy from an earlier session, as long as it's still what the hash says
+    fn get_cached_definitions(hash: &str) -> Option<Vec<u8>> {
+        let bytes = fs::read(DefinitionPathResolver::get_cached_definition(hash)).ok()?;
+
+        if hash_definition(&bytes) == hash {
+            Some(bytes)
+        } else {
+            None
+        }
+    }
+
+    fn cache_definitions(hash: &str, bytes: &[u8]) {
+        let path = DefinitionPathResolver::get_cached_definition(hash);
+
+        let result = match path.parent() {
+            Some(dir) => fs::create_dir_all(dir).and_then(|_| fs::write(&path, bytes)),
+            None => fs::write(&path, bytes),
+        };
+
+        if let Err(e) = result {
+            error!("[DEFINITIONS] Could not cache definitions: {}", e);
+        }
+    }
+
+    fn on_definition_manifest(
+        client: &mut Box<dyn TransferClient>,
+        hash: String,
+        size: u32,
+        chunk_hashes: Vec<String>,
+        state: &mut NetworkState,
+        ctx: &mut NetworkContext<'_>,
+    ) {
+        let is_downloading = state
+            .definition_download
+            .as_ref()
+            .is_some_and(|definition| definition.get_hash() == hash);
+        // Loaded ones stay until the host that sent them is gone
+        let is_loaded = state.loaded_definition_hash.is_some()
+            && (!state.host_left || state.loaded_definition_hash.as_deref() == Some(hash.as_str()));
+
+        if client.is_host() || is_downloading || is_loaded {
+            return;
+        }
+
+        if let Some(bytes) = Self::get_cached_definitions(&hash) {
+            info!("[DEFINITIONS] Using cached copy of the server's definitions.");
+
+            state.definition_download = None;
+            state.loaded_definition_hash = Some(hash);
+            state.host_left = false;
+            Self::load_definitions(client, bytes.into_boxed_slice(), ctx);
+            return;
+        }
+
+        match ChunkedDefinition::from_manifest(hash, size, chunk_hashes) {
+            Ok(definition) => {
+                info!(
+                    "[DEFINITIONS] Receiving {} bytes of definitions in {} chunks.",
+                    size,
+                    definition.get_chunk_count()
+                );
+                ctx.app.definition_progress(0, definition.get_chunk_count());
+                state.definition_download = Some(definition);
+            }
+            Err(e) => error!("[DEFINITIONS] {}", e),
+        }
+    }
+
+    fn on_definition_chunk(
+        client: &mut Box<dyn TransferClient>,
+        hash: String,
+        index: u32,
+        bytes: Box<[u8]>,
+        state: &mut NetworkState,
+        ctx: &mut NetworkContext<'_>,
+    ) {
+        let Some(definition) = state.definition_download.as_mut() else {
+            return;
+        };
+
+        if definition.get_hash() != hash || !definition.add_chunk(index, bytes, Instant::now()) {
+            return;
+        }
+
+        ctx.app.definition_progress(
+            definition.get_received_count(),
+            definition.get_chunk_count(),
+        );
+
+        if !definition.is_complete() {
+            return;
+        }
+
+        let Some(definition) = state.definition_download.take() else {
+            return;
+        };
+
+        match definition.into_bytes() {
+            Ok(bytes) => {
+                Self::cache_definitions(&hash, &bytes);
+                state.loaded_definition_hash = Some(hash);
+                state.host_left = false;
+                Self::load_definitions(client, bytes.into_boxed_slice(), ctx);
+            }
+            // Every chunk matched the manifest, so the host sent a manifest that doesn't add up
+            Err(e) => error!("[DEFINITIONS] {}", e),
+        }
+    }
+
+    // Asks the host for the next chunks, and again for any that went missing
+    fn request_definition_chunks(client: &mut Box<dyn TransferClient>, state: &mut NetworkState) {
+        let Some(definition) = state.definition_download.as_mut() else {
+            return;
+        };
+
+        if let Some(chunks) = definition.next_request(Instant::now()) {
+            client.request_definition_chunks(definition.get_hash().to_string(), chunks);
+        }
+    }
+
+    // The current client carries on until the new one is connected, then steps aside without a word
+    fn poll_pending_client(
+        client: &mut Box<dyn TransferClient>,
+        state: &mut NetworkState,
+        ctx: &mut NetworkContext<'_>,
+    ) {
+        let Some(mut pending_client) = state.pending_client.take() else {
+            return;
+        };
+
+        while let Ok(message) = pending_client.get_next_message() {
+            let takes_over = matches!(
+                message,
+                ReceiveMessage::Event(Event::ConnectionEstablished | Event::ConnectionLost(_))
+            );
+
+            Self::handle_message(&mut pending_client, message, state, ctx);
+
+            if takes_over {
+                client.close();
+                *client = pending_client;
+                return;
+            }
+        }
+
+        // Sent somewhere else again while connecting
+        if state.pending_client.is_some() {
+            pending_client.close();
+        } else {
+            state.pending_client = Some(pending_client);
+        }
+    }
+
+    fn play_buffered_updates(
+        client: &mut Box<dyn TransferClient>,
+        state: &mut NetworkState,
+        ctx: &mut NetworkContext<'_>,
+    ) {
+        let mut ready = Vec::new();
+
+        for (from, buffer) in state.playout.iter_mut() {
+            while let Some((time, data)) = buffer.pop_ready() {
+                ready.push((from.clone(), time, data));
+            }
+        }
+
+        for (from, time, data) in ready {
+            Self::apply_update(client, &from, data, time, state, ctx);
+        }
+    }
+
     fn handle_event(
         client: &mut Box<dyn TransferClient>,
         event: Event,
@@ -391,8 +784,8 @@ impl NetworkHandler {
                 if client.is_host() {
                     // Display server started message
                     ctx.app.server_started();
-                    if let Some(session_code) = client.get_session_id().as_deref() {
-                        ctx.app.set_session_code(session_code);
+                    if let Some(join_code) = client.get_join_code().as_deref() {
+                        ctx.app.set_session_code(join_code);
                     }
                     // Unfreeze aircraft
                     ctx.sim.take_control();
@@ -407,6 +800,11 @@ impl NetworkHandler {
             }
             Event::ConnectionLost(reason) => {
                 info!("[NETWORK] Server/Client stopped. Reason: {}", reason);
+                // The session is moving on, the new client tells whether it got there
+                if state.pending_client.is_some() {
+                    return;
+                }
+
                 // TAKE BACK CONTROL
                 ctx.sim.take_control();
 
@@ -416,6 +814,38 @@ impl NetworkHandler {
 
                 ctx.app.client_fail(&reason);
             }
+            Event::Reconnecting => {
+                info!("[NETWORK] Connection dropped, reconnecting...");
+                // Whatever was buffered is stale by the time we're back
+                state.playout.clear();
+                ctx.app.reconnecting();
+            }
+            Event::Resumed => {
+                info!("[NETWORK] Reconnected.");
+                ctx.app.resumed();
+                // Definitions are still loaded, only the values need to catch up
+                if ctx.sync.ready_to_process_data {
+                    ctx.sim.definitions.reset_sync();
+                    client.send_ready();
+                }
+            }
+            // Anything sealed for them before then never got opened
+            Event::KeyShared(name) => {
+                if client.is_host() {
+                    Self::share_definitions(client, name, state, ctx);
+                }
+            }
+            Event::PortForwarded(method, external_port) => {
+                info!(
+                    "[NETWORK] Port forwarded with {} on external port {}.",
+                    method, external_port
+                );
+                ctx.app.port_forwarded(&method.to_string(), external_port);
+            }
+            Event::PortForwardFailed(reason) => {
+                info!("[NETWORK] Port forwarding failed. Reason: {}", reason);
+                ctx.app.port_forward_failed(&reason);
+            }
             Event::UnablePunchthrough => ctx
                 .app
                 .client_fail("Could not connect to host! Please port forward or use Cloud Host."),
@@ -423,7 +853,11 @@ impl NetworkHandler {
                 .app
                 .server_fail("Could not connect to Cloud Server to fetch session ID."),
             Event::Metrics(metrics) => {
-                ctx.app.send_network(&metrics);
+                ctx.app.send_network(
+                    &metrics,
+                    state.get_buffer_depth(),
+                    state.get_playout_delay(),
+                );
             }
         }
     }
@@ -4,6 +4,8 @@ use log::info;
 use yourcontrols_net::TransferClient;
 
 use crate::definitions::{ProgramAction, SyncPermission};
+use crate::simconfig::{Config, WeatherSyncMode};
+use crate::sync::pause::PauseState;
 
 use super::network::NetworkState;
 use super::simconnect::SimState;
@@ -25,7 +27,12 @@ impl SyncState {
 pub struct SyncController;
 
 impl SyncController {
-    pub fn tick(state: &mut SyncState, network: &mut NetworkState, sim: &mut SimState) {
+    pub fn tick(
+        state: &mut SyncState,
+        network: &mut NetworkState,
+        sim: &mut SimState,
+        config: &Config,
+    ) {
         let Some(mut client) = network.transfer_client.take() else {
             return;
         };
@@ -34,6 +41,22 @@ impl SyncController {
             client.stop(e.to_string());
         }
 
+        // Pause or sim rate changed in our sim
+        if let Some(pause) = sim.definitions.get_next_pause_change() {
+            if state.ready_to_process_data {
+                SyncHandler::send_pause_change(pause, &mut client);
+            }
+        }
+
+        // Let everyone compare their weather with ours
+        if client.is_host() && config.weather_sync != WeatherSyncMode::Off {
+            if let Some(weather) = sim.definitions.get_next_weather_publish() {
+                if state.ready_to_process_data {
+                    client.set_weather(weather);
+                }
+            }
+        }
+
         // Handle specific program triggered actions
         if let Some(pending_action) = sim.definitions.get_next_pending_action() {
             SyncHandler::handle_program_action(pending_action, &mut client, network, sim);
@@ -102,6 +125,24 @@ impl SyncHandler {
         }
     }
 
+    fn send_pause_change(pause: PauseState, client: &mut Box<dyn TransferClient>) {
+        info!(
+            "[PAUSE] Paused: {}, sim rate: {}",
+            pause.paused, pause.sim_rate
+        );
+
+        if client.is_host() {
+            client.set_pause(
+                client.get_server_name().to_string(),
+                pause.paused,
+                pause.sim_rate,
+                None,
+            );
+        } else {
+            client.request_pause(pause.paused, pause.sim_rate);
+        }
+    }
+
     fn write_update_data(
         data: (
             Option<yourcontrols_types::AllNeedSync>,
@@ -10,6 +10,25 @@ pub enum ConfigLoadError {
     SerializeError(serde_json::Error),
 }
 
+// Who can pause or change sim rate for everyone
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum PausePolicy {
+    #[default]
+    HostOnly,
+    Anyone,
+}
+
+// What to do with the host's weather
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum WeatherSyncMode {
+    #[default]
+    Off,
+    // Show the differences to our weather
+    Warn,
+    // Also set the altimeter to the host's
+    Apply,
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct Config {
     pub conn_timeout: u64,
@@ -19,6 +38,10 @@ pub struct Config {
     pub ui_dark_theme: bool,
     pub streamer_mode: bool,
     pub instructor_mode: bool,
+    #[serde(default)]
+    pub pause_policy: PausePolicy,
+    #[serde(default)]
+    pub weather_sync: WeatherSyncMode,
 }
 
 impl Default for Config {
@@ -31,6 +54,8 @@ impl Default for Config {
             ui_dark_theme: true,
             streamer_mode: false,
             instructor_mode: false,
+            pause_policy: PausePolicy::HostOnly,
+            weather_sync: WeatherSyncMode::Off,
         }
     }
 }
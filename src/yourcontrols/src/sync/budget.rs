@@ -0,0 +1,126 @@
+use std::time::Instant;
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+// Rough size of a var on the wire besides its name, a msgpack value plus map overhead
+const VAR_OVERHEAD_BYTES: usize = 10;
+
+// What gets sent first when the budget runs low, highest first
+#[derive(Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    FlightControls,
+    Engines,
+    Systems,
+    Cosmetic,
+}
+
+impl Priority {
+    // Share of the budget this class may use up, the rest is held back for higher classes
+    fn allowance(&self) -> f64 {
+        match self {
+            Priority::FlightControls => 1.0,
+            Priority::Engines => 0.75,
+            Priority::Systems => 0.5,
+            Priority::Cosmetic => 0.25,
+        }
+    }
+}
+
+pub fn estimate_var_size(var_name: &str) -> usize {
+    var_name.len() + VAR_OVERHEAD_BYTES
+}
+
+// Token buckets limiting how many packets and bytes of var updates are sent each second
+pub struct SendBudget {
+    bytes_per_second: f64,
+    packets_per_second: f64,
+    bytes: f64,
+    packets: f64,
+    last_refill: Instant,
+}
+
+impl SendBudget {
+    pub fn new(bytes_per_second: u32, packets_per_second: u32) -> Self {
+        Self {
+            bytes_per_second: bytes_per_second as f64,
+            packets_per_second: packets_per_second as f64,
+            bytes: bytes_per_second as f64,
+            packets: packets_per_second as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    pub fn refill(&mut self) {
+        let elapsed = self.last_refill.elapsed().as_secs_f64();
+        self.last_refill = Instant::now();
+
+        self.bytes = (self.bytes + elapsed * self.bytes_per_second).min(self.bytes_per_second);
+        self.packets =
+            (self.packets + elapsed * self.packets_per_second).min(self.packets_per_second);
+    }
+
+    pub fn has_packet(&self) -> bool {
+        self.packets >= 1.0
+    }
+
+    pub fn spend_packet(&mut self) {
+        self.packets -= 1.0;
+    }
+
+    // Lower priorities can't dig into the part of the budget reserved for higher ones
+    pub fn try_spend_bytes(&mut self, bytes: usize, priority: Priority) -> bool {
+        let reserved = self.bytes_per_second * (1.0 - priority.allowance());
+        let bytes = bytes as f64;
+
+        if self.bytes - bytes < reserved {
+            return false;
+        }
+
+        self.bytes -= bytes;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_priorities_keep_headroom() {
+        let mut budget = SendBudget::new(100, 10);
+
+        // Cosmetic vars can only use a quarter of the budget
+        assert!(budget.try_spend_bytes(20, Priority::Cosmetic));
+        assert!(!budget.try_spend_bytes(10, Priority::Cosmetic));
+
+        // Systems can use up to half
+        assert!(budget.try_spend_bytes(30, Priority::Systems));
+        assert!(!budget.try_spend_bytes(1, Priority::Systems));
+
+        // Flight controls can use everything left
+        assert!(budget.try_spend_bytes(50, Priority::FlightControls));
+        assert!(!budget.try_spend_bytes(1, Priority::FlightControls));
+    }
+
+    #[test]
+    fn test_refills_over_time() {
+        let mut budget = SendBudget::new(1000, 100);
+
+        for _ in 0..100 {
+            budget.spend_packet();
+        }
+        assert!(budget.try_spend_bytes(1000, Priority::FlightControls));
+        assert!(!budget.has_packet());
+
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        budget.refill();
+
+        assert!(budget.has_packet());
+        assert!(budget.try_spend_bytes(40, Priority::FlightControls));
+        // Never refills past a second's worth
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        budget.refill();
+        assert!(!budget.try_spend_bytes(1001, Priority::FlightControls));
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::warn;
+use yourcontrols_types::VarStamp;
+
+// Lamport clock for shared vars, so that when two peers change the same var at once both settle on the last writer
+pub struct SharedVarClock {
+    clock: u64,
+    peer: u32,
+    // Stamp of the last applied change, local or remote, for each var
+    latest: HashMap<String, VarStamp>,
+    // Number of conflicts seen for each var, for finding chatty vars
+    conflicts: HashMap<String, u32>,
+}
+
+impl SharedVarClock {
+    pub fn new() -> Self {
+        Self {
+            clock: 0,
+            peer: generate_peer_id(),
+            latest: HashMap::new(),
+            conflicts: HashMap::new(),
+        }
+    }
+
+    pub fn stamp_local(&mut self, var_name: &str) -> VarStamp {
+        self.clock += 1;
+
+        let stamp = VarStamp {
+            clock: self.clock,
+            peer: self.peer,
+        };
+
+        self.latest.insert(var_name.to_string(), stamp);
+
+        stamp
+    }
+
+    // Returns whether a remote change is newer than what was last applied
+    pub fn receive(&mut self, var_name: &str, stamp: VarStamp) -> bool {
+        self.clock = self.clock.max(stamp.clock);
+
+        match self.latest.get(var_name) {
+            Some(latest) if *latest == stamp => false,
+            Some(latest) if *latest > stamp => {
+                let count = self.conflicts.entry(var_name.to_string()).or_insert(0);
+                *count += 1;
+
+                warn!(
+                    "[SYNC] Conflict on shared var {}, keeping {:?} over {:?} ({} conflicts so far)",
+                    var_name, latest, stamp, count
+                );
+
+                false
+            }
+            _ => {
+                self.latest.insert(var_name.to_string(), stamp);
+                true
+            }
+        }
+    }
+
+    // Stamp of the last change to a var if it was made locally
+    pub fn get_local_stamp(&self, var_name: &str) -> Option<VarStamp> {
+        self.latest
+            .get(var_name)
+            .filter(|stamp| stamp.peer == self.peer)
+            .copied()
+    }
+}
+
+fn generate_peer_id() -> u32 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.subsec_nanos())
+        .unwrap_or(0);
+
+    nanos ^ std::process::id().rotate_left(16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concurrent_changes_converge() {
+        let mut a = SharedVarClock::new();
+        let mut b = SharedVarClock::new();
+        b.peer = a.peer.wrapping_add(1);
+
+        // Both pilots flip the switch within the same packet interval
+        let stamp_a = a.stamp_local("L:SWITCH");
+        let stamp_b = b.stamp_local("L:SWITCH");
+
+        let a_accepts = a.receive("L:SWITCH", stamp_b);
+        let b_accepts = b.receive("L:SWITCH", stamp_a);
+
+        // Ties on the clock go to the higher peer, so only one side gives way
+        assert!(a_accepts);
+        assert!(!b_accepts);
+        assert_eq!(a.latest.get("L:SWITCH"), b.latest.get("L:SWITCH"));
+        // Only the winner resends its value
+        assert_eq!(a.get_local_stamp("L:SWITCH"), None);
+        assert_eq!(b.get_local_stamp("L:SWITCH"), Some(stamp_b));
+    }
+
+    #[test]
+    fn test_later_changes_win() {
+        let mut a = SharedVarClock::new();
+        let mut b = SharedVarClock::new();
+
+        let stamp = a.stamp_local("L:SWITCH");
+        assert!(b.receive("L:SWITCH", stamp));
+        // Duplicates are ignored
+        assert!(!b.receive("L:SWITCH", stamp));
+
+        // A change made after seeing the remote one is always newer
+        let reply = b.stamp_local("L:SWITCH");
+        assert!(reply > stamp);
+        assert!(a.receive("L:SWITCH", reply));
+        assert_eq!(a.get_local_stamp("L:SWITCH"), None);
+    }
+}
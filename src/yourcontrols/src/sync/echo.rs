@@ -0,0 +1,425 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use yourcontrols_types::VarReaderTypes;
+
+// How long to wait for the sim to read back a written value before giving up on it
+const EXPECTATION_TIMEOUT: Duration = Duration::from_secs(5);
+// Floats can come back slightly off after unit conversions in the sim
+const F64_TOLERANCE: f64 = 0.001;
+const F64_RELATIVE_TOLERANCE: f64 = 0.00001;
+// Knobs stepped further than this are only matched on their target
+const MAX_PATH_STEPS: f64 = 64.0;
+
+struct ExpectedReadback {
+    target: VarReaderTypes,
+    // Whole steps the sim reads back on the way to the target when stepping a knob or selector, in order
+    path: VecDeque<f64>,
+    // Last value read back while the gauge interpolates towards the target
+    interpolated: Option<f64>,
+    // Number of readbacks left to suppress, only events can expect more than one
+    count: u32,
+    written_at: Instant,
+}
+
+// Remembers what the sim should read back after applying a remote value, so only those readbacks are kept from being sent again
+pub struct EchoSuppressor {
+    expected: HashMap<String, ExpectedReadback>,
+    timeout: Duration,
+}
+
+impl EchoSuppressor {
+    pub fn new() -> Self {
+        Self::with_timeout(EXPECTATION_TIMEOUT)
+    }
+
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self {
+            expected: HashMap::new(),
+            timeout,
+        }
+    }
+
+    // A remote value got written to a var which currently has the value `from`
+    pub fn expect_value(
+        &mut self,
+        var_name: &str,
+        target: VarReaderTypes,
+        from: Option<&VarReaderTypes>,
+    ) {
+        self.expect_steps(var_name, target.clone(), get_readback_path(from, &target));
+    }
+
+    // A remote value got written through a mapping that knows the steps it takes on the way there
+    pub fn expect_steps(&mut self, var_name: &str, target: VarReaderTypes, path: VecDeque<f64>) {
+        self.expected.insert(
+            var_name.to_string(),
+            ExpectedReadback {
+                target,
+                path,
+                interpolated: None,
+                count: 1,
+                written_at: Instant::now(),
+            },
+        );
+    }
+
+    // A remote value is being interpolated to, so anything read back between `from` and the target is ours
+    pub fn expect_interpolation(
+        &mut self,
+        var_name: &str,
+        target: VarReaderTypes,
+        from: Option<&VarReaderTypes>,
+    ) {
+        let interpolated = match (from, &target) {
+            (Some(VarReaderTypes::String(_)), _) | (_, VarReaderTypes::String(_)) => None,
+            (from, _) => from.map(|from| from.get_as_f64()),
+        };
+
+        self.expected.insert(
+            var_name.to_string(),
+            ExpectedReadback {
+                target,
+                path: VecDeque::new(),
+                interpolated,
+                count: 1,
+                written_at: Instant::now(),
+            },
+        );
+    }
+
+    // A remote event got triggered, each trigger is read back once
+    pub fn expect_event(&mut self, event_name: &str, data: u32) {
+        let target = VarReaderTypes::I64(data as i64);
+
+        if let Some(expected) = self.expected.get_mut(event_name) {
+            if expected.written_at.elapsed() <= self.timeout && expected.target == target {
+                expected.count += 1;
+                expected.written_at = Instant::now();
+                return;
+            }
+        }
+
+        self.expected.insert(
+            event_name.to_string(),
+            ExpectedReadback {
+                target,
+                path: VecDeque::new(),
+                interpolated: None,
+                count: 1,
+                written_at: Instant::now(),
+            },
+        );
+    }
+
+    pub fn is_event_echo(&mut self, event_name: &str, data: u32) -> bool {
+        self.is_echo(event_name, &VarReaderTypes::I64(data as i64))
+    }
+
+    // Whether a value read from the sim is the result of a remote write rather than a local change
+    pub fn is_echo(&mut self, var_name: &str, value: &VarReaderTypes) -> bool {
+        let expected = match self.expected.get_mut(var_name) {
+            Some(expected) => expected,
+            None => return false,
+        };
+
+        if expected.written_at.elapsed() > self.timeout {
+            self.expected.remove(var_name);
+            return false;
+        }
+
+        if values_match(&expected.target, value) {
+            expected.count -= 1;
+
+            if expected.count == 0 {
+                self.expected.remove(var_name);
+            }
+
+            return true;
+        }
+
+        if advance_path(expected, value) || advance_interpolation(expected, value) {
+            return true;
+        }
+
+        // The var went somewhere else, so this is a local change and it wins
+        self.expected.remove(var_name);
+        false
+    }
+
+    pub fn clear(&mut self) {
+        self.expected.clear();
+    }
+}
+
+fn tolerance(value: &VarReaderTypes) -> f64 {
+    match value {
+        VarReaderTypes::F64(v) => F64_TOLERANCE.max(v.abs() * F64_RELATIVE_TOLERANCE),
+        _ => 0.0,
+    }
+}
+
+fn values_match(target: &VarReaderTypes, value: &VarReaderTypes) -> bool {
+    match (target, value) {
+        (VarReaderTypes::String(target), VarReaderTypes::String(value)) => target == value,
+        (VarReaderTypes::String(_), _) | (_, VarReaderTypes::String(_)) => false,
+        _ => (target.get_as_f64() - value.get_as_f64()).abs() <= tolerance(target),
+    }
+}
+
+fn get_whole_number(value: &VarReaderTypes) -> Option<f64> {
+    let value = match value {
+        VarReaderTypes::I32(_) | VarReaderTypes::I64(_) | VarReaderTypes::F64(_) => {
+            value.get_as_f64()
+        }
+        VarReaderTypes::Bool(_) | VarReaderTypes::String(_) => return None,
+    };
+
+    if (value - value.round()).abs() > F64_TOLERANCE {
+        return None;
+    }
+
+    Some(value.round())
+}
+
+// Only vars moved in whole steps go through values that can be known ahead of time
+fn get_readback_path(from: Option<&VarReaderTypes>, target: &VarReaderTypes) -> VecDeque<f64> {
+    let (from, target) = match (from.and_then(get_whole_number), get_whole_number(target)) {
+        (Some(from), Some(target)) => (from, target),
+        _ => return VecDeque::new(),
+    };
+
+    let distance = target - from;
+    if distance.abs() > MAX_PATH_STEPS {
+        return VecDeque::new();
+    }
+
+    (1..distance.abs() as i64)
+        .map(|step| from + distance.signum() * step as f64)
+        .collect()
+}
+
+// Readbacks may skip steps but never go back, so the steps before a match are dropped along with it
+fn advance_path(expected: &mut ExpectedReadback, value: &VarReaderTypes) -> bool {
+    let value = match get_whole_number(value) {
+        Some(value) => value,
+        None => return false,
+    };
+
+    match expected.path.iter().position(|step| *step == value) {
+        Some(index) => {
+            expected.path.drain(..=index);
+            true
+        }
+        None => false,
+    }
+}
+
+// Interpolation only moves towards the target, so a readback past it or back the way it came is a local change
+fn advance_interpolation(expected: &mut ExpectedReadback, value: &VarReaderTypes) -> bool {
+    let last = match expected.interpolated {
+        Some(last) => last,
+        None => return false,
+    };
+
+    let value = match value {
+        VarReaderTypes::String(_) => return false,
+        value => value.get_as_f64(),
+    };
+
+    let target = expected.target.get_as_f64();
+    let tolerance = tolerance(&expected.target);
+
+    if value < last.min(target) - tolerance || value > last.max(target) + tolerance {
+        return false;
+    }
+
+    expected.interpolated = Some(value);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suppresses_matching_readback() {
+        let mut echo = EchoSuppressor::new();
+
+        echo.expect_value("PLANE ALTITUDE", VarReaderTypes::F64(1000.0), None);
+        // Unit conversions can be slightly off
+        assert!(echo.is_echo("PLANE ALTITUDE", &VarReaderTypes::F64(1000.0004)));
+        // Only suppressed once
+        assert!(!echo.is_echo("PLANE ALTITUDE", &VarReaderTypes::F64(1000.0)));
+        // Vars that were never written are never echoes
+        assert!(!echo.is_echo("PLANE LATITUDE", &VarReaderTypes::F64(1000.0)));
+    }
+
+    #[test]
+    fn test_local_change_propagates() {
+        let mut echo = EchoSuppressor::new();
+
+        echo.expect_value("L:SWITCH", VarReaderTypes::F64(1.0), None);
+        // User flipped the switch somewhere else before the readback
+        assert!(!echo.is_echo("L:SWITCH", &VarReaderTypes::F64(2.0)));
+        // Expectation is dropped so the next readback goes through too
+        assert!(!echo.is_echo("L:SWITCH", &VarReaderTypes::F64(1.0)));
+
+        echo.expect_value("ELT ACTIVATED", VarReaderTypes::Bool(true), None);
+        assert!(!echo.is_echo("ELT ACTIVATED", &VarReaderTypes::Bool(false)));
+
+        echo.expect_value("ATC ID", VarReaderTypes::String("N172SP".to_string()), None);
+        assert!(!echo.is_echo("ATC ID", &VarReaderTypes::String("N172S".to_string())));
+    }
+
+    #[test]
+    fn test_transition_readbacks() {
+        let mut echo = EchoSuppressor::new();
+
+        // Stepping a knob from 2 to 5 reads back every step along the way
+        echo.expect_value(
+            "LIGHT POTENTIOMETER:3",
+            VarReaderTypes::I32(5),
+            Some(&VarReaderTypes::I32(2)),
+        );
+        assert!(echo.is_echo("LIGHT POTENTIOMETER:3", &VarReaderTypes::I32(3)));
+        assert!(echo.is_echo("LIGHT POTENTIOMETER:3", &VarReaderTypes::I32(4)));
+        assert!(echo.is_echo("LIGHT POTENTIOMETER:3", &VarReaderTypes::I32(5)));
+        // Done once the target is reached
+        assert!(!echo.is_echo("LIGHT POTENTIOMETER:3", &VarReaderTypes::I32(4)));
+
+        // Going past the target is a local change
+        echo.expect_value(
+            "LIGHT POTENTIOMETER:3",
+            VarReaderTypes::I32(5),
+            Some(&VarReaderTypes::I32(2)),
+        );
+        assert!(!echo.is_echo("LIGHT POTENTIOMETER:3", &VarReaderTypes::I32(6)));
+
+        // So is turning it back the way it came
+        echo.expect_value(
+            "LIGHT POTENTIOMETER:3",
+            VarReaderTypes::I32(5),
+            Some(&VarReaderTypes::I32(2)),
+        );
+        assert!(echo.is_echo("LIGHT POTENTIOMETER:3", &VarReaderTypes::I32(4)));
+        assert!(!echo.is_echo("LIGHT POTENTIOMETER:3", &VarReaderTypes::I32(3)));
+    }
+
+    #[test]
+    fn test_supplied_path_wraps() {
+        let mut echo = EchoSuppressor::new();
+
+        // An 8 position selector turned from 1 to 6 goes the short way, through 0 and 7
+        echo.expect_steps(
+            "L:SELECTOR",
+            VarReaderTypes::I32(6),
+            VecDeque::from(vec![0.0, 7.0]),
+        );
+        assert!(echo.is_echo("L:SELECTOR", &VarReaderTypes::I32(0)));
+        assert!(echo.is_echo("L:SELECTOR", &VarReaderTypes::I32(7)));
+        assert!(echo.is_echo("L:SELECTOR", &VarReaderTypes::I32(6)));
+
+        // The long way round is a local change
+        echo.expect_steps(
+            "L:SELECTOR",
+            VarReaderTypes::I32(6),
+            VecDeque::from(vec![0.0, 7.0]),
+        );
+        assert!(!echo.is_echo("L:SELECTOR", &VarReaderTypes::I32(2)));
+    }
+
+    #[test]
+    fn test_interpolated_readbacks() {
+        let mut echo = EchoSuppressor::new();
+
+        echo.expect_interpolation(
+            "GENERAL ENG THROTTLE LEVER POSITION:1",
+            VarReaderTypes::F64(80.0),
+            Some(&VarReaderTypes::F64(20.0)),
+        );
+        assert!(echo.is_echo(
+            "GENERAL ENG THROTTLE LEVER POSITION:1",
+            &VarReaderTypes::F64(35.0)
+        ));
+        assert!(echo.is_echo(
+            "GENERAL ENG THROTTLE LEVER POSITION:1",
+            &VarReaderTypes::F64(60.0)
+        ));
+        assert!(echo.is_echo(
+            "GENERAL ENG THROTTLE LEVER POSITION:1",
+            &VarReaderTypes::F64(80.0)
+        ));
+        // Done once the target is reached
+        assert!(!echo.is_echo(
+            "GENERAL ENG THROTTLE LEVER POSITION:1",
+            &VarReaderTypes::F64(70.0)
+        ));
+
+        // Pulling the lever back while it interpolates is a local change
+        echo.expect_interpolation(
+            "GENERAL ENG THROTTLE LEVER POSITION:1",
+            VarReaderTypes::F64(80.0),
+            Some(&VarReaderTypes::F64(20.0)),
+        );
+        assert!(echo.is_echo(
+            "GENERAL ENG THROTTLE LEVER POSITION:1",
+            &VarReaderTypes::F64(50.0)
+        ));
+        assert!(!echo.is_echo(
+            "GENERAL ENG THROTTLE LEVER POSITION:1",
+            &VarReaderTypes::F64(40.0)
+        ));
+    }
+
+    #[test]
+    fn test_values_between_are_not_echoes() {
+        let mut echo = EchoSuppressor::new();
+
+        // Nothing is known about where a lever passes through, only the target is suppressed
+        echo.expect_value(
+            "GENERAL ENG THROTTLE LEVER POSITION:1",
+            VarReaderTypes::F64(80.0),
+            Some(&VarReaderTypes::F64(20.5)),
+        );
+        assert!(!echo.is_echo(
+            "GENERAL ENG THROTTLE LEVER POSITION:1",
+            &VarReaderTypes::F64(50.0)
+        ));
+    }
+
+    #[test]
+    fn test_events() {
+        let mut echo = EchoSuppressor::new();
+
+        echo.expect_event("TOGGLE_BEACON_LIGHTS", 0);
+        echo.expect_event("TOGGLE_BEACON_LIGHTS", 0);
+
+        assert!(echo.is_event_echo("TOGGLE_BEACON_LIGHTS", 0));
+        assert!(echo.is_event_echo("TOGGLE_BEACON_LIGHTS", 0));
+        assert!(!echo.is_event_echo("TOGGLE_BEACON_LIGHTS", 0));
+
+        echo.expect_event("AP_ALT_VAR_SET_ENGLISH", 5000);
+        assert!(!echo.is_event_echo("AP_ALT_VAR_SET_ENGLISH", 6000));
+    }
+
+    #[test]
+    fn test_expectations_time_out() {
+        let mut echo = EchoSuppressor::with_timeout(Duration::from_millis(0));
+
+        echo.expect_value("L:SWITCH", VarReaderTypes::F64(1.0), None);
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(!echo.is_echo("L:SWITCH", &VarReaderTypes::F64(1.0)));
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut echo = EchoSuppressor::new();
+
+        echo.expect_value("L:SWITCH", VarReaderTypes::F64(1.0), None);
+        echo.clear();
+        assert!(!echo.is_echo("L:SWITCH", &VarReaderTypes::F64(1.0)));
+    }
+}
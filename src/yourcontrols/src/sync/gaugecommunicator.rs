@@ -1,4 +1,5 @@
 use super::memwriter::MemWriter;
+use schemars::JsonSchema;
 use serde::Deserialize;
 use simconnect::SimConnector;
 use std::collections::HashMap;
@@ -47,7 +48,7 @@ pub struct InterpolateData {
     pub value: f64,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
 pub enum InterpolationType {
     Default,
     Wrap180,
@@ -195,6 +196,10 @@ impl GaugeCommunicator {
         );
     }
 
+    pub fn has_interpolate_mapping(&self, index_var_name: &str) -> bool {
+        self.interpolate_datums.contains_key(index_var_name)
+    }
+
     pub fn send_new_interpolation_data(
         &self,
         conn: &SimConnector,
@@ -0,0 +1,280 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use log::{info, warn};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::{c_void, CString},
+    io::{Cursor, Read},
+    os::raw::c_char,
+};
+
+// The simconnect crate predates input events, so the few calls needed are bound here
+// and made on a connection of our own, its dispatch loop would drop the replies
+type Handle = *mut c_void;
+
+#[allow(non_snake_case)]
+extern "system" {
+    fn SimConnect_Open(
+        phSimConnect: *mut Handle,
+        szName: *const c_char,
+        hWnd: *mut c_void,
+        UserEventWin32: u32,
+        hEventHandle: Handle,
+        ConfigIndex: u32,
+    ) -> i32;
+    fn SimConnect_Close(hSimConnect: Handle) -> i32;
+    fn SimConnect_GetNextDispatch(
+        hSimConnect: Handle,
+        ppData: *mut *mut u8,
+        pcbData: *mut u32,
+    ) -> i32;
+    fn SimConnect_EnumerateInputEvents(hSimConnect: Handle, RequestID: u32) -> i32;
+    fn SimConnect_SetInputEvent(
+        hSimConnect: Handle,
+        Hash: u64,
+        cbUnitSize: u32,
+        Value: *const c_void,
+    ) -> i32;
+}
+
+const RECV_ID_QUIT: u32 = 3;
+const RECV_ID_ENUMERATE_INPUT_EVENTS: u32 = 34;
+const ENUMERATE_REQUEST_ID: u32 = 0;
+// SIMCONNECT_INPUT_EVENT_DESCRIPTOR, packed
+const EVENT_NAME_SIZE: usize = 64;
+const DESCRIPTOR_SIZE: usize = EVENT_NAME_SIZE + 8 + 4;
+
+struct InputEventList {
+    events: Vec<(String, u64)>,
+    is_last: bool,
+}
+
+// Reads a SIMCONNECT_RECV_ENUMERATE_INPUT_EVENTS, which can be split over several messages
+fn parse_input_event_list(data: &[u8]) -> Option<InputEventList> {
+    let mut cursor = Cursor::new(data);
+    // SIMCONNECT_RECV
+    let _size = cursor.read_u32::<LittleEndian>().ok()?;
+    let _version = cursor.read_u32::<LittleEndian>().ok()?;
+    let id = cursor.read_u32::<LittleEndian>().ok()?;
+    if id != RECV_ID_ENUMERATE_INPUT_EVENTS {
+        return None;
+    }
+    // SIMCONNECT_RECV_LIST_TEMPLATE
+    let _request_id = cursor.read_u32::<LittleEndian>().ok()?;
+    let array_size = cursor.read_u32::<LittleEndian>().ok()? as usize;
+    let entry_number = cursor.read_u32::<LittleEndian>().ok()?;
+    let out_of = cursor.read_u32::<LittleEndian>().ok()?;
+
+    let remaining = data.len() - cursor.position() as usize;
+    if array_size > remaining / DESCRIPTOR_SIZE {
+        return None;
+    }
+
+    let mut events = Vec::with_capacity(array_size);
+    for _ in 0..array_size {
+        let mut name = [0; EVENT_NAME_SIZE];
+        cursor.read_exact(&mut name).ok()?;
+        let hash = cursor.read_u64::<LittleEndian>().ok()?;
+        let _event_type = cursor.read_u32::<LittleEndian>().ok()?;
+
+        let length = name.iter().position(|&b| b == 0).unwrap_or(EVENT_NAME_SIZE);
+        events.push((String::from_utf8_lossy(&name[..length]).to_string(), hash));
+    }
+
+    Some(InputEventList {
+        events,
+        is_last: entry_number + 1 >= out_of,
+    })
+}
+
+// MSFS 2024 input event hashes for the loaded aircraft, looked up by the event's name
+pub struct InputEvents {
+    handle: Handle,
+    hashes: HashMap<String, u64>,
+    // Names definitions sync, checked against the aircraft once enumerated
+    registered: HashSet<String>,
+}
+
+impl InputEvents {
+    pub fn new() -> Self {
+        Self {
+            handle: std::ptr::null_mut(),
+            hashes: HashMap::new(),
+            registered: HashSet::new(),
+        }
+    }
+
+    pub fn register(&mut self, event_name: &str) {
+        self.registered.insert(event_name.to_string());
+    }
+
+    pub fn has_registered(&self) -> bool {
+        !self.registered.is_empty()
+    }
+
+    // Hashes are only valid for the aircraft they were enumerated on
+    pub fn enumerate(&mut self) {
+        self.hashes.clear();
+
+        if self.handle.is_null() {
+            let name = CString::new("YourControls Input Events").unwrap();
+            let result = unsafe {
+                SimConnect_Open(
+                    &mut self.handle,
+                    name.as_ptr(),
+                    std::ptr::null_mut(),
+                    0,
+                    std::ptr::null_mut(),
+                    0,
+                )
+            };
+
+            if result != 0 {
+                self.handle = std::ptr::null_mut();
+                warn!(
+                    "[INPUT] Could not connect to enumerate input events: {}",
+                    result
+                );
+                return;
+            }
+        }
+
+        unsafe { SimConnect_EnumerateInputEvents(self.handle, ENUMERATE_REQUEST_ID) };
+    }
+
+    pub fn poll(&mut self) {
+        if self.handle.is_null() {
+            return;
+        }
+
+        let mut data = std::ptr::null_mut();
+        let mut size = 0;
+
+        while unsafe { SimConnect_GetNextDispatch(self.handle, &mut data, &mut size) } == 0 {
+            let bytes = unsafe { std::slice::from_raw_parts(data, size as usize) };
+
+            if let Some(list) = parse_input_event_list(bytes) {
+                self.hashes.extend(list.events);
+
+                if list.is_last {
+                    self.on_enumerated();
+                }
+            } else if bytes.get(8..12) == Some(&RECV_ID_QUIT.to_le_bytes()) {
+                self.close();
+                return;
+            }
+        }
+    }
+
+    fn on_enumerated(&mut self) {
+        info!("[INPUT] Enumerated {} input events", self.hashes.len());
+
+        for event_name in self.registered.iter() {
+            if !self.hashes.contains_key(event_name) {
+                warn!("[INPUT] Aircraft has no input event {}", event_name);
+            }
+        }
+    }
+
+    pub fn get_hash(&self, event_name: &str) -> Option<u64> {
+        self.hashes.get(event_name).copied()
+    }
+
+    // Returns false if the event can't be set by its hash yet
+    pub fn set(&self, event_name: &str, value: f64) -> bool {
+        let hash = match self.get_hash(event_name) {
+            Some(hash) => hash,
+            None => return false,
+        };
+
+        let result = unsafe {
+            SimConnect_SetInputEvent(
+                self.handle,
+                hash,
+                std::mem::size_of::<f64>() as u32,
+                &value as *const f64 as *const c_void,
+            )
+        };
+
+        result == 0
+    }
+
+    fn close(&mut self) {
+        if !self.handle.is_null() {
+            unsafe { SimConnect_Close(self.handle) };
+            self.handle = std::ptr::null_mut();
+        }
+        self.hashes.clear();
+    }
+}
+
+impl Drop for InputEvents {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_descriptor(data: &mut Vec<u8>, name: &str, hash: u64) {
+        let mut name_bytes = [0; EVENT_NAME_SIZE];
+        name_bytes[..name.len()].copy_from_slice(name.as_bytes());
+
+        data.extend_from_slice(&name_bytes);
+        data.extend_from_slice(&hash.to_le_bytes());
+        data.extend_from_slice(&1u32.to_le_bytes());
+    }
+
+    fn write_list(events: &[(&str, u64)], entry_number: u32, out_of: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+        for value in [
+            0,
+            0,
+            RECV_ID_ENUMERATE_INPUT_EVENTS,
+            ENUMERATE_REQUEST_ID,
+            events.len() as u32,
+            entry_number,
+            out_of,
+        ] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+
+        for (name, hash) in events {
+            write_descriptor(&mut data, name, *hash);
+        }
+
+        data
+    }
+
+    #[test]
+    fn test_parses_enumerated_input_events() {
+        let data = write_list(
+            &[("LIGHTING_LANDING_1", 0x1234), ("HANDLING_FLAPS", u64::MAX)],
+            0,
+            2,
+        );
+
+        let list = parse_input_event_list(&data).unwrap();
+        assert_eq!(
+            list.events,
+            vec![
+                ("LIGHTING_LANDING_1".to_string(), 0x1234),
+                ("HANDLING_FLAPS".to_string(), u64::MAX)
+            ]
+        );
+        // Another message is still to come
+        assert!(!list.is_last);
+
+        let list = parse_input_event_list(&write_list(&[], 1, 2)).unwrap();
+        assert!(list.is_last);
+    }
+
+    #[test]
+    fn test_rejects_truncated_input_event_list() {
+        let mut data = write_list(&[("LIGHTING_LANDING_1", 0x1234)], 0, 1);
+        data.truncate(data.len() - 1);
+
+        assert!(parse_input_event_list(&data).is_none());
+    }
+}
@@ -1,5 +1,10 @@
+pub mod budget;
+pub mod conflict;
+pub mod echo;
 pub mod freezer;
 pub mod gaugecommunicator;
+pub mod inputevents;
 pub mod jscommunicator;
 pub mod memwriter;
+pub mod pause;
 pub mod transfer;
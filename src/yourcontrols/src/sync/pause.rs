@@ -0,0 +1,251 @@
+use std::{collections::HashMap, time::Instant};
+
+use crate::{sync::gaugecommunicator::GaugeCommunicator, util::InDataTypes};
+
+use super::transfer::AircraftVars;
+use log::info;
+use simconnect::SimConnector;
+use yourcontrols_types::VarReaderTypes;
+
+pub const SIM_RATE_VAR: &str = "SIMULATION RATE";
+// Well above any id Events will hand out
+const PAUSE_EVENT_ID: u32 = 0xFFFF_0000;
+// How long to ignore the sim stepping through rates after applying a remote one
+const APPLY_TIMEOUT_SECS: f64 = 2.0;
+// Range the sim lets the rate be stepped through
+const MIN_SIM_RATE: f64 = 0.25;
+const MAX_SIM_RATE: f64 = 128.0;
+// Pause_EX1 flags, only a full pause is shared, the menu and active pause are left to each pilot
+const PAUSE_STATE_FLAG_PAUSE: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PauseState {
+    pub paused: bool,
+    pub sim_rate: f64,
+}
+
+impl PauseState {
+    // Anything from the network goes through here, a rate the sim can't step to is brought within range
+    pub fn new(paused: bool, sim_rate: f64) -> Option<Self> {
+        if !sim_rate.is_finite() {
+            return None;
+        }
+
+        Some(Self {
+            paused,
+            sim_rate: sim_rate.clamp(MIN_SIM_RATE, MAX_SIM_RATE),
+        })
+    }
+}
+
+impl Default for PauseState {
+    fn default() -> Self {
+        Self {
+            paused: false,
+            sim_rate: 1.0,
+        }
+    }
+}
+
+// RPN to get the sim from one pause state to another, sim rate can only be stepped in powers of two
+fn get_apply_script(from: PauseState, to: PauseState) -> String {
+    let mut script = Vec::new();
+
+    if from.paused != to.paused {
+        script.push(format!("{} (>K:PAUSE_SET)", to.paused as u8));
+    }
+
+    let from_rate = from.sim_rate.clamp(MIN_SIM_RATE, MAX_SIM_RATE);
+    let to_rate = to.sim_rate.clamp(MIN_SIM_RATE, MAX_SIM_RATE);
+    let steps = (to_rate / from_rate).log2().round() as i32;
+    let event = if steps > 0 {
+        "(>K:SIM_RATE_INCR)"
+    } else {
+        "(>K:SIM_RATE_DECR)"
+    };
+
+    for _ in 0..steps.abs() {
+        script.push(event.to_string());
+    }
+
+    script.join(" ")
+}
+
+// Keeps pause and sim rate the same for everyone, like Freezer it talks to the sim directly
+pub struct PauseSync {
+    // Last state everyone agreed on
+    current: PauseState,
+    // Last state the sim told us about
+    reported: PauseState,
+    // A change made in our sim that should be sent out
+    pending: Option<PauseState>,
+    // A state received from the network that the sim is still catching up to
+    applying: Option<(PauseState, Instant)>,
+}
+
+impl PauseSync {
+    pub fn new() -> Self {
+        Self {
+            current: PauseState::default(),
+            reported: PauseState::default(),
+            pending: None,
+            applying: None,
+        }
+    }
+
+    pub fn register_vars(&self, avars: &mut AircraftVars) {
+        avars.add_var(SIM_RATE_VAR, "Number", InDataTypes::F64);
+    }
+
+    pub fn on_connected(&self, conn: &SimConnector) {
+        conn.subscribe_to_system_event(PAUSE_EVENT_ID, "Pause_EX1");
+    }
+
+    // Returns whether the event was ours
+    pub fn process_event_data(&mut self, data: &simconnect::SIMCONNECT_RECV_EVENT) -> bool {
+        if data.uEventID != PAUSE_EVENT_ID {
+            return false;
+        }
+
+        self.on_sim_state(PauseState {
+            paused: data.dwData & PAUSE_STATE_FLAG_PAUSE != 0,
+            ..self.reported
+        });
+
+        true
+    }
+
+    pub fn on_vars_change(&mut self, vars: &HashMap<String, VarReaderTypes>) {
+        if let Some(VarReaderTypes::F64(sim_rate)) = vars.get(SIM_RATE_VAR) {
+            self.on_sim_state(PauseState {
+                sim_rate: *sim_rate,
+                ..self.reported
+            });
+        }
+    }
+
+    fn on_sim_state(&mut self, state: PauseState) {
+        self.reported = state;
+
+        if let Some((target, applied_at)) = self.applying {
+            // The sim goes through every rate in between
+            if state != target && applied_at.elapsed().as_secs_f64() < APPLY_TIMEOUT_SECS {
+                return;
+            }
+
+            self.applying = None;
+        }
+
+        if state == self.current {
+            return;
+        }
+
+        self.current = state;
+        self.pending = Some(state);
+    }
+
+    pub fn apply(&mut self, conn: &SimConnector, gauge: &GaugeCommunicator, state: PauseState) {
+        let state = match PauseState::new(state.paused, state.sim_rate) {
+            Some(state) => state,
+            None => return,
+        };
+
+        let script = get_apply_script(self.current, state);
+
+        self.current = state;
+        self.pending = None;
+
+        if script.is_empty() {
+            return;
+        }
+
+        info!(
+            "[PAUSE] Applying paused: {}, sim rate: {}",
+            state.paused, state.sim_rate
+        );
+
+        self.applying = Some((state, Instant::now()));
+        gauge.send_raw(conn, &script);
+    }
+
+    pub fn get_next_change(&mut self) -> Option<PauseState> {
+        self.pending.take()
+    }
+
+    pub fn get_state(&self) -> PauseState {
+        self.current
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.current.paused
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(paused: bool, sim_rate: f64) -> PauseState {
+        PauseState { paused, sim_rate }
+    }
+
+    #[test]
+    fn test_apply_script() {
+        assert_eq!(get_apply_script(state(false, 1.0), state(false, 1.0)), "");
+        assert_eq!(
+            get_apply_script(state(false, 1.0), state(true, 1.0)),
+            "1 (>K:PAUSE_SET)"
+        );
+        assert_eq!(
+            get_apply_script(state(true, 1.0), state(false, 4.0)),
+            "0 (>K:PAUSE_SET) (>K:SIM_RATE_INCR) (>K:SIM_RATE_INCR)"
+        );
+        assert_eq!(
+            get_apply_script(state(false, 2.0), state(false, 0.5)),
+            "(>K:SIM_RATE_DECR) (>K:SIM_RATE_DECR)"
+        );
+    }
+
+    #[test]
+    fn test_sim_rate_kept_in_range() {
+        assert_eq!(PauseState::new(false, f64::NAN), None);
+        assert_eq!(PauseState::new(false, f64::INFINITY), None);
+        assert_eq!(PauseState::new(false, 0.0), Some(state(false, 0.25)));
+        assert_eq!(PauseState::new(true, 1e9), Some(state(true, 128.0)));
+
+        // Rates that used to come out as i32::MIN steps
+        assert_eq!(
+            get_apply_script(state(false, 1.0), state(false, 0.0)),
+            "(>K:SIM_RATE_DECR) (>K:SIM_RATE_DECR)"
+        );
+        assert_eq!(get_apply_script(state(false, 0.0), state(false, 0.25)), "");
+    }
+
+    #[test]
+    fn test_local_changes_are_queued_once() {
+        let mut pause = PauseSync::new();
+
+        pause.on_sim_state(state(true, 1.0));
+        pause.on_sim_state(state(true, 1.0));
+
+        assert_eq!(pause.get_next_change(), Some(state(true, 1.0)));
+        assert_eq!(pause.get_next_change(), None);
+    }
+
+    #[test]
+    fn test_applied_state_is_not_sent_back() {
+        let mut pause = PauseSync::new();
+
+        pause.current = state(false, 4.0);
+        pause.applying = Some((state(false, 4.0), Instant::now()));
+
+        // Stepping up to the new rate
+        pause.on_sim_state(state(false, 2.0));
+        pause.on_sim_state(state(false, 4.0));
+        assert_eq!(pause.get_next_change(), None);
+
+        // Changes afterwards are ours again
+        pause.on_sim_state(state(false, 8.0));
+        assert_eq!(pause.get_next_change(), Some(state(false, 8.0)));
+    }
+}
@@ -10,7 +10,10 @@ use std::{
 };
 use yourcontrols_types::VarReaderTypes;
 
-use super::gaugecommunicator::{GaugeCommunicator, GetResult};
+use super::{
+    gaugecommunicator::{GaugeCommunicator, GetResult},
+    inputevents::InputEvents,
+};
 
 pub struct Events {
     event_map: BiHashMap<String, u32>,
@@ -83,6 +86,7 @@ impl Events {
 }
 pub struct LVarSyncer {
     pub transfer: GaugeCommunicator,
+    pub input_events: InputEvents,
     current_values: HashMap<String, f64>,
     raw_count: u32,
 }
@@ -91,6 +95,7 @@ impl LVarSyncer {
     pub fn new() -> Self {
         Self {
             transfer: GaugeCommunicator::new(),
+            input_events: InputEvents::new(),
             current_values: HashMap::new(),
             raw_count: 0,
         }
@@ -216,7 +221,7 @@ impl AircraftVars {
         };
 
         for (var_name, value) in vars.iter() {
-            self.current_values.insert(var_name.clone(), *value);
+            self.current_values.insert(var_name.clone(), value.clone());
         }
 
         Ok(vars)
@@ -270,6 +275,16 @@ impl AircraftVars {
                         0.0,
                     );
                 }
+                InDataTypes::String => {
+                    conn.add_data_definition(
+                        self.define_id,
+                        var_name,
+                        &var_data.var_units,
+                        simconnect::SIMCONNECT_DATATYPE_SIMCONNECT_DATATYPE_STRING256,
+                        var_data.datum_id,
+                        0.0,
+                    );
+                }
             }
         }
     }
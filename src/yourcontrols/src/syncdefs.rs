@@ -2,13 +2,14 @@ use std::{
     cmp::PartialOrd,
     fmt::Display,
     ops::{Add, AddAssign, Mul, Sub, SubAssign},
+    time::{Duration, Instant},
 };
 
 use num::{FromPrimitive, ToPrimitive};
 
 use crate::{
     sync::transfer::LVarSyncer,
-    util::{float_eq, wrap_diff, NumberDigits},
+    util::{float_eq, rotary_path, rotary_steps, wrap_diff, NumberDigits},
 };
 
 const GROUP_ID: u32 = 5;
@@ -19,6 +20,12 @@ where
 {
     fn set_current(&mut self, current: T);
     fn set_new(&mut self, new: T, conn: &simconnect::SimConnector, lvar_transfer: &mut LVarSyncer);
+    // Called every step for actions that spread their work out over time
+    fn poll(&mut self, _conn: &simconnect::SimConnector, _lvar_transfer: &mut LVarSyncer) {}
+    // Values the sim reads back in order on the way from one value to another, if the action knows them
+    fn get_readback_path(&self, _from: T, _to: T) -> Option<Vec<f64>> {
+        None
+    }
 }
 
 pub struct ToggleSwitch {
@@ -477,6 +484,114 @@ impl Syncable<i32> for NumDigitSet {
         }
     }
 }
+
+// Multi position knobs that can only be moved one detent at a time
+pub struct RotarySelector {
+    inc_event_id: Option<u32>,
+    dec_event_id: Option<u32>,
+    inc_event_name: Option<String>,
+    dec_event_name: Option<String>,
+    event_param: u32,
+    positions: i32,
+    wrap: bool,
+    step_delay: Duration,
+    current: i32,
+    // Steps left to reach the target, negative for dec
+    pending_steps: i32,
+    last_step: Instant,
+}
+
+impl RotarySelector {
+    pub fn new(positions: i32, wrap: bool, step_delay: Duration) -> Self {
+        Self {
+            inc_event_id: None,
+            dec_event_id: None,
+            inc_event_name: None,
+            dec_event_name: None,
+            event_param: 0,
+            positions,
+            wrap,
+            step_delay,
+            current: 0,
+            pending_steps: 0,
+            last_step: Instant::now(),
+        }
+    }
+
+    pub fn set_event_ids(&mut self, inc_event_id: u32, dec_event_id: u32) {
+        self.inc_event_id = Some(inc_event_id);
+        self.dec_event_id = Some(dec_event_id);
+    }
+
+    pub fn set_event_names(&mut self, inc_event_name: String, dec_event_name: String) {
+        self.inc_event_name = Some(format!("K:{}", inc_event_name));
+        self.dec_event_name = Some(format!("K:{}", dec_event_name));
+    }
+
+    pub fn set_event_param(&mut self, event_param: u32) {
+        self.event_param = event_param;
+    }
+
+    fn step(&mut self, conn: &simconnect::SimConnector, lvar_transfer: &mut LVarSyncer) {
+        let (event_id, event_name) = if self.pending_steps > 0 {
+            self.pending_steps -= 1;
+            (self.inc_event_id, &self.inc_event_name)
+        } else {
+            self.pending_steps += 1;
+            (self.dec_event_id, &self.dec_event_name)
+        };
+
+        if let Some(event_id) = event_id {
+            conn.transmit_client_event(
+                1,
+                event_id,
+                self.event_param,
+                GROUP_ID,
+                simconnect::SIMCONNECT_EVENT_FLAG_GROUPID_IS_PRIORITY,
+            );
+        } else if let Some(event_name) = event_name {
+            lvar_transfer.set_unchecked(conn, event_name, None, &self.event_param.to_string())
+        }
+
+        self.last_step = Instant::now();
+    }
+}
+
+impl Syncable<i32> for RotarySelector {
+    fn set_current(&mut self, current: i32) {
+        self.current = current
+    }
+
+    fn set_new(
+        &mut self,
+        new: i32,
+        conn: &simconnect::SimConnector,
+        lvar_transfer: &mut LVarSyncer,
+    ) {
+        self.pending_steps = rotary_steps(self.current, new, self.positions, self.wrap);
+        self.poll(conn, lvar_transfer);
+    }
+
+    fn poll(&mut self, conn: &simconnect::SimConnector, lvar_transfer: &mut LVarSyncer) {
+        if self.step_delay.is_zero() {
+            while self.pending_steps != 0 {
+                self.step(conn, lvar_transfer);
+            }
+        } else if self.pending_steps != 0 && self.last_step.elapsed() >= self.step_delay {
+            self.step(conn, lvar_transfer);
+        }
+    }
+
+    fn get_readback_path(&self, from: i32, to: i32) -> Option<Vec<f64>> {
+        Some(
+            rotary_path(from, to, self.positions, self.wrap)
+                .into_iter()
+                .map(f64::from)
+                .collect(),
+        )
+    }
+}
+
 pub struct CustomCalculator {
     set_string: String,
     current: f64,
@@ -504,6 +619,39 @@ impl Syncable<f64> for CustomCalculator {
     }
 }
 
+// MSFS 2024 input events (B: vars), set by their hash once the aircraft's events are enumerated,
+// or through their binding e.g. (>B:LIGHTING_LANDING_1_Set) until then
+pub struct InputEvent {
+    event_name: String,
+    set_string: String,
+    current: f64,
+}
+
+impl InputEvent {
+    pub fn new(event_name: String, set_string: String) -> Self {
+        Self {
+            event_name,
+            set_string,
+            current: 0.0,
+        }
+    }
+}
+
+impl Syncable<f64> for InputEvent {
+    fn set_current(&mut self, new: f64) {
+        self.current = new
+    }
+
+    fn set_new(&mut self, new: f64, conn: &simconnect::SimConnector, transfer: &mut LVarSyncer) {
+        if float_eq(&self.current, &new) {
+            return;
+        }
+        if !transfer.input_events.set(&self.event_name, new) {
+            transfer.send_raw(conn, &format!("{} (>{})", new, self.set_string));
+        }
+    }
+}
+
 pub struct LocalVarProxy {
     target: String,
     loopback_var: Option<String>,
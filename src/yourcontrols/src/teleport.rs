@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+
+use log::info;
+use simconnect::SimConnector;
+use yourcontrols_types::VarReaderTypes;
+
+use crate::{sync::transfer::AircraftVars, util::InDataTypes};
+
+pub const SLEW_VAR: &str = "IS SLEW ACTIVE";
+const LATITUDE_VAR: &str = "PLANE LATITUDE";
+const LONGITUDE_VAR: &str = "PLANE LONGITUDE";
+
+const EARTH_RADIUS_FEET: f64 = 20_902_231.0;
+
+// The sim moving the aircraft on its own, ids well above any Events hands out
+const RESET_EVENTS: [(u32, &str); 3] = [
+    (0xFFFF_0001, "PositionChanged"),
+    (0xFFFF_0002, "CrashReset"),
+    (0xFFFF_0003, "FlightLoaded"),
+];
+
+// Great circle distance between two positions in radians
+fn get_distance_feet(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lat1, lon1) = from;
+    let (lat2, lon2) = to;
+
+    let a = ((lat2 - lat1) / 2.0).sin().powi(2)
+        + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS_FEET * a.sqrt().min(1.0).asin()
+}
+
+// Notices when the aircraft jumps instead of flying there, so peers set the position instead of interpolating to it
+pub struct TeleportDetector {
+    max_jump_feet: f64,
+    in_radians: bool,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    slewing: bool,
+    should_snap: bool,
+}
+
+impl TeleportDetector {
+    pub fn new(max_jump_feet: f64, in_radians: bool) -> Self {
+        Self {
+            max_jump_feet,
+            in_radians,
+            latitude: None,
+            longitude: None,
+            slewing: false,
+            should_snap: false,
+        }
+    }
+
+    pub fn register_vars(&self, avars: &mut AircraftVars) {
+        avars.add_var(SLEW_VAR, "Bool", InDataTypes::Bool);
+    }
+
+    pub fn on_connected(&self, conn: &SimConnector) {
+        for (event_id, event_name) in RESET_EVENTS {
+            conn.subscribe_to_system_event(event_id, event_name);
+        }
+    }
+
+    // Returns whether the event was ours
+    pub fn process_event_data(&mut self, data: &simconnect::SIMCONNECT_RECV_EVENT) -> bool {
+        let Some((_, event_name)) = RESET_EVENTS.iter().find(|(id, _)| *id == data.uEventID) else {
+            return false;
+        };
+
+        info!("[TELEPORT] Sim event {}", event_name);
+        self.should_snap = true;
+
+        true
+    }
+
+    fn angle_to_radians(&self, value: f64) -> f64 {
+        if self.in_radians {
+            value
+        } else {
+            value.to_radians()
+        }
+    }
+
+    pub fn on_vars_change(&mut self, vars: &HashMap<String, VarReaderTypes>) {
+        // Entering and leaving slew snaps, in between only real jumps do so the send budget still applies
+        if let Some(VarReaderTypes::Bool(slewing)) = vars.get(SLEW_VAR) {
+            if *slewing != self.slewing {
+                info!(
+                    "[TELEPORT] Slew {}",
+                    if *slewing { "started" } else { "ended" }
+                );
+                self.should_snap = true;
+            }
+
+            self.slewing = *slewing;
+        }
+
+        let last_position = self.latitude.zip(self.longitude);
+
+        if let Some(latitude) = vars.get(LATITUDE_VAR) {
+            self.latitude = Some(self.angle_to_radians(latitude.get_as_f64()));
+        }
+
+        if let Some(longitude) = vars.get(LONGITUDE_VAR) {
+            self.longitude = Some(self.angle_to_radians(longitude.get_as_f64()));
+        }
+
+        if let (Some(from), Some(to)) = (last_position, self.latitude.zip(self.longitude)) {
+            let distance = get_distance_feet(from, to);
+
+            if distance > self.max_jump_feet {
+                info!("[TELEPORT] Aircraft jumped {:.0} feet", distance);
+                self.should_snap = true;
+            }
+        }
+    }
+
+    pub fn take_should_snap(&mut self) -> bool {
+        std::mem::replace(&mut self.should_snap, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(latitude: f64, longitude: f64) -> HashMap<String, VarReaderTypes> {
+        let mut vars = HashMap::new();
+        vars.insert(LATITUDE_VAR.to_string(), VarReaderTypes::F64(latitude));
+        vars.insert(LONGITUDE_VAR.to_string(), VarReaderTypes::F64(longitude));
+        vars
+    }
+
+    #[test]
+    fn test_distance() {
+        // A degree of latitude is about 60 nautical miles
+        let distance = get_distance_feet((0.0, 0.0), (1.0_f64.to_radians(), 0.0));
+        assert!((distance - 364_800.0).abs() < 1000.0);
+    }
+
+    #[test]
+    fn test_flying_does_not_snap() {
+        let mut detector = TeleportDetector::new(5000.0, false);
+
+        detector.on_vars_change(&position(47.0, 8.0));
+        // Roughly 600 feet
+        detector.on_vars_change(&position(47.0, 8.0025));
+
+        assert!(!detector.take_should_snap());
+    }
+
+    #[test]
+    fn test_jump_snaps_once() {
+        let mut detector = TeleportDetector::new(5000.0, false);
+
+        detector.on_vars_change(&position(47.0, 8.0));
+        detector.on_vars_change(&position(51.5, -0.1));
+
+        assert!(detector.take_should_snap());
+        assert!(!detector.take_should_snap());
+
+        // Only one of them changing is still compared against the last full position
+        let mut vars = HashMap::new();
+        vars.insert(LATITUDE_VAR.to_string(), VarReaderTypes::F64(51.5001));
+        detector.on_vars_change(&vars);
+        assert!(!detector.take_should_snap());
+    }
+
+    #[test]
+    fn test_slewing_snaps_on_edges() {
+        let mut detector = TeleportDetector::new(5000.0, true);
+
+        let mut vars = position(0.8, 0.1);
+        vars.insert(SLEW_VAR.to_string(), VarReaderTypes::Bool(true));
+        detector.on_vars_change(&vars);
+        assert!(detector.take_should_snap());
+
+        // Moving around while slewing goes through the budget like flying
+        detector.on_vars_change(&position(0.8, 0.1000001));
+        assert!(!detector.take_should_snap());
+
+        // Unless it's far enough to be a jump anyway
+        detector.on_vars_change(&position(0.8, 0.2));
+        assert!(detector.take_should_snap());
+
+        let mut vars = HashMap::new();
+        vars.insert(SLEW_VAR.to_string(), VarReaderTypes::Bool(false));
+        detector.on_vars_change(&vars);
+        assert!(detector.take_should_snap());
+
+        detector.on_vars_change(&vars);
+        assert!(!detector.take_should_snap());
+    }
+}
@@ -1,3 +1,4 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::{
     net::IpAddr,
@@ -5,12 +6,14 @@ use std::{
 };
 use yourcontrols_types::Error;
 
-pub fn get_hostname_ip(hostname: &str, isipv6: bool) -> Result<IpAddr, Error> {
-    match dns_lookup::lookup_host(hostname)?
-        .into_iter()
-        .find(|&x| x.is_ipv6() && isipv6 || x.is_ipv4() && !isipv6)
-    {
-        Some(ip) => Ok(ip),
+// Sockets are dual stack, so an address in the other family is fine when there's none in the preferred one
+pub fn get_hostname_ip(hostname: &str, prefer_ipv6: bool) -> Result<IpAddr, Error> {
+    let ips = dns_lookup::lookup_host(hostname)?;
+
+    let preferred = ips.iter().find(|x| x.is_ipv6() == prefer_ipv6);
+
+    match preferred.or(ips.first()) {
+        Some(ip) => Ok(*ip),
         None => Err(Error::MismatchingIpVersion),
     }
 }
@@ -28,6 +31,47 @@ pub fn wrap_diff(from: f64, to: f64, max: f64) -> f64 {
     to - from
 }
 
+// Number of inc (positive) or dec (negative) steps to get a selector from one position to another
+pub fn rotary_steps(from: i32, to: i32, positions: i32, wrap: bool) -> i32 {
+    if positions <= 1 {
+        return 0;
+    }
+
+    if wrap {
+        let from = from.rem_euclid(positions);
+        let to = to.rem_euclid(positions);
+        wrap_diff(from as f64, to as f64, positions as f64) as i32
+    } else {
+        to.clamp(0, positions - 1) - from.clamp(0, positions - 1)
+    }
+}
+
+// Positions a selector passes through on its way from one position to another, not counting either
+pub fn rotary_path(from: i32, to: i32, positions: i32, wrap: bool) -> Vec<i32> {
+    if positions <= 1 {
+        return Vec::new();
+    }
+
+    let steps = rotary_steps(from, to, positions, wrap);
+
+    let from = if wrap {
+        from.rem_euclid(positions)
+    } else {
+        from.clamp(0, positions - 1)
+    };
+
+    (1..steps.abs())
+        .map(|step| {
+            let position = from + steps.signum() * step;
+            if wrap {
+                position.rem_euclid(positions)
+            } else {
+                position
+            }
+        })
+        .collect()
+}
+
 #[derive(Eq, PartialEq)]
 pub enum Category {
     Shared,
@@ -36,13 +80,14 @@ pub enum Category {
     Init,
 }
 
-#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Copy, Clone)]
 #[serde(rename_all = "lowercase")]
 pub enum InDataTypes {
     Bool,
     I32,
     I64,
     F64,
+    String,
 }
 
 pub struct NumberDigits {
@@ -137,4 +182,31 @@ mod test {
         assert!(float_eq(&wrap_diff(350.0, 10.0, 360.0), &20.0));
         assert!(float_eq(&wrap_diff(10.0, 350.0, 360.0), &-20.0));
     }
+
+    #[test]
+    fn test_rotary_steps() {
+        // Without wrap, always go the long way around
+        assert_eq!(rotary_steps(0, 3, 4, false), 3);
+        assert_eq!(rotary_steps(3, 0, 4, false), -3);
+        assert_eq!(rotary_steps(1, 10, 4, false), 2);
+        // With wrap, take the shortest path
+        assert_eq!(rotary_steps(0, 3, 4, true), -1);
+        assert_eq!(rotary_steps(3, 0, 4, true), 1);
+        assert_eq!(rotary_steps(4, 1, 5, true), 2);
+        assert_eq!(rotary_steps(1, 2, 5, true), 1);
+        assert_eq!(rotary_steps(2, 2, 5, true), 0);
+        // Single position selectors never move
+        assert_eq!(rotary_steps(0, 1, 1, true), 0);
+    }
+
+    #[test]
+    fn test_rotary_path() {
+        assert_eq!(rotary_path(0, 3, 4, false), vec![1, 2]);
+        // Crosses the wrap point the short way around
+        assert_eq!(rotary_path(1, 6, 8, true), vec![0, 7]);
+        assert_eq!(rotary_path(6, 1, 8, true), vec![7, 0]);
+        // Adjacent positions have nothing in between
+        assert_eq!(rotary_path(3, 0, 4, true), Vec::<i32>::new());
+        assert_eq!(rotary_path(0, 1, 1, true), Vec::<i32>::new());
+    }
 }
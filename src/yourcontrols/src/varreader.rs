@@ -6,6 +6,25 @@ use std::{
 };
 use yourcontrols_types::VarReaderTypes;
 
+// Strings are read and written as SIMCONNECT_DATATYPE_STRING256
+pub const STRING_DATA_SIZE: usize = 256;
+// Leaves room for the null terminator
+const MAX_STRING_LEN: usize = STRING_DATA_SIZE - 1;
+
+// Cuts a string down to what the sim will hold, never in the middle of a character
+pub fn fit_string(value: &mut String) {
+    if value.len() <= MAX_STRING_LEN {
+        return;
+    }
+
+    let mut length = MAX_STRING_LEN;
+    while !value.is_char_boundary(length) {
+        length -= 1;
+    }
+
+    value.truncate(length);
+}
+
 struct DefinitionEntry {
     data_type: InDataTypes,
     data_name: String,
@@ -93,6 +112,20 @@ impl VarReader {
                         result_data = VarReaderTypes::F64(cursor.read_f64::<LittleEndian>()?);
                         data_size = 2;
                     }
+                    InDataTypes::String => {
+                        data_size = (STRING_DATA_SIZE / 4) as isize;
+
+                        let mut bytes: Vec<u8> = Vec::with_capacity(STRING_DATA_SIZE);
+                        for i in 1..=data_size {
+                            bytes.extend_from_slice(&current_pos.offset(i).read().to_le_bytes())
+                        }
+                        // Null terminated
+                        let length = bytes.iter().position(|x| *x == 0).unwrap_or(bytes.len());
+
+                        result_data = VarReaderTypes::String(
+                            String::from_utf8_lossy(&bytes[..length]).to_string(),
+                        );
+                    }
                 };
 
                 return_data.insert(data.data_name.clone(), result_data);
@@ -117,6 +150,13 @@ impl VarReader {
                 VarReaderTypes::I32(n) => buf.extend((*n as i64).to_le_bytes().iter()),
                 VarReaderTypes::I64(n) => buf.extend(n.to_le_bytes().iter()),
                 VarReaderTypes::F64(n) => buf.extend(n.to_le_bytes().iter()),
+                VarReaderTypes::String(n) => {
+                    let mut n = n.clone();
+                    fit_string(&mut n);
+
+                    buf.extend_from_slice(n.as_bytes());
+                    buf.resize(buf.len() + STRING_DATA_SIZE - n.len(), 0);
+                }
             };
         }
 
@@ -214,4 +254,80 @@ mod tests {
         assert_eq!(values["PLANE LATITUDE"], VarReaderTypes::F64(42.0));
         assert_eq!(values["PLANE LONGITUDE"], VarReaderTypes::F64(128.0));
     }
+
+    #[test]
+    fn test_write_and_read_back_string() {
+        let mut definitions = VarReader::new();
+
+        definitions.add_definition("ATC ID", InDataTypes::String);
+        definitions.add_definition("PLANE ALTITUDE", InDataTypes::F64);
+
+        let mut new_data = HashMap::new();
+        new_data.insert(
+            "ATC ID".to_string(),
+            VarReaderTypes::String("N172SP".to_string()),
+        );
+        new_data.insert("PLANE ALTITUDE".to_string(), VarReaderTypes::F64(1500.0));
+
+        let data = definitions.write_to_data(&new_data);
+        assert_eq!(data.len(), 4 + STRING_DATA_SIZE + 4 + 8);
+
+        let values = definitions
+            .read_from_bytes(
+                definitions.get_number_definitions(),
+                data.as_ptr() as *const u32,
+            )
+            .unwrap();
+        assert_eq!(
+            values["ATC ID"],
+            VarReaderTypes::String("N172SP".to_string())
+        );
+        assert_eq!(values["PLANE ALTITUDE"], VarReaderTypes::F64(1500.0));
+
+        // Strings too long get truncated to fit the null terminator
+        new_data.clear();
+        new_data.insert(
+            "ATC ID".to_string(),
+            VarReaderTypes::String("A".repeat(STRING_DATA_SIZE + 10)),
+        );
+
+        let data = definitions.write_to_data(&new_data);
+        let values = definitions
+            .read_from_bytes(1, data.as_ptr() as *const u32)
+            .unwrap();
+        assert_eq!(
+            values["ATC ID"],
+            VarReaderTypes::String("A".repeat(STRING_DATA_SIZE - 1))
+        );
+    }
+
+    #[test]
+    fn test_over_length_string_keeps_whole_characters() {
+        let mut definitions = VarReader::new();
+        definitions.add_definition("ATC AIRLINE", InDataTypes::String);
+
+        // Two bytes each, the 255th byte would be half of one
+        let mut new_data = HashMap::new();
+        new_data.insert(
+            "ATC AIRLINE".to_string(),
+            VarReaderTypes::String("é".repeat(STRING_DATA_SIZE)),
+        );
+
+        let data = definitions.write_to_data(&new_data);
+        assert_eq!(data.len(), 4 + STRING_DATA_SIZE);
+        // Null terminated
+        assert_eq!(data[4 + MAX_STRING_LEN - 1..], [0, 0]);
+
+        let values = definitions
+            .read_from_bytes(1, data.as_ptr() as *const u32)
+            .unwrap();
+        assert_eq!(
+            values["ATC AIRLINE"],
+            VarReaderTypes::String("é".repeat(MAX_STRING_LEN / 2))
+        );
+
+        let mut value = "é".repeat(STRING_DATA_SIZE);
+        fit_string(&mut value);
+        assert_eq!(value.len(), MAX_STRING_LEN - 1);
+    }
 }
@@ -0,0 +1,263 @@
+use log::info;
+use simconnect::SimConnector;
+use std::time::Instant;
+use yourcontrols_types::{VarMap, VarReaderTypes, WeatherSample};
+
+use crate::sync::gaugecommunicator::GaugeCommunicator;
+use crate::sync::transfer::AircraftVars;
+use crate::util::InDataTypes;
+
+const PRESSURE_VAR: &str = "AMBIENT PRESSURE";
+const WIND_VELOCITY_VAR: &str = "AMBIENT WIND VELOCITY";
+const WIND_DIRECTION_VAR: &str = "AMBIENT WIND DIRECTION";
+const TEMPERATURE_VAR: &str = "AMBIENT TEMPERATURE";
+const KOHLSMAN_VAR: &str = "KOHLSMAN SETTING MB";
+
+const PUBLISH_INTERVAL_SECS: u64 = 10;
+
+// Differences small enough to not be worth bothering anyone about
+const PRESSURE_TOLERANCE: f64 = 1.0;
+const WIND_VELOCITY_TOLERANCE: f64 = 5.0;
+const WIND_DIRECTION_TOLERANCE: f64 = 20.0;
+const TEMPERATURE_TOLERANCE: f64 = 2.0;
+const KOHLSMAN_TOLERANCE: f64 = 0.5;
+
+// The host's weather minus ours
+#[derive(Debug, PartialEq)]
+pub struct WeatherDeltas {
+    pub pressure: f64,
+    pub wind_velocity: f64,
+    pub wind_direction: f64,
+    pub temperature: f64,
+    pub kohlsman: f64,
+}
+
+impl WeatherDeltas {
+    pub fn new(local: &WeatherSample, remote: &WeatherSample) -> Self {
+        // The direction of a calm wind means nothing
+        let wind_direction = if local.wind_velocity < WIND_VELOCITY_TOLERANCE
+            && remote.wind_velocity < WIND_VELOCITY_TOLERANCE
+        {
+            0.0
+        } else {
+            (remote.wind_direction - local.wind_direction + 540.0).rem_euclid(360.0) - 180.0
+        };
+
+        Self {
+            pressure: remote.ambient_pressure - local.ambient_pressure,
+            wind_velocity: remote.wind_velocity - local.wind_velocity,
+            wind_direction,
+            temperature: remote.temperature - local.temperature,
+            kohlsman: remote.kohlsman - local.kohlsman,
+        }
+    }
+
+    // Human readable differences that are big enough to matter
+    pub fn get_warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        if self.pressure.abs() >= PRESSURE_TOLERANCE {
+            warnings.push(format!("Pressure {:+.1} mb", self.pressure));
+        }
+
+        if self.wind_velocity.abs() >= WIND_VELOCITY_TOLERANCE {
+            warnings.push(format!("Wind speed {:+.0} kt", self.wind_velocity));
+        }
+
+        if self.wind_direction.abs() >= WIND_DIRECTION_TOLERANCE {
+            warnings.push(format!("Wind direction {:+.0}°", self.wind_direction));
+        }
+
+        if self.temperature.abs() >= TEMPERATURE_TOLERANCE {
+            warnings.push(format!("Temperature {:+.1} °C", self.temperature));
+        }
+
+        if self.kohlsman.abs() >= KOHLSMAN_TOLERANCE {
+            warnings.push(format!("Altimeter setting {:+.1} mb", self.kohlsman));
+        }
+
+        warnings
+    }
+}
+
+// K:KOHLSMAN_SET takes millibars * 16
+fn get_kohlsman_script(millibars: f64) -> String {
+    format!("{} (>K:KOHLSMAN_SET)", (millibars * 16.0).round() as i32)
+}
+
+// Like Corrector it reads its own set of vars, the host publishes them and everyone else compares against their own
+// Live weather can't be injected through SimConnect, so only the altimeter setting gets applied and the rest is shown as a warning
+pub struct WeatherSync {
+    avars: AircraftVars,
+    current: Option<WeatherSample>,
+    last_published: Option<Instant>,
+}
+
+impl WeatherSync {
+    pub fn new(define_id: u32) -> Self {
+        let mut avars = AircraftVars::new(define_id);
+
+        avars.add_var(PRESSURE_VAR, "Millibars", InDataTypes::F64);
+        avars.add_var(WIND_VELOCITY_VAR, "Knots", InDataTypes::F64);
+        avars.add_var(WIND_DIRECTION_VAR, "Degrees", InDataTypes::F64);
+        avars.add_var(TEMPERATURE_VAR, "Celsius", InDataTypes::F64);
+        avars.add_var(KOHLSMAN_VAR, "Millibars", InDataTypes::F64);
+
+        Self {
+            avars,
+            current: None,
+            last_published: None,
+        }
+    }
+
+    pub fn on_connected(&self, conn: &SimConnector) {
+        self.avars.on_connected(conn);
+        conn.request_data_on_sim_object(
+            5830,
+            self.avars.define_id,
+            0,
+            simconnect::SIMCONNECT_PERIOD_SIMCONNECT_PERIOD_SECOND,
+            simconnect::SIMCONNECT_CLIENT_DATA_REQUEST_FLAG_TAGGED,
+            0,
+            0,
+            0,
+        );
+    }
+
+    pub fn process_sim_object_data(&mut self, data: &simconnect::SIMCONNECT_RECV_SIMOBJECT_DATA) {
+        if self.avars.define_id != data.dwDefineID {
+            return;
+        }
+
+        if let Ok(data) = self.avars.read_vars(data) {
+            self.on_vars_change(&data);
+        }
+    }
+
+    fn on_vars_change(&mut self, data: &VarMap) {
+        let mut sample = self.current.unwrap_or_default();
+
+        for (var_name, value) in [
+            (PRESSURE_VAR, &mut sample.ambient_pressure),
+            (WIND_VELOCITY_VAR, &mut sample.wind_velocity),
+            (WIND_DIRECTION_VAR, &mut sample.wind_direction),
+            (TEMPERATURE_VAR, &mut sample.temperature),
+            (KOHLSMAN_VAR, &mut sample.kohlsman),
+        ] {
+            if let Some(VarReaderTypes::F64(new_value)) = data.get(var_name) {
+                *value = *new_value;
+            }
+        }
+
+        self.current = Some(sample);
+    }
+
+    // Our weather if it's time to send it out again
+    pub fn get_next_publish(&mut self) -> Option<WeatherSample> {
+        let current = self.current?;
+
+        if let Some(last_published) = self.last_published {
+            if last_published.elapsed().as_secs() < PUBLISH_INTERVAL_SECS {
+                return None;
+            }
+        }
+
+        self.last_published = Some(Instant::now());
+        Some(current)
+    }
+
+    // Compares the host's weather with ours, setting the altimeter to match if asked to. Returns what is still different
+    pub fn on_remote_weather(
+        &mut self,
+        conn: &SimConnector,
+        gauge: &GaugeCommunicator,
+        remote: &WeatherSample,
+        apply: bool,
+    ) -> Vec<String> {
+        let Some(local) = self.current.as_ref() else {
+            return Vec::new();
+        };
+
+        let mut deltas = WeatherDeltas::new(local, remote);
+
+        if apply && deltas.kohlsman.abs() >= KOHLSMAN_TOLERANCE {
+            info!("[WEATHER] Setting altimeter to {:.1} mb", remote.kohlsman);
+            gauge.send_raw(conn, &get_kohlsman_script(remote.kohlsman));
+            deltas.kohlsman = 0.0;
+        }
+
+        deltas.get_warnings()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(pressure: f64, wind: (f64, f64), temperature: f64, kohlsman: f64) -> WeatherSample {
+        WeatherSample {
+            ambient_pressure: pressure,
+            wind_velocity: wind.0,
+            wind_direction: wind.1,
+            temperature,
+            kohlsman,
+        }
+    }
+
+    #[test]
+    fn test_same_weather_has_no_warnings() {
+        let local = sample(1013.0, (10.0, 270.0), 15.0, 1013.0);
+        let remote = sample(1013.4, (12.0, 280.0), 16.0, 1013.2);
+
+        assert!(WeatherDeltas::new(&local, &remote)
+            .get_warnings()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_warnings_show_deltas() {
+        let local = sample(1013.0, (10.0, 270.0), 15.0, 1013.0);
+        let remote = sample(1003.0, (25.0, 300.0), 10.0, 1003.0);
+
+        assert_eq!(
+            WeatherDeltas::new(&local, &remote).get_warnings(),
+            vec![
+                "Pressure -10.0 mb",
+                "Wind speed +15 kt",
+                "Wind direction +30°",
+                "Temperature -5.0 °C",
+                "Altimeter setting -10.0 mb",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wind_direction_wraps() {
+        let local = sample(1013.0, (20.0, 350.0), 15.0, 1013.0);
+        let remote = sample(1013.0, (20.0, 10.0), 15.0, 1013.0);
+        assert_eq!(WeatherDeltas::new(&local, &remote).wind_direction, 20.0);
+
+        // Calm winds can point anywhere
+        let local = sample(1013.0, (2.0, 90.0), 15.0, 1013.0);
+        let remote = sample(1013.0, (3.0, 270.0), 15.0, 1013.0);
+        assert_eq!(WeatherDeltas::new(&local, &remote).wind_direction, 0.0);
+    }
+
+    #[test]
+    fn test_kohlsman_script() {
+        assert_eq!(get_kohlsman_script(1013.25), "16212 (>K:KOHLSMAN_SET)");
+    }
+
+    #[test]
+    fn test_publishes_periodically() {
+        let mut weather = WeatherSync::new(3);
+        assert_eq!(weather.get_next_publish(), None);
+
+        let mut data = VarMap::new();
+        data.insert(PRESSURE_VAR.to_string(), VarReaderTypes::F64(1000.0));
+        weather.on_vars_change(&data);
+
+        assert_eq!(weather.get_next_publish().unwrap().ambient_pressure, 1000.0);
+        assert_eq!(weather.get_next_publish(), None);
+    }
+}
@@ -82,6 +82,10 @@ function EmulatorShowTable() {
 }
 
 function EmulatorNormalizeValue(varType, value) {
+    if (varType === "string") {
+        return value != null ? String(value) : "";
+    }
+
     if (isNaN(value)) {
         return null;
     }
@@ -117,17 +121,24 @@ function EmulatorAddRow(id, name, varType, value) {
 
     var valueCell = document.createElement("td");
     var valueInput = document.createElement("input");
-    valueInput.type = "number";
-    valueInput.step = varType === "f64" ? "any" : "1";
+    var isString = varType === "string";
+    valueInput.type = isString ? "text" : "number";
+    if (!isString) {
+        valueInput.step = varType === "f64" ? "any" : "1";
+    }
     if (varType === "bool") {
         valueInput.min = "0";
         valueInput.max = "1";
     }
     valueInput.className = "form-control form-control-sm themed emulator-value-input";
-    var normalizedInitial = EmulatorNormalizeValue(varType, value != null ? value : 0.0);
-    valueInput.value = normalizedInitial != null ? normalizedInitial : 0.0;
+    var defaultValue = isString ? "" : 0.0;
+    var normalizedInitial = EmulatorNormalizeValue(varType, value != null ? value : defaultValue);
+    valueInput.value = normalizedInitial != null ? normalizedInitial : defaultValue;
     valueInput.addEventListener("change", function () {
-        var normalized = EmulatorNormalizeValue(varType, parseFloat(valueInput.value));
+        var normalized = EmulatorNormalizeValue(
+            varType,
+            isString ? valueInput.value : parseFloat(valueInput.value)
+        );
         if (normalized == null) {
             return;
         }
@@ -169,6 +180,27 @@ function EmulatorAddRow(id, name, varType, value) {
         chipCell.appendChild(chip);
     }
 
+    if (!isString) {
+        EmulatorAddNumberChips(addChip, valueInput, id, varType);
+    }
+
+    row.appendChild(nameCell);
+    row.appendChild(typeCell);
+    row.appendChild(valueCell);
+    row.appendChild(chipCell);
+    row.appendChild(actionCell);
+
+    emulatorRows.appendChild(row);
+    emulatorRowMap[id] = {
+        row: row,
+        input: valueInput,
+        varType: varType,
+    };
+
+    EmulatorShowTable();
+}
+
+function EmulatorAddNumberChips(addChip, valueInput, id, varType) {
     addChip("Toggle", function () {
         var current = parseFloat(valueInput.value) || 0;
         var next = current > 0 ? 0 : 1;
@@ -199,21 +231,6 @@ function EmulatorAddRow(id, name, varType, value) {
             EmulatorSendValue(id, next);
         });
     });
-
-    row.appendChild(nameCell);
-    row.appendChild(typeCell);
-    row.appendChild(valueCell);
-    row.appendChild(chipCell);
-    row.appendChild(actionCell);
-
-    emulatorRows.appendChild(row);
-    emulatorRowMap[id] = {
-        row: row,
-        input: valueInput,
-        varType: varType,
-    };
-
-    EmulatorShowTable();
 }
 
 function EmulatorUpdateRowValue(id, value) {
@@ -222,17 +239,18 @@ function EmulatorUpdateRowValue(id, value) {
         return;
     }
 
-    var normalized = EmulatorNormalizeValue(rowInfo.varType, value != null ? value : 0.0);
-    rowInfo.input.value = normalized != null ? normalized : 0.0;
+    var defaultValue = rowInfo.varType === "string" ? "" : 0.0;
+    var normalized = EmulatorNormalizeValue(rowInfo.varType, value != null ? value : defaultValue);
+    rowInfo.input.value = normalized != null ? normalized : defaultValue;
 }
 
 function EmulatorSendValue(id, value) {
-    if (isNaN(value)) {
+    emulatorVars[id] = emulatorVars[id] || { varType: "f64", value: value };
+    var varType = emulatorVars[id].varType || "f64";
+    if (varType !== "string" && isNaN(value)) {
         return;
     }
 
-    emulatorVars[id] = emulatorVars[id] || { varType: "f64", value: value };
-    var varType = emulatorVars[id].varType || "f64";
     var normalized = EmulatorNormalizeValue(varType, value);
     if (normalized == null) {
         return;
@@ -4,6 +4,7 @@ var settings_button = document.getElementById("settings-button");
 var alert = document.getElementById("alert");
 var version_alert_text = document.getElementById("version-alert-text");
 var overloaded_alert = document.getElementById("overloaded-alert");
+var weather_alert = document.getElementById("weather-alert");
 var fs2020List = document.getElementById("fs2020-list");
 var fs2024List = document.getElementById("fs2024-list");
 
@@ -22,6 +23,8 @@ var timeout_input = document.getElementById("timeout-input");
 var theme_selector = document.getElementById("theme-select");
 var streamer_mode = document.getElementById("streamer-mode");
 var instructor_mode = document.getElementById("instructor-mode");
+var anyone_can_pause = document.getElementById("anyone-can-pause");
+var weather_sync_select = document.getElementById("weather-sync-select");
 
 var username_div = document.getElementById("username-div");
 var port_div = document.getElementById("port-div");
@@ -45,6 +48,9 @@ var joinConnectDirect = document.getElementById("join-connect-direct");
 var joinConnectCloud = document.getElementById("join-connect-cloud");
 var joinIpInput = document.getElementById("join-ip-input");
 var joinPortInput = document.getElementById("join-port-input");
+var lanSessionsDiv = document.getElementById("lan-sessions-div");
+var lanSessionsList = document.getElementById("lan-sessions-list");
+var lanSessionsEmpty = document.getElementById("lan-sessions-empty");
 
 // Network
 var downloadBandwidth = document.getElementById("download-bandwidth");
@@ -53,6 +59,7 @@ var uploadBandwidth = document.getElementById("upload-bandwidth");
 var uploadRate = document.getElementById("upload-rate");
 var networkLoss = document.getElementById("network-loss");
 var ping = document.getElementById("network-ping");
+var networkBuffer = document.getElementById("network-buffer");
 
 var forceButton = document.getElementById("force-button");
 var observerButton = document.getElementById("observer-button");
@@ -131,6 +138,8 @@ function OnConnected() {
     joinConnectDirect.disabled = true;
     joinIpInput.disabled = true;
     joinPortInput.disabled = true;
+    // Stopped on the app side when connecting
+    lanSessionsDiv.hidden = true;
 
     if (streamer_mode.checked) {
         joinIpInput.value = joinIpInput.value.split(/\d/).join("X");
@@ -151,6 +160,7 @@ function OnConnected() {
 
 function OnDisconnect(text) {
     alert.updatetext("danger", text);
+    weather_alert.hidden = true;
     is_connected = false;
     is_client = false;
     FormButtonsDisabled(false);
@@ -169,6 +179,9 @@ function OnDisconnect(text) {
     joinConnectDirect.disabled = false;
     joinIpInput.disabled = false;
     joinPortInput.disabled = false;
+    if (joinConnectDirect.checked) {
+        SetLanDiscovery(true);
+    }
 
     connectionList.clear();
 
@@ -197,6 +210,11 @@ function SetSessionCode(code) {
         $("#external-ipv4").show();
         $("#external-ipv6").show();
         $("#session-id").hide()
+    } else if (code.indexOf("-") == -1) {
+        // Direct servers only have the secret, joiners still need the address
+        $("#session-id").show().text("Session Code: " + code);
+        $("#external-ipv4").show();
+        $("#external-ipv6").show();
     } else {
         $("#session-id").show().text("Session Code: " + code);
         $("#external-ipv4").hide();
@@ -253,6 +271,8 @@ function LoadSettings(newSettings) {
     joinIpInput.value = newSettings.ip;
     streamer_mode.checked = newSettings.streamer_mode;
     instructor_mode.checked = newSettings.instructor_mode;
+    anyone_can_pause.checked = newSettings.pause_policy == "Anyone";
+    weather_sync_select.value = newSettings.weather_sync || "Off";
 
     username.value = newSettings.name;
     timeout_input.value = newSettings.conn_timeout;
@@ -273,6 +293,53 @@ function UpdateMetrics(metrics) {
     networkLoss.textContent =
         (metrics.packetLoss * 100).toFixed(2) + "% Packet loss";
     ping.textContent = metrics.ping.toFixed(0) + "ms";
+    networkBuffer.textContent =
+        metrics.bufferDepth + " Buffered (" + metrics.playoutDelay.toFixed(0) + "ms)";
+}
+
+var methodNames = { cloudServer: "Cloud P2P", relay: "Cloud Host", direct: "Direct" };
+
+function UpdateNatType(nat) {
+    var natText = document.getElementById("nat-type-text");
+    natText.hidden = false;
+    natText.textContent = "NAT type: " + nat.natType + ". Recommended: " + methodNames[nat.recommended] + ".";
+    // Don't switch methods under a running session
+    if (is_connected) {
+        return;
+    }
+    var radio = document.querySelector("input[name=connectionRadios][value=" + nat.recommended + "]");
+    radio.checked = true;
+    radio.dispatchEvent(new Event("change"));
+}
+
+function SetLanDiscovery(enabled) {
+    lanSessionsDiv.hidden = !enabled;
+    invoke({
+        type: enabled ? "startLanDiscovery" : "stopLanDiscovery",
+    });
+}
+
+function UpdateLanSessions(sessions) {
+    lanSessionsList.innerHTML = "";
+    lanSessionsEmpty.hidden = sessions.length > 0;
+
+    sessions.forEach(function (session) {
+        const entry = document.createElement("button");
+        entry.type = "button";
+        entry.className = "list-group-item list-group-item-action themed";
+        entry.textContent = session.name + " - " + session.aircraft + " (" + session.players + (session.players == 1 ? " player" : " players") + ", v" + session.version + ")";
+        entry.addEventListener("click", function () {
+            joinIpInput.value = session.ip;
+            joinPortInput.value = session.port;
+        });
+
+        lanSessionsList.appendChild(entry);
+    });
+}
+
+function UpdateWeatherWarning(warnings) {
+    weather_alert.hidden = warnings.length == 0;
+    weather_alert.textContent = "Host's weather differs: " + warnings.join(", ");
 }
 
 // Handle server messages
@@ -288,6 +355,12 @@ function MessageReceived(data) {
             $("#not_server_running").append(forceButton);
             OnConnected();
             break;
+        case "reconnecting":
+            alert.updatetext("warning", "Connection lost, reconnecting...");
+            break;
+        case "resumed":
+            alert.updatetext("success", "Reconnected to server.");
+            break;
         case "server_fail":
             OnDisconnect("Server failed to start. Reason: " + data["data"]);
             break;
@@ -300,6 +373,13 @@ function MessageReceived(data) {
             $("#not_user_client").append(forceButton);
             OnConnected();
             break;
+        case "port_forwarded":
+            var forwarded = JSON.parse(data["data"]);
+            alert.updatetext("success", "Server started! Port forwarded with " + forwarded.method + ", others can join on port " + forwarded.externalPort + ".");
+            break;
+        case "port_forward_failed":
+            alert.updatetext("warning", "Server started, but the port could not be forwarded automatically. Reason: " + data["data"]);
+            break;
         case "host":
             is_client = false;
             forceButton.hidden = false;
@@ -330,6 +410,24 @@ function MessageReceived(data) {
         case "stable":
             overloaded_alert.hidden = true;
             break;
+        case "nat_type":
+            UpdateNatType(JSON.parse(data["data"]));
+            break;
+        case "lan_sessions":
+            UpdateLanSessions(JSON.parse(data["data"]));
+            break;
+        case "definition_progress":
+            var progress = JSON.parse(data["data"]);
+            if (progress.received < progress.total) {
+                var percent = Math.floor(progress.received / progress.total * 100);
+                alert.updatetext("warning", "Receiving aircraft definitions... " + percent + "%");
+            } else {
+                alert.updatetext("success", "Connected to server.");
+            }
+            break;
+        case "weather":
+            UpdateWeatherWarning(JSON.parse(data["data"]));
+            break;
         case "newconnection":
             connectionList.add(data["data"]);
             setTheme(settings.ui_dark_theme);
@@ -481,12 +579,15 @@ joinConnectCloud.addEventListener("change", function () {
     sessionDiv.hidden = false;
     joinPortDiv.hidden = true;
     joinIpDiv.hidden = true;
+    SetLanDiscovery(false);
 });
 
 joinConnectDirect.addEventListener("change", function () {
-    sessionDiv.hidden = true;
+    // The session code is needed for the key either way
+    sessionDiv.hidden = false;
     joinPortDiv.hidden = false;
     joinIpDiv.hidden = false;
+    SetLanDiscovery(true);
 });
 
 joinPortInput.addEventListener("change", function () {
@@ -546,6 +647,8 @@ $("#settings-form").submit(function (e) {
     newSettings.ui_dark_theme = theme_selector.checked;
     newSettings.streamer_mode = streamer_mode.checked;
     newSettings.instructor_mode = instructor_mode.checked;
+    newSettings.pause_policy = anyone_can_pause.checked ? "Anyone" : "HostOnly";
+    newSettings.weather_sync = weather_sync_select.value;
 
     for (key in newSettings) {
         if (newSettings[key] === null) {
@@ -649,7 +752,6 @@ $("#connect-button").click(function (e) {
             return;
         }
 
-        data["session_id"] = null; // Joining directly should never include a session_id
         data["port"] = parseInt(joinPortInput.value);
     }
 